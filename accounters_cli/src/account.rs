@@ -2,17 +2,11 @@ use accounters_lib::data::{
     Database,
     account::AccountType,
     money::{
-        Currency,
-        NumberAlignment
-    }
-};
-use std::{
-    fmt::Write,
-    collections::{
-        HashSet,
-        HashMap
+        AmountAlignment,
+        AmountFormat
     }
 };
+use std::fmt::Write;
 
 pub struct MultiAccountViewState {
     account_type: AccountType
@@ -34,43 +28,23 @@ impl MultiAccountViewState {
     pub fn produce_text(&self, database: &Database) -> String { 
         let mut output = String::from("\n\n\n");
 
-        let accounts = database.get_account_names().map(|name| {
-                (name, database.get_account(name))
-            }).filter_map(|(name, acc)| {
-                if acc.get_account_type() != &self.account_type {
-                    None
-                } else {
-                    Some((
-                        name, 
-                        database.get_account_balance(name, None, None).unwrap()
-                    ))
+        let mut accounts = database.get_account_names().filter_map(|name| {
+                let account = database.get_account(name)?;
+                if account.get_account_type() != &self.account_type {
+                    return None
                 }
+                Some((name, database.get_account_balance(name, None, None)))
             }).collect::<Vec<_>>();
+        accounts.sort_by(|(a, _), (b, _)| a.as_ref().cmp(b.as_ref()));
 
-        let mut currencies = HashSet::new();
-
-        accounts.iter().flat_map(|(_, amount)| {
-            amount.currencies().to_vec()
-        }).for_each(|currency| { currencies.insert(currency); });
-
-        let currencies = currencies.iter().collect::<Vec<_>>();
-
-        let mut alignments: HashMap<Currency, NumberAlignment> = HashMap::new();
-        for currency in currencies.iter() {
-            /*
-            let max_length = accounts.iter().map(|(_, amount)| {
-                amount.in_currency(currency).to_string().len()
-            }).max().unwrap();
-            */
-            alignments.insert(
-                (*currency).clone(),
-                NumberAlignment::from_numbers(accounts.iter().map(|(_,amount)| amount.in_currency(currency)))
-            );
-        }
+        let alignment = AmountAlignment::from_amounts(
+            accounts.iter().filter_map(|(_, amount)| amount.as_ref().ok()),
+            AmountFormat::default()
+        );
 
         let account_name_length = accounts.iter().map(|(name, _)| {
             name.as_ref().len()
-        }).max().unwrap();
+        }).max().unwrap_or(0);
 
 
         for (account_name, amount) in accounts {
@@ -80,23 +54,12 @@ impl MultiAccountViewState {
                 account_name.as_ref(),
                 account_name_length + 5
             ).unwrap();
-            for currency in currencies.iter() {
-                write!(
-                    output,
-                    "   {}",
-                    amount.in_currency(currency).format(alignments.get(currency).unwrap()),
-                ).unwrap();
+            match amount {
+                Ok(amount) => writeln!(output, "   {}", alignment.format_amount(&amount)).unwrap(),
+                Err(error) => writeln!(output, "   the balance could not be computed: {}", error).unwrap(),
             }
-            writeln!(output).unwrap();
         }
 
-        /*
-            writeln!(
-                output, 
-                "{:>35}  : {}", account_name.as_ref(), balance
-            ).unwrap();
-        }
-        */
         output
     }
 }
@@ -1,41 +1,52 @@
-use std::fs::read_dir;
+use accounters_lib::data::{
+    Database,
+    storage::{JournalStorage, JsonStorage, Storage},
+    yearly::YearlyStorage
+};
 
-
-use accounters_lib::data::Database;
-
-pub fn load_database(dir_path: &str) -> Option<(String, Database)> {
-    let paths = find_databases(dir_path).unwrap();
+/// Let the user choose a database in the folder and load it
+///
+/// The storage it was loaded from is returned too, to save it later.
+pub fn load_database(dir_path: &str) -> Option<(String, Database, Box<dyn Storage>)> {
+    let storages = storages(dir_path);
+    let databases = find_databases(&storages);
     loop {
         let n_lines = termsize::get().unwrap().rows as usize;
         println!("Available databases in {dir_path}:");
-        for (index, name) in paths.iter().enumerate() {
+        for (index, (_, name)) in databases.iter().enumerate() {
             println!("\t{}) {}", index+1, name)
         }
-        println!("{}", "\n".repeat(n_lines - 4 - paths.len()));
+        println!("{}", "\n".repeat(n_lines - 4 - databases.len()));
         println!("Select by index or name, or press q to quit:");
         let mut input = String::new();
 
         std::io::stdin().read_line(&mut input).unwrap();
         let trimmed_input = input.trim();
         
-        if let Ok(index) = trimmed_input.parse::<usize>() {
-            let Some(name) = paths.get(index - 1) else {
+        let selected = if let Ok(index) = trimmed_input.parse::<usize>() {
+            let Some(selected) = index.checked_sub(1).and_then(|i| databases.get(i)) else {
                 println!("Index out of bounds");
                 continue
             };
-            println!("{name} loaded");
-            return Some((
-                String::from(name),
-                Database::read_from_file(&format!("{dir_path}/{name}.json")).unwrap()
-            ));
-        }
+            Some(selected)
+        } else {
+            databases.iter().find(|(_, name)| name == trimmed_input)
+        };
 
-        if paths.iter().any(|x| x == trimmed_input) {
-            println!("{trimmed_input} loaded");
-            return Some((
-                String::from(trimmed_input),
-                Database::read_from_file(&format!("{dir_path}/{trimmed_input}.json")).unwrap()
-            ));
+        if let Some((storage_index, name)) = selected {
+            let storage = &storages[*storage_index].1;
+            let name = name.strip_suffix(storages[*storage_index].0).unwrap();
+            match storage.load(name) {
+                Ok(database) => {
+                    println!("{name} loaded");
+                    let storage = storages.into_iter().nth(*storage_index).unwrap().1;
+                    return Some((String::from(name), database, storage));
+                },
+                Err(error) => {
+                    println!("Could not load {name}: {error}");
+                    continue
+                }
+            }
         }
 
         if trimmed_input == "q" {
@@ -46,17 +57,45 @@ pub fn load_database(dir_path: &str) -> Option<(String, Database)> {
     }
 }
 
+/// Every kind of storage that can be in the folder, with the suffix that
+/// tells their databases apart
+fn storages(dir_path: &str) -> Vec<(&'static str, Box<dyn Storage>)> {
+    vec![
+        ("", Box::new(JsonStorage::new(dir_path))),
+        (".journal", Box::new(JournalStorage::new(dir_path))),
+        ("/", Box::new(YearlyStorage::new(dir_path))),
+        #[cfg(feature = "sqlite")]
+        (".sqlite", Box::new(accounters_lib::data::sqlite::SqliteStorage::new(dir_path))),
+    ]
+}
 
-fn find_databases(path: &str) -> Option<Vec<String>> {
-    Some(
-        read_dir(path).ok()?.filter_map(|file| {
-            let path_buf = file.ok()?.path();
-            let path = path_buf.as_path();
+/// The databases available, with the index of their storage and the name to
+/// show
+fn find_databases(storages: &[(&'static str, Box<dyn Storage>)]) -> Vec<(usize, String)> {
+    storages.iter().enumerate().flat_map(|(index, (suffix, storage))| {
+        storage.list().unwrap_or_default()
+            .into_iter()
+            .map(move |name| (index, format!("{name}{suffix}")))
+    }).collect()
+}
 
-            if path.extension()? != "json" {
-                return None
-            }
-            Some(path.file_stem()?.to_str()?.to_owned())
-        }).collect()
-    )
+/// Load a database by the name shown in the list, like `home` or
+/// `home.journal`, without asking anything
+pub fn load_by_name(dir_path: &str, name: &str) -> Result<Database, String> {
+    load_with_storage(dir_path, name).map(|(_, database, _)| database)
+}
+
+/// Load a database by the name shown in the list, with its name in the
+/// storage and the storage, to save it later
+pub fn load_with_storage(dir_path: &str, name: &str) -> Result<(String, Database, Box<dyn Storage>), String> {
+    let storages = storages(dir_path);
+    let (storage_index, name) = find_databases(&storages)
+        .into_iter()
+        .find(|(_, listed)| listed == name)
+        .ok_or_else(|| format!("there is no database {name} in {dir_path}"))?;
+    let (suffix, storage) = &storages[storage_index];
+    let name = name.strip_suffix(suffix).unwrap().to_owned();
+    let database = storage.load(&name).map_err(|error| error.to_string())?;
+    let storage = storages.into_iter().nth(storage_index).unwrap().1;
+    Ok((name, database, storage))
 }
@@ -0,0 +1,74 @@
+use accounters_lib::data::{
+    datespec::DateParser,
+    query::Query,
+    tags::Tag,
+};
+use accounters_lib::export::csv::{self, CsvOptions, NumberFormat};
+
+use std::io::Write;
+
+const USAGE: &str = "\
+Usage: accounters_cli export (postings|balances) <database> <output> [options]
+
+The output can be - to write to the terminal. Options:
+    --dates <dates>       only the transactions in these dates, or the
+                          balances at the end of them
+    --account <prefix>    only the accounts whose name starts like this
+    --tag <tag>           only the transactions with this tag
+    --text <text>         only the transactions with this in the name or notes
+    --columns <columns>   postings, full, or a list like date,account,amount
+    --locale <locale>     write numbers as usual in this locale, like sv_SE
+    --delimiter <char>    separate fields with this instead of a comma";
+
+/// Write a database as CSV, following the arguments after `export`
+pub fn run<I: Iterator<Item = String>>(dir_path: &str, mut args: I) -> Result<(), String> {
+    let (Some(kind), Some(db_name), Some(output)) = (args.next(), args.next(), args.next()) else {
+        return Err(USAGE.to_owned());
+    };
+    let date_parser = DateParser::today();
+    let mut query = Query::default();
+    let mut options = CsvOptions::default();
+    let mut as_of = None;
+
+    while let Some(option) = args.next() {
+        let value = args.next().ok_or_else(|| format!("{option} needs a value\n\n{USAGE}"))?;
+        match option.as_str() {
+            "--dates" => {
+                let dates = date_parser.parse(&value).map_err(|error| format!("wrong dates {value}: {error}"))?;
+                as_of = Some(dates.end());
+                query = query.during(&dates);
+            },
+            "--account" => query.account = Some(value),
+            "--tag" => query.tags.push(Tag::new(&value)),
+            "--text" => query.text = Some(value),
+            "--columns" => options.columns = value.parse()?,
+            "--locale" => {
+                let format = NumberFormat::for_locale(&value).ok_or_else(|| format!("unknown locale {value}"))?;
+                options = options.with_number_format(format);
+            },
+            "--delimiter" => {
+                let mut chars = value.chars();
+                let (Some(delimiter), None) = (chars.next(), chars.next()) else {
+                    return Err(format!("the delimiter must be one character, not {value}"));
+                };
+                options.delimiter = delimiter;
+            },
+            _ => return Err(format!("unknown option {option}\n\n{USAGE}")),
+        }
+    }
+
+    let database = crate::db_loader::load_by_name(dir_path, &db_name)?;
+    let mut writer: Box<dyn Write> = if output == "-" {
+        Box::new(std::io::stdout().lock())
+    } else {
+        let file = std::fs::File::create(&output).map_err(|error| format!("could not create {output}: {error}"))?;
+        Box::new(std::io::BufWriter::new(file))
+    };
+
+    match kind.as_str() {
+        "postings" => csv::write_postings(&database, &query, &options, &mut writer).map_err(|error| error.to_string())?,
+        "balances" => csv::write_balances(&database, &query, as_of, &options, &mut writer).map_err(|error| error.to_string())?,
+        _ => return Err(USAGE.to_owned()),
+    }
+    writer.flush().map_err(|error| error.to_string())
+}
@@ -1,11 +1,14 @@
 mod db_loader;
+mod export;
+mod rules;
 mod transaction;
 mod account;
 
 use accounters_lib::data::{
     Database,
+    storage::Storage,
     money::Amount,
-    datetime::DateTime
+    datespec::{DateParser, DateSpec}
 };
 
 use transaction::{
@@ -16,9 +19,26 @@ use transaction::{
 };
 use account::MultiAccountViewState;
 
+/// Browse and edit a database of the `files` folder, export one with
+/// `accounters_cli export ...` or manage its rules with
+/// `accounters_cli rules ...`
 fn main() {
-    let (name, database) = db_loader::load_database("files").unwrap();
-    let mut state = State::init(name, database);
+    let mut args = std::env::args().skip(1);
+    if let Some(command) = args.next() {
+        let result = match command.as_str() {
+            "export" => export::run("files", args),
+            "rules" => rules::run("files", args),
+            _ => Err(format!("unknown command {command}, the commands are export and rules")),
+        };
+        if let Err(error) = result {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+        return
+    }
+
+    let (name, database, storage) = db_loader::load_database("files").unwrap();
+    let mut state = State::init(name, database, storage);
     loop {
         state.print();
         let mut text_input = String::new();
@@ -35,21 +55,26 @@ fn main() {
     println!("{}", "\n".repeat(n_lines-3));
 }
 
+#[allow(dead_code)]
 #[derive(Debug)]
 enum Input {
     Quit,
     Literal(String),
     Amount(Amount),
     Integer(i64),
-    DateTime(DateTime)
+    Date(DateSpec)
 }
 
 struct State {
     database: Database,
     db_name: String,
+    storage: Box<dyn Storage>,
+    date_parser: DateParser,
+    message: String,
     mode: Vec<Mode>
 }
 
+#[allow(dead_code)]
 enum Mode {
     StartScreen,
     MultiTransactionView(MultiTransactionViewState),
@@ -61,10 +86,13 @@ enum Mode {
 
 
 impl State {
-    fn init(db_name: String, database: Database) -> Self {
+    fn init(db_name: String, database: Database, storage: Box<dyn Storage>) -> Self {
         State {
             db_name,
             database,
+            storage,
+            date_parser: DateParser::today(),
+            message: String::new(),
             mode: vec![Mode::StartScreen]
         }
     }
@@ -79,15 +107,21 @@ impl State {
                 top_text.push_str("\t1) Show accounts\n");
                 top_text.push_str("\t2) Show transactions\n");
                 top_text.push_str("\t3) Delete database\n");
+                top_text.push_str("\tu) Undo last change\n");
+                top_text.push_str("\tr) Redo last undone change\n");
+                top_text.push_str("\ts) Save changes\n");
                 top_text.push_str("\tq) Exit\n");
-                let bottom_text = String::from("Press index or q:");
+                if !self.message.is_empty() {
+                    top_text.push_str(&format!("\n{}\n", self.message));
+                }
+                let bottom_text = String::from("Press index, u, r, s or q:");
                 (top_text, bottom_text)
             },
             MultiTransactionView(tv_state) => {
                 let (top, bottom) = tv_state.produce_text(&self.database);
                 (top, bottom)
             },
-            MultiTransactionViewConfiguration(config) => {
+            MultiTransactionViewConfiguration(_config) => {
                 (String::from("a"), String::from("b"))
             },
             TransactionView(transaction_view) => {
@@ -102,7 +136,7 @@ impl State {
                     String::from("Show assets (a), flows (f), or go back (q)")
                 )
             },
-            TransactionEdit(te_state) => {
+            TransactionEdit(_te_state) => {
                 (String::from("OMG\n"), String::from("Please go back (q)"))
             }
         };
@@ -115,15 +149,20 @@ impl State {
         if input == "q" || input == "quit" {
             return Input::Quit
         }
-        if let Ok(datetime) = input.parse::<DateTime>() {
-            return Input::DateTime(datetime)
+        // Rows can always be picked as #12, plain numbers are taken as
+        // indices only when they are not a date like 2023
+        if let Some(Ok(integer)) = input.strip_prefix('#').map(str::parse::<i64>) {
+            return Input::Integer(integer)
         }
-        if let Ok(amount) = input.parse::<Amount>() {
-            return Input::Amount(amount)
+        if let Ok(date) = self.date_parser.parse(input) {
+            return Input::Date(date)
         }
         if let Ok(integer) = input.parse::<i64>() {
             return Input::Integer(integer)
         }
+        if let Ok(amount) = input.parse::<Amount>() {
+            return Input::Amount(amount)
+        }
         Input::Literal(input.to_owned())
     }
 
@@ -145,6 +184,11 @@ impl State {
                     return
                 }
 
+                if let Input::Date(date) = input {
+                    tv_state.move_to(&self.database, &date);
+                    return
+                }
+
                 if let Input::Integer(index) = input {
                     let transaction_id = *tv_state.get_transaction_id(index as usize);
                     self.mode.push(Mode::TransactionView(TransactionViewState::new(transaction_id)));
@@ -166,6 +210,26 @@ impl State {
 }
 
 fn start_screen_select_mode(state: &mut State, input: Input) {
+    if let Input::Literal(input) = &input {
+        state.message = match input.as_str() {
+            "u" => match state.database.undo() {
+                Ok(true) => String::from("Last change undone"),
+                Ok(false) => String::from("Nothing to undo"),
+                Err(error) => format!("Could not undo: {error}"),
+            },
+            "r" => match state.database.redo() {
+                Ok(true) => String::from("Last undone change applied again"),
+                Ok(false) => String::from("Nothing to redo"),
+                Err(error) => format!("Could not redo: {error}"),
+            },
+            "s" => match state.storage.save(&state.db_name, &state.database) {
+                Ok(()) => String::from("Database saved"),
+                Err(error) => format!("Could not save: {error}"),
+            },
+            _ => String::new(),
+        };
+        return
+    }
     let Input::Integer(input) = input else {
         return
     };
@@ -0,0 +1,93 @@
+use accounters_lib::data::{
+    datespec::DateParser,
+    query::Query,
+    rules::Rule,
+};
+
+const USAGE: &str = "\
+Usage: accounters_cli rules <command> <database> [arguments]
+
+Commands:
+    list                    show the rules in the order they are tried
+    add <file>              add the rules of a JSON file, which has one rule
+                            or a list of them
+    remove <name>           remove the rule with this name
+    apply [options]         apply the rules again to the transactions
+
+Options of apply:
+    --dates <dates>         only the transactions in these dates
+    --account <prefix>      only the transactions of the accounts whose name
+                            starts like this";
+
+/// List, change or apply the rules of a database, following the arguments
+/// after `rules`
+pub fn run<I: Iterator<Item = String>>(dir_path: &str, mut args: I) -> Result<(), String> {
+    let (Some(command), Some(db_name)) = (args.next(), args.next()) else {
+        return Err(USAGE.to_owned());
+    };
+    let (name, mut database, storage) = crate::db_loader::load_with_storage(dir_path, &db_name)?;
+
+    match command.as_str() {
+        "list" => {
+            for (index, rule) in database.get_rules().iter().enumerate() {
+                let text = serde_json::to_string(rule).map_err(|error| error.to_string())?;
+                println!("{}) {text}", index + 1);
+            }
+            return Ok(())
+        },
+        "add" => {
+            let path = args.next().ok_or_else(|| USAGE.to_owned())?;
+            let text = std::fs::read_to_string(&path).map_err(|error| format!("could not read {path}: {error}"))?;
+            let rules = match text.trim_start().starts_with('[') {
+                true => serde_json::from_str::<Vec<Rule>>(&text),
+                false => serde_json::from_str::<Rule>(&text).map(|rule| vec![rule]),
+            }.map_err(|error| format!("{path}: {error}"))?;
+            let n_rules = rules.len();
+            database.grouped(|database| {
+                rules.into_iter().try_for_each(|rule| {
+                    let rule_name = rule.name.clone();
+                    database.add_rule(rule).map_err(|error| format!("rule {rule_name}: {error}"))
+                })
+            })?;
+            println!("{n_rules} rules added");
+        },
+        "remove" => {
+            let rule_name = args.next().ok_or_else(|| USAGE.to_owned())?;
+            database.remove_rule(&rule_name).map_err(|error| error.to_string())?;
+        },
+        "apply" => {
+            let mut query = Query::default();
+            while let Some(option) = args.next() {
+                let value = args.next().ok_or_else(|| format!("{option} needs a value\n\n{USAGE}"))?;
+                match option.as_str() {
+                    "--dates" => {
+                        let dates = DateParser::today()
+                            .parse(&value)
+                            .map_err(|error| format!("wrong dates {value}: {error}"))?;
+                        query = query.during(&dates);
+                    },
+                    "--account" => query.account = Some(value),
+                    _ => return Err(format!("unknown option {option}\n\n{USAGE}")),
+                }
+            }
+            let ids = database
+                .query(&query)
+                .map_err(|error| error.to_string())?
+                .into_iter()
+                .map(|transaction| transaction.generate_id())
+                .collect::<Vec<_>>();
+            let applied = database.apply_rules(&ids).map_err(|error| error.to_string())?;
+            println!("{} of {} transactions changed", applied.changed.len(), ids.len());
+            for transaction in applied.duplicates.iter().filter_map(|id| database.get_transaction(id)) {
+                println!(
+                    "{} on {} left as it was: the rules make it a copy of another one",
+                    transaction.get_name(),
+                    transaction.get_datetime().get_date_string()
+                );
+            }
+        },
+        _ => return Err(USAGE.to_owned()),
+    }
+
+    storage.save(&name, &database).map_err(|error| error.to_string())
+}
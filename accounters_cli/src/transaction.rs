@@ -1,11 +1,11 @@
 use accounters_lib::data::{
     Database,
     transaction::{Transaction, TransactionId},
-    datetime::DateTime,
-    account::{AccountName, AccountType}
+    account::AccountType,
+    datespec::DateSpec
 };
 
-use time::{Date, Time, macros::format_description};
+use time::{Date, macros::format_description};
 
 pub struct MultiTransactionViewState {
     id_list: Vec<TransactionId>,
@@ -27,7 +27,7 @@ impl MultiTransactionViewState {
     pub fn new(database: &Database) -> Self {
         let mut all_transactions_id: Vec<TransactionId> = database.get_transaction_ids().cloned().collect();
 
-        all_transactions_id.sort_by_key(|id| database.get_transaction(id).get_datetime());
+        all_transactions_id.sort_by_key(|id| database.get_transaction(id).map(|x| *x.get_datetime()));
         all_transactions_id.reverse();
         let config = MultiTransactionViewConfig;
 
@@ -51,7 +51,11 @@ impl MultiTransactionViewState {
             self.id_list.len()
         );
         for (index, transaction_index) in ((self.current_range.0)..(self.current_range.1)).enumerate() {
-            let transaction = database.get_transaction(&self.id_list[transaction_index]);
+            let Some(transaction) = self.id_list
+                .get(transaction_index)
+                .and_then(|id| database.get_transaction(id)) else {
+                continue
+            };
 
             let date = transaction.get_datetime().get_date();
             let time = transaction.get_datetime().get_time();
@@ -59,14 +63,14 @@ impl MultiTransactionViewState {
             output.push_str(&format!(
                 "\t{}\t{}  {} \t{}\n",
                 index+1,
-                if last_date.map_or(true, |x| x != *date) { format!("{}", date) } else { "          ".to_string() },
+                if last_date != Some(*date) { format!("{}", date) } else { "          ".to_string() },
                 time.map_or("     ".to_string(), |x| x.format(&format_description!("[hour]:[minute]")).unwrap()),
                 transaction.get_name()
             ));
 
             last_date = Some(date.to_owned());
         }
-        (output, String::from("Your move"))
+        (output, String::from("Show a row (1 or #1), jump to a date, go forward (f), back (b) or quit (q):"))
     }
 
     pub fn move_forward(&mut self, _n: Option<usize>) {
@@ -76,6 +80,16 @@ impl MultiTransactionViewState {
         )
     }
 
+    /// Show the transactions from the last one within `date` backwards
+    pub fn move_to(&mut self, database: &Database, date: &DateSpec) {
+        let end = date.end();
+        let first = self.id_list
+            .iter()
+            .position(|id| database.get_transaction(id).is_some_and(|x| *x.get_datetime() <= end))
+            .unwrap_or(self.id_list.len());
+        self.current_range = (first, first + self.config.get_transactions_per_page())
+    }
+
     pub fn move_back(&mut self, _n: Option<usize>) {
         self.current_range = (
             self.current_range.0 - self.config.get_transactions_per_page(),
@@ -93,7 +107,9 @@ impl TransactionViewState {
         Self { transaction_id }
     }
     pub fn produce_text(&self, database: &Database) -> String {
-        let transaction = database.get_transaction(&self.transaction_id);
+        let Some(transaction) = database.get_transaction(&self.transaction_id) else {
+            return format!("There is no transaction with id {}\n", self.transaction_id.0)
+        };
         let mut output = format!(
             "Transaction with id {}\n\n",
             self.transaction_id.0
@@ -112,11 +128,11 @@ impl TransactionViewState {
         ));
 
         let asset_accounts = transaction.get_associated_accounts().filter(|acc_name| {
-            matches!(database.get_account(acc_name).get_account_type(), AccountType::Asset)
+            database.get_account(acc_name).is_some_and(|acc| matches!(acc.get_account_type(), AccountType::Asset))
         }).collect::<Vec<_>>();
 
         let flow_accounts = transaction.get_associated_accounts().filter(|acc_name| {
-            matches!(database.get_account(acc_name).get_account_type(), AccountType::Flow)
+            database.get_account(acc_name).is_some_and(|acc| matches!(acc.get_account_type(), AccountType::Flow))
         }).collect::<Vec<_>>();
 
         output.push_str("\n\tAssets:\n\n");
@@ -124,12 +140,13 @@ impl TransactionViewState {
             output.push_str("NOTHING\n");
         } else {
             for account in asset_accounts {
-                let amount = transaction.get_amount(account).unwrap();
-                output.push_str(&format!(
-                    "{:>30} : {}\n",
-                    account.as_ref(),
-                    amount
-                ));
+                if let Ok(amount) = transaction.get_amount(account) {
+                    output.push_str(&format!(
+                        "{:>30} : {}\n",
+                        account.as_ref(),
+                        amount
+                    ));
+                }
             }
         }
 
@@ -138,12 +155,13 @@ impl TransactionViewState {
             output.push_str("NOTHING\n");
         } else {
             for account in flow_accounts {
-                let amount = transaction.get_amount(account).unwrap();
-                output.push_str(&format!(
-                    "{:>30} : {}\n",
-                    account.as_ref(),
-                    amount
-                ));
+                if let Ok(amount) = transaction.get_amount(account) {
+                    output.push_str(&format!(
+                        "{:>30} : {}\n",
+                        account.as_ref(),
+                        amount
+                    ));
+                }
             }
         }
 
@@ -151,12 +169,14 @@ impl TransactionViewState {
     }
 }
 
+#[allow(dead_code)]
 pub struct TransactionEditState {
     transaction: Transaction,
     original_id: Option<TransactionId>,
     mode: Mode
 }
 
+#[allow(dead_code)]
 enum Mode {
     Neutral,
     EditName,
@@ -168,12 +188,13 @@ enum Mode {
     AddAmount,
 }
 
+#[allow(dead_code)]
 impl TransactionEditState {
     pub fn new(database: &Database, transaction_id: Option<TransactionId>) -> Self {
-        let transaction = transaction_id.map_or(
-            Transaction::empty(),
-            |id| database.get_transaction(&id).clone()
-        );
+        let transaction = transaction_id
+            .and_then(|id| database.get_transaction(&id))
+            .cloned()
+            .unwrap_or_else(Transaction::empty);
 
         Self {
             transaction,
@@ -0,0 +1,277 @@
+//! Import of ISO 20022 camt.053 bank statements
+//!
+//! Every booked `Ntry` of a statement becomes a transaction between the
+//! asset account and the uncategorized one, dated on its booking date.
+//!
+//! - The name is the counterparty: the creditor of payments and the debtor
+//!   of incomes. The remittance information goes to the notes, or becomes
+//!   the name if there is no counterparty.
+//! - The value date is kept in the `value_date` metadata, and the reference
+//!   given by the bank in `source_id`.
+//! - Opening balances are checked at the start of their day, and the other
+//!   balances at the end of theirs.
+
+use accounters_lib::data::{
+    account::AccountName,
+    datetime::DateTime,
+    money::Amount,
+    transaction::Transaction,
+};
+
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+use crate::imported::{read_text, BalanceCheck, Imported, StatementAccounts, Warning};
+use crate::markup::{parse_markup, Element};
+
+pub fn import_camt(path: &str, accounts: &StatementAccounts) -> Result<Imported, String> {
+    let text = read_text(path).map_err(|error| error.to_string())?;
+    parse_camt(&text, accounts)
+}
+
+pub fn parse_camt(text: &str, accounts: &StatementAccounts) -> Result<Imported, String> {
+    let root = parse_markup(text, 1)?;
+    let statements = root.descendants("Stmt");
+    if statements.is_empty() {
+        return Err(String::from("it is not a camt.053 statement"))
+    }
+
+    let mut imported = Imported::default();
+    let mut assets = BTreeSet::new();
+    for statement in statements {
+        let Some(account) = statement.child("Acct") else {
+            imported.warnings.push(Warning::new(statement.line, "statement skipped: it has no account"));
+            continue
+        };
+        let account_number = account
+            .path(&["Id", "IBAN"])
+            .or_else(|| account.path(&["Id", "Othr", "Id"]))
+            .and_then(Element::text);
+        let Some(account_number) = account_number else {
+            imported.warnings.push(Warning::new(account.line, "statement skipped: it has no account number"));
+            continue
+        };
+        let asset = accounts.asset_for(account_number);
+
+        for entry in statement.children_named("Ntry") {
+            match read_entry(entry, &asset, accounts) {
+                Ok(Some(transaction)) => imported.transactions.push(transaction),
+                Ok(None) => (),
+                Err(message) => imported.warnings.push(Warning::new(entry.line, format!("entry skipped: {message}"))),
+            }
+        }
+
+        for balance in statement.children_named("Bal") {
+            match read_balance(balance, &asset) {
+                Ok(Some(check)) => imported.balances.push(check),
+                Ok(None) => (),
+                Err(message) => imported.warnings.push(Warning::new(balance.line, format!("balance not checked: {message}"))),
+            }
+        }
+        assets.insert(asset);
+    }
+
+    imported.accounts = accounts.accounts(assets.iter());
+    Ok(imported)
+}
+
+/// The transaction of an entry, or nothing if it is not booked yet
+fn read_entry(entry: &Element, asset: &str, accounts: &StatementAccounts) -> Result<Option<Transaction>, String> {
+    let status = entry.child("Sts").and_then(|status| status.text().or_else(|| status.value_of("Cd")));
+    if matches!(status, Some("PDNG" | "INFO" | "FUTR")) {
+        return Ok(None)
+    }
+
+    let amount = read_amount(entry)?;
+    let booking_date = date_of(entry.child("BookgDt")).ok_or("it has no booking date")?;
+    let value_date = date_of(entry.child("ValDt"));
+
+    let details = entry.path(&["NtryDtls", "TxDtls"]);
+    let is_credit = entry.value_of("CdtDbtInd") == Some("CRDT");
+    let counterparty = details.and_then(|details| {
+        let (first, second) = if is_credit { ("Dbtr", "Cdtr") } else { ("Cdtr", "Dbtr") };
+        [first, second].into_iter().find_map(|party| {
+            let parties = details.child("RltdPties")?;
+            parties.path(&[party, "Nm"]).or_else(|| parties.path(&[party, "Pty", "Nm"]))?.text()
+        })
+    });
+    let remittance = details.and_then(|details| details.child("RmtInf")).map(|information| {
+        let unstructured = information.children_named("Ustrd").filter_map(Element::text).collect::<Vec<_>>();
+        if unstructured.is_empty() {
+            information
+                .descendants("Ref")
+                .into_iter()
+                .filter_map(Element::text)
+                .collect::<Vec<_>>()
+                .join(" ")
+        } else {
+            unstructured.join(" ")
+        }
+    }).filter(|remittance| !remittance.is_empty());
+    let additional = entry.value_of("AddtlNtryInf");
+
+    let (name, notes) = match (counterparty, remittance.as_deref(), additional) {
+        (Some(counterparty), remittance, _) => (counterparty, remittance.unwrap_or_default()),
+        (None, Some(remittance), _) => (remittance, ""),
+        (None, None, Some(additional)) => (additional, ""),
+        (None, None, None) => ("", ""),
+    };
+
+    let mut transaction = accounts.transaction(asset, name, notes, booking_date, amount);
+    if let Some(value_date) = value_date {
+        transaction.set_metadata("value_date", &value_date.get_date_string());
+    }
+    let reference = entry
+        .value_of("AcctSvcrRef")
+        .or_else(|| details.and_then(|details| details.path(&["Refs", "AcctSvcrRef"])?.text()))
+        .or_else(|| details.and_then(|details| details.path(&["Refs", "EndToEndId"])?.text()))
+        .filter(|reference| *reference != "NOTPROVIDED");
+    if let Some(reference) = reference {
+        transaction.set_metadata("source_id", reference);
+    }
+    Ok(Some(transaction))
+}
+
+/// The balance to check, if it is of a kind that says how much was in the
+/// account at some moment
+fn read_balance(balance: &Element, asset: &str) -> Result<Option<BalanceCheck>, String> {
+    let kind = balance.path(&["Tp", "CdOrPrtry", "Cd"]).and_then(Element::text);
+    let date = date_of(balance.child("Dt")).ok_or("it has no date")?;
+    let date = match kind {
+        // The opening balance is the one at the end of the day before
+        Some("OPBD") => DateTime::new(date.get_date().previous_day().ok_or("invalid date")?, None),
+        Some("PRCD" | "CLBD" | "ITBD") => date,
+        _ => return Ok(None),
+    };
+    Ok(Some(BalanceCheck {
+        line: balance.line,
+        account: AccountName::new(asset),
+        date,
+        amount: read_amount(balance)?,
+    }))
+}
+
+/// The amount of an entry or a balance, negative if it is a debit
+fn read_amount(element: &Element) -> Result<Amount, String> {
+    let amount = element.child("Amt").ok_or("it has no amount")?;
+    let number = amount.text().ok_or("it has no amount")?;
+    let currency = amount.attributes.get("Ccy").ok_or("the amount has no currency")?;
+    let sign = match element.value_of("CdtDbtInd") {
+        Some("DBIT") => "-",
+        _ => "",
+    };
+    Amount::from_str(&format!("{sign}{number} {currency}")).map_err(|_| format!("invalid amount '{number}'"))
+}
+
+/// A date given as `<Dt>` or `<DtTm>`, keeping only the day
+fn date_of(element: Option<&Element>) -> Option<DateTime> {
+    let element = element?;
+    let text = element.value_of("Dt").or_else(|| element.value_of("DtTm"))?;
+    DateTime::from_str(text.get(..10)?).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use accounters_lib::data::Database;
+
+    mod statement {
+        use super::*;
+
+        const EXAMPLE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.02">
+  <BkToCstmrStmt>
+    <GrpHdr><MsgId>1</MsgId></GrpHdr>
+    <Stmt>
+      <Id>STMT-1</Id>
+      <Acct><Id><IBAN>SE4550000000058398257466</IBAN></Id><Ccy>SEK</Ccy></Acct>
+      <Bal>
+        <Tp><CdOrPrtry><Cd>OPBD</Cd></CdOrPrtry></Tp>
+        <Amt Ccy="SEK">0.00</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <Dt><Dt>2024-03-01</Dt></Dt>
+      </Bal>
+      <Bal>
+        <Tp><CdOrPrtry><Cd>CLBD</Cd></CdOrPrtry></Tp>
+        <Amt Ccy="SEK">24500.00</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <Dt><Dt>2024-03-31</Dt></Dt>
+      </Bal>
+      <Ntry>
+        <Amt Ccy="SEK">25000.00</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <Sts>BOOK</Sts>
+        <BookgDt><Dt>2024-03-25</Dt></BookgDt>
+        <ValDt><Dt>2024-03-26</Dt></ValDt>
+        <AcctSvcrRef>REF-1</AcctSvcrRef>
+        <NtryDtls><TxDtls>
+          <RltdPties><Dbtr><Nm>Employer AB</Nm></Dbtr><Cdtr><Nm>Me</Nm></Cdtr></RltdPties>
+          <RmtInf><Ustrd>Salary</Ustrd><Ustrd>March</Ustrd></RmtInf>
+        </TxDtls></NtryDtls>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="SEK">500.00</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <Sts><Cd>BOOK</Cd></Sts>
+        <BookgDt><DtTm>2024-03-28T10:15:00+01:00</DtTm></BookgDt>
+        <NtryDtls><TxDtls>
+          <Refs><EndToEndId>NOTPROVIDED</EndToEndId></Refs>
+          <RmtInf><Strd><CdtrRefInf><Ref>RF18539007547034</Ref></CdtrRefInf></Strd></RmtInf>
+        </TxDtls></NtryDtls>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="SEK">99.00</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <Sts>PDNG</Sts>
+        <BookgDt><Dt>2024-03-31</Dt></BookgDt>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="SEK">1.00</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+      </Ntry>
+    </Stmt>
+  </BkToCstmrStmt>
+</Document>
+"#;
+
+        #[test]
+        fn importing() {
+            let imported = parse_camt(EXAMPLE, &StatementAccounts::default()).unwrap();
+            assert_eq!(imported.warnings, vec![Warning::new(48, "entry skipped: it has no booking date")]);
+
+            let [salary, payment] = imported.transactions.as_slice() else {
+                panic!("there should be two transactions")
+            };
+            assert_eq!(salary.get_name(), "Employer AB");
+            assert_eq!(salary.get_notes(), "Salary March");
+            assert_eq!(salary.get_metadata()["value_date"], "2024-03-26");
+            assert_eq!(salary.get_metadata()["source_id"], "REF-1");
+            assert_eq!(payment.get_name(), "RF18539007547034");
+            assert_eq!(payment.get_datetime(), &DateTime::from_str("2024-03-28").unwrap());
+            assert!(payment.get_metadata().get("source_id").is_none());
+            assert_eq!(
+                payment.get_amount(&AccountName::new("asset/SE4550000000058398257466")).unwrap(),
+                &Amount::from_str("-500 SEK").unwrap()
+            );
+
+            let mut database = Database::default();
+            for account in imported.accounts.iter() {
+                database.add_account(account.clone()).unwrap();
+            }
+            for transaction in imported.transactions.iter() {
+                database.add_transaction(transaction.clone()).unwrap();
+            }
+            let [opening, closing] = imported.balances.as_slice() else {
+                panic!("there should be two balances")
+            };
+            assert_eq!(opening.date, DateTime::from_str("2024-02-29").unwrap());
+            assert!(opening.verify(&database).is_ok());
+            assert!(closing.verify(&database).is_ok());
+        }
+
+        #[test]
+        fn not_camt() {
+            assert!(parse_camt("<OFX></OFX>", &StatementAccounts::default()).is_err());
+        }
+    }
+}
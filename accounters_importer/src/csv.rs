@@ -0,0 +1,448 @@
+//! Import of CSV files described by a [`Profile`]
+//!
+//! Each row becomes a transaction between the asset account of the profile
+//! and the first counter account whose condition the row meets. Rows that
+//! cannot be read are skipped with a warning.
+
+use accounters_lib::data::{
+    account::{Account, AccountType},
+    datespec::{DateFormat, DateParser},
+    datetime::DateTime,
+    money::{Amount, Number},
+    transaction::Transaction,
+};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::iter::Peekable;
+use std::str::Chars;
+use std::str::FromStr;
+
+use crate::imported::{read_text, Imported, Warning};
+use crate::profile::{fill_template, Column, Condition, Profile, Sign};
+
+pub fn import_csv(path: &str, profile: &Profile) -> Result<Imported, String> {
+    let text = read_text(path).map_err(|error| error.to_string())?;
+    parse_csv(&text, profile)
+}
+
+pub fn parse_csv(text: &str, profile: &Profile) -> Result<Imported, String> {
+    // Files saved by spreadsheets often start with a byte order mark
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let mut records = Records::new(text, profile.delimiter);
+    let header = match profile.header {
+        true => match records.next().transpose() {
+            Ok(header) => header.map(|header| header.fields).unwrap_or_default(),
+            Err(error) => return Err(format!("invalid header: {error}")),
+        },
+        false => Vec::new(),
+    };
+    let mut indices = BTreeMap::new();
+    for (name, column) in profile.columns.iter() {
+        let index = match column {
+            Column::Index(index) => *index,
+            Column::Name(column) => header
+                .iter()
+                .position(|title| title.trim() == column)
+                .ok_or_else(|| format!("there is no column '{column}' in the header"))?,
+        };
+        indices.insert(name.as_str(), index);
+    }
+
+    let mut date_parser = DateParser::today();
+    if let Some(format) = &profile.date_format {
+        date_parser = date_parser.with_format(DateFormat::new(format));
+    }
+    let reader = RowReader { profile, indices, date_parser };
+
+    let mut imported = Imported::default();
+    for record in records {
+        let warning = match record {
+            Ok(record) => match reader.read(&record.fields) {
+                Ok(transaction) => {
+                    imported.transactions.push(transaction);
+                    continue
+                },
+                Err(message) => Warning::new(record.line, format!("row skipped: {message}")),
+            },
+            Err(error) => Warning::new(
+                error.line,
+                format!("row skipped: column {}: {}", error.column, error.message),
+            ),
+        };
+        imported.warnings.push(warning);
+    }
+    imported.accounts = accounts_from_names(&imported.transactions);
+    Ok(imported)
+}
+
+/// A row of a CSV file, with the line where it starts
+#[derive(Debug, PartialEq, Eq)]
+pub struct Record {
+    pub line: usize,
+    pub fields: Vec<String>,
+}
+
+/// A row that does not follow the CSV syntax
+#[derive(Debug, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl Display for SyntaxError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+/// The rows of a CSV file as described in RFC 4180
+///
+/// Quoted fields can have delimiters, line breaks and quotes, written as
+/// `""`. Empty lines are skipped. After a row with an error, reading goes
+/// on from the next line.
+#[derive(Clone)]
+pub struct Records<'a> {
+    chars: Peekable<Chars<'a>>,
+    delimiter: char,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Records<'a> {
+    pub fn new(text: &'a str, delimiter: char) -> Self {
+        Records { chars: text.chars().peekable(), delimiter, line: 1, column: 1 }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    /// An error at the current position
+    fn error(&self, message: impl Into<String>) -> SyntaxError {
+        SyntaxError { line: self.line, column: self.column, message: message.into() }
+    }
+
+    /// Go past the end of the current line
+    fn skip_line(&mut self) {
+        while self.bump().is_some_and(|c| c != '\n') {}
+    }
+
+    fn read_quoted(&mut self, field: &mut String) -> Result<(), SyntaxError> {
+        let start = self.clone();
+        self.bump();
+        loop {
+            match self.bump() {
+                Some('"') if self.chars.peek() == Some(&'"') => {
+                    self.bump();
+                    field.push('"');
+                },
+                Some('"') => break,
+                Some(c) => field.push(c),
+                None => {
+                    // Only the line of the quote is lost
+                    *self = start;
+                    let error = self.error("the quote is never closed");
+                    self.skip_line();
+                    return Err(error)
+                }
+            }
+        }
+        match self.chars.peek() {
+            Some(&c) if c != self.delimiter && c != '\r' && c != '\n' => {
+                let error = self.error(format!("unexpected '{c}' after a closing quote"));
+                self.skip_line();
+                Err(error)
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn read_unquoted(&mut self, field: &mut String) -> Result<(), SyntaxError> {
+        while let Some(&c) = self.chars.peek() {
+            if c == self.delimiter || c == '\r' || c == '\n' {
+                break
+            }
+            if c == '"' {
+                let error = self.error("quotes are only allowed in quoted fields");
+                self.skip_line();
+                return Err(error)
+            }
+            field.push(c);
+            self.bump();
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Records<'_> {
+    type Item = Result<Record, SyntaxError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while matches!(self.chars.peek(), Some('\r' | '\n')) {
+            self.bump();
+        }
+        self.chars.peek()?;
+
+        let line = self.line;
+        let mut fields = Vec::new();
+        loop {
+            let mut field = String::new();
+            let read = match self.chars.peek() {
+                Some('"') => self.read_quoted(&mut field),
+                _ => self.read_unquoted(&mut field),
+            };
+            if let Err(error) = read {
+                return Some(Err(error))
+            }
+            fields.push(field);
+            match self.bump() {
+                Some('\r') => {
+                    if self.chars.peek() == Some(&'\n') {
+                        self.bump();
+                    }
+                    break
+                },
+                Some('\n') | None => break,
+                _ => (),
+            }
+        }
+        Some(Ok(Record { line, fields }))
+    }
+}
+
+struct RowReader<'a> {
+    profile: &'a Profile,
+    /// The position of each column of the profile
+    indices: BTreeMap<&'a str, usize>,
+    date_parser: DateParser,
+}
+
+impl RowReader<'_> {
+    fn read(&self, fields: &[String]) -> Result<Transaction, String> {
+        let value = |column: &str| self.value(fields, column);
+        let required = |column: &str| value(column).ok_or_else(|| format!("the {column} is missing"));
+
+        let date = required("date")?;
+        let mut datetime = self.date_parser
+            .parse_datetime(date)
+            .map_err(|error| format!("invalid date '{date}' in column {}: {error}", self.position("date")))?;
+        if let Some(time) = value("time") {
+            datetime = DateTime::from_str(&format!("{} {}", datetime.get_date_string(), time))
+                .map_err(|error| format!("invalid time '{time}' in column {}: {error}", self.position("time")))?;
+        }
+
+        let mut number = match (value("amount"), value("credit"), value("debit")) {
+            (Some(amount), _, _) => self.number("amount", amount)?,
+            (None, None, None) => return Err(String::from("the amount is missing")),
+            (None, credit, debit) => {
+                let credit = credit.map(|credit| self.number("credit", credit)).transpose()?.unwrap_or_default();
+                let debit = debit.map(|debit| self.number("debit", debit)).transpose()?.unwrap_or_default();
+                credit - debit
+            }
+        };
+        let is_negative = !number.is_nonnegative();
+        let negate = match &self.profile.sign {
+            Sign::AsIs => false,
+            Sign::Negated => true,
+            Sign::NegativeWhen(condition) => self.meets(fields, condition) != is_negative,
+        };
+        if negate {
+            number = -number;
+        }
+        let currency = value("currency")
+            .or(self.profile.currency.as_deref())
+            .ok_or("the currency is missing")?;
+        let amount = Amount::from_str(&format!("{} {}", number.to_plain_string(), currency))
+            .map_err(|error| format!("invalid amount: {error}"))?;
+
+        let fill = |template: &str| fill_template(template, |column| value(column).unwrap_or_default().to_owned());
+        let asset = fill(&self.profile.asset_account);
+        let counter = self.profile.counter_accounts
+            .iter()
+            .find(|counter| counter.when.as_ref().is_none_or(|condition| self.meets(fields, condition)))
+            .ok_or("it meets the condition of no counter account")?;
+        let counter_account = fill(&counter.account);
+        if counter_account == asset {
+            return Err(format!("both sides are the account {asset}"))
+        }
+        let counter_amount = if counter.negate { -&amount } else { amount.clone() };
+
+        let mut transaction = Transaction::from_amounts(
+            value("name").unwrap_or_default(),
+            value("notes").unwrap_or_default(),
+            datetime,
+            &[(asset, amount), (counter_account, counter_amount)],
+        );
+        if let Some(id) = value("id") {
+            transaction.set_metadata("source_id", id);
+        }
+        Ok(transaction)
+    }
+
+    /// The value of a column of the profile, if it is not empty
+    fn value<'f>(&self, fields: &'f [String], column: &str) -> Option<&'f str> {
+        let index = self.indices.get(column)?;
+        fields.get(*index).map(|value| value.trim()).filter(|value| !value.is_empty())
+    }
+
+    /// The position of a column of the profile, starting at 1 as in the
+    /// errors of the file
+    fn position(&self, column: &str) -> usize {
+        self.indices.get(column).map_or(0, |index| index + 1)
+    }
+
+    fn meets(&self, fields: &[String], condition: &Condition) -> bool {
+        let value = self.value(fields, &condition.column).unwrap_or_default();
+        condition.values.iter().any(|expected| expected == value)
+    }
+
+    /// Read a number written with the separators of the profile
+    fn number(&self, column: &str, text: &str) -> Result<Number, String> {
+        let mut plain = text.replace(' ', "");
+        if let Some(separator) = self.profile.thousands_separator {
+            plain = plain.replace(separator, "");
+        }
+        if self.profile.decimal_separator != '.' {
+            plain = plain.replace('.', "").replace(self.profile.decimal_separator, ".");
+        }
+        Number::from_str(&plain).map_err(|_| format!("invalid amount '{text}' in column {}", self.position(column)))
+    }
+}
+
+/// The accounts used by the transactions, with their type guessed from the
+/// name
+fn accounts_from_names(transactions: &[Transaction]) -> Vec<Account> {
+    let account_names = transactions
+        .iter()
+        .flat_map(|transaction| transaction.get_associated_accounts())
+        .collect::<BTreeSet<_>>();
+
+    account_names.into_iter().map(|account_name| {
+        let account_class = match account_name.as_ref().split('/').next() {
+            Some("asset") => AccountType::Asset,
+            _ => AccountType::Flow
+        };
+        Account::new(account_name.as_ref(), account_class)
+    }).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::profile::{load_profiles, parse_profiles};
+    use accounters_lib::data::account::AccountName;
+
+    mod reading {
+        use super::*;
+
+        fn read(text: &str) -> Vec<Result<Record, SyntaxError>> {
+            Records::new(text, ',').collect()
+        }
+
+        fn record(line: usize, fields: &[&str]) -> Result<Record, SyntaxError> {
+            Ok(Record { line, fields: fields.iter().map(|field| field.to_string()).collect() })
+        }
+
+        #[test]
+        fn fields() {
+            let text = "a,\"b,c\",,\"say \"\"hi\"\"\"\r\n\r\n\"two\nlines\",x,\"\"\nlast,";
+            assert_eq!(read(text), vec![
+                record(1, &["a", "b,c", "", "say \"hi\""]),
+                record(3, &["two\nlines", "x", ""]),
+                record(5, &["last", ""]),
+            ]);
+            assert_eq!(Records::new("1;\"2;3\"\n", ';').collect::<Vec<_>>(), vec![record(1, &["1", "2;3"])]);
+        }
+
+        #[test]
+        fn errors() {
+            let error = |line, column, message: &str| Err(SyntaxError { line, column, message: message.to_owned() });
+            let text = "a,\"b\"c,d\nok,1\nx,5\" screen\ny,\"open\nz,2\n";
+            assert_eq!(read(text), vec![
+                error(1, 6, "unexpected 'c' after a closing quote"),
+                record(2, &["ok", "1"]),
+                error(3, 4, "quotes are only allowed in quoted fields"),
+                error(4, 3, "the quote is never closed"),
+                record(5, &["z", "2"]),
+            ]);
+        }
+    }
+
+    mod profiles {
+        use super::*;
+
+        #[test]
+        fn expenses_app() {
+            let profile = &load_profiles(None).unwrap()["expenses_app"];
+            let text = r#""Tipo","Fecha","Hora","Título","Cantidad","Divisa","Cambio","Grupo","Categoría","Cuenta","Notas","Etiquetas","Estado"
+"Gastos","2023-07-13 14:54:00","14:54","Comprar nabos","-132.00","SEK","1","Comida","Verdura","ICA_Bank","Con ""comillas"", y coma","",""
+"Ingresos","2023-07-14 09:00:00","09:00","Nómina","2000","SEK","1","Trabajo","Sueldo","ICA_Bank","","",""
+"Transferencia","2023-07-15 10:00:00","10:00","Ahorro","-500","SEK","1","","","ICA_Bank","","",""
+"Gastos","ayer por la tarde","","Roto","-1","SEK","1","","","ICA_Bank","","",""
+"#;
+            let imported = parse_csv(text, profile).unwrap();
+            assert_eq!(imported.warnings.len(), 1);
+            assert_eq!(imported.warnings[0].line, 5);
+            assert!(imported.warnings[0].message.starts_with("row skipped: invalid date 'ayer por la tarde' in column 2"));
+
+            let [food, salary, transfer] = imported.transactions.as_slice() else {
+                panic!("there should be three transactions")
+            };
+            let amount = |transaction: &Transaction, account: &str| {
+                transaction.get_amount(&AccountName::new(account)).unwrap().to_plain_string()
+            };
+            assert_eq!(food.get_datetime(), &DateTime::from_str("2023-07-13 14:54").unwrap());
+            assert_eq!(food.get_notes(), "Con \"comillas\", y coma");
+            assert_eq!(amount(food, "expense/Comida/Verdura"), "-132.00 SEK");
+            assert_eq!(amount(salary, "income/Trabajo/Sueldo"), "2000.00 SEK");
+            assert_eq!(amount(transfer, "asset/ICA_Bank"), "-500.00 SEK");
+            assert_eq!(amount(transfer, "asset/transfer"), "500.00 SEK");
+            assert_eq!(imported.accounts.len(), 4);
+        }
+
+        #[test]
+        fn bank_export() {
+            let profiles = parse_profiles(r#"{"bank": {
+                "delimiter": ";",
+                "columns": {"date": "Datum", "name": "Text", "debit": "Ut", "credit": "In", "id": "Ref", "kind": "Typ"},
+                "date_format": "dd.mm.yyyy",
+                "decimal_separator": ",",
+                "thousands_separator": " ",
+                "currency": "SEK",
+                "asset_account": "asset/bank",
+                "counter_accounts": [
+                    {"when": {"column": "kind", "is": ["Överföring"]}, "account": "asset/savings", "negate": true},
+                    {"account": "flow/{kind}"}
+                ]
+            }}"#).unwrap();
+            let text = "Datum;Typ;Text;Ut;In;Ref\n31.01.2024;Kort;ICA;1 234,50;;A1\n01.02.2024;Överföring;Spar;100;;A2\n";
+            let imported = parse_csv(text, &profiles["bank"]).unwrap();
+            assert!(imported.warnings.is_empty());
+
+            let [card, savings] = imported.transactions.as_slice() else {
+                panic!("there should be two transactions")
+            };
+            assert_eq!(card.get_datetime(), &DateTime::from_str("2024-01-31").unwrap());
+            assert_eq!(card.get_amount(&AccountName::new("flow/Kort")).unwrap().to_plain_string(), "-1234.50 SEK");
+            assert_eq!(card.get_metadata()["source_id"], "A1");
+            assert_eq!(savings.get_amount(&AccountName::new("asset/savings")).unwrap().to_plain_string(), "100.00 SEK");
+
+            let with_bom = parse_csv(&format!("\u{feff}{text}"), &profiles["bank"]).unwrap();
+            assert!(with_bom.warnings.is_empty());
+            assert_eq!(with_bom.transactions.len(), 2);
+
+            let no_amount = parse_csv(&format!("{text}02.02.2024;Kort;ICA;;;A3\n"), &profiles["bank"]).unwrap();
+            assert_eq!(no_amount.transactions.len(), 2);
+            assert_eq!(no_amount.warnings, vec![Warning::new(4, "row skipped: the amount is missing")]);
+        }
+    }
+}
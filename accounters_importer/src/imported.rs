@@ -0,0 +1,134 @@
+use accounters_lib::data::{
+    account::{Account, AccountName, AccountType},
+    datespec::DateSpec,
+    datetime::DateTime,
+    money::{Amount, Currency},
+    transaction::Transaction,
+    Database
+};
+
+/// Everything read from a file by one of the importers
+#[derive(Default)]
+pub struct Imported {
+    pub accounts: Vec<Account>,
+    pub transactions: Vec<Transaction>,
+    pub prices: Vec<Price>,
+    /// Balances stated in the file, to be checked once the transactions
+    /// are in the database
+    pub balances: Vec<BalanceCheck>,
+    /// What could not be imported
+    pub warnings: Vec<Warning>,
+}
+
+/// The price of a commodity at some moment
+pub struct Price {
+    pub datetime: DateTime,
+    pub commodity: Currency,
+    pub price: Amount,
+}
+
+/// The balance that an account should have at the end of a day
+pub struct BalanceCheck {
+    pub line: usize,
+    pub account: AccountName,
+    pub date: DateTime,
+    pub amount: Amount,
+}
+
+impl BalanceCheck {
+    /// Compare the balance with the one of the account in the database,
+    /// which includes everything until the end of the day
+    pub fn verify(&self, database: &Database) -> Result<(), Warning> {
+        let end = DateSpec::Date(self.date).end();
+        let balance = database
+            .get_account_balance(&self.account, None, Some(end))
+            .map_err(|error| Warning::new(self.line, format!("the balance could not be checked: {error}")))?;
+        if (balance.clone() - &self.amount).is_zero() {
+            Ok(())
+        } else {
+            Err(Warning::new(self.line, format!(
+                "the balance of {} on {} is {} in the file, but {} in the database",
+                self.account.as_ref(),
+                self.date.get_date_string(),
+                self.amount,
+                balance
+            )))
+        }
+    }
+}
+
+/// Where the transactions of a bank statement go
+///
+/// Statements only tell about one account, so every transaction gets a
+/// counter-posting to an account to be categorized later.
+#[derive(Clone)]
+pub struct StatementAccounts {
+    /// The account of the statement. If there is none, it is named after
+    /// the account number in the statement, like `asset/12345678`.
+    pub asset: Option<String>,
+    pub uncategorized: String,
+    /// The currency of the files that do not tell it, like QIF files
+    pub currency: Option<Currency>,
+}
+
+impl Default for StatementAccounts {
+    fn default() -> Self {
+        StatementAccounts { asset: None, uncategorized: String::from("flow/uncategorized"), currency: None }
+    }
+}
+
+impl StatementAccounts {
+    pub fn asset_for(&self, account_number: &str) -> String {
+        self.asset.clone().unwrap_or_else(|| format!("asset/{}", account_number.trim().replace('/', "_")))
+    }
+
+    /// A transaction that moves the amount between the asset account and the
+    /// uncategorized one
+    pub fn transaction(&self, asset: &str, name: &str, notes: &str, date: DateTime, amount: Amount) -> Transaction {
+        // Flows have the same sign as the assets they move
+        let amounts = [(asset.to_owned(), amount.clone()), (self.uncategorized.clone(), amount)];
+        Transaction::from_amounts(name, notes, date, &amounts)
+    }
+
+    /// Both accounts, for the transactions of the given asset accounts
+    pub fn accounts<'a, I: IntoIterator<Item = &'a String>>(&self, assets: I) -> Vec<Account> {
+        let mut accounts = assets
+            .into_iter()
+            .map(|name| Account::new(name, AccountType::Asset))
+            .collect::<Vec<_>>();
+        accounts.push(Account::new(&self.uncategorized, AccountType::Flow));
+        accounts
+    }
+}
+
+/// Something in the file that was skipped, with the line where it is
+/// (starting at 1)
+#[derive(Debug, PartialEq, Eq)]
+pub struct Warning {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Warning {
+    pub fn new(line: usize, message: impl Into<String>) -> Self {
+        Warning { line, message: message.into() }
+    }
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Read a text file, as Latin-1 if it is not UTF-8
+///
+/// Bank exports are often in the encoding of old Windows versions, which
+/// is close enough to Latin-1 for account names and payees.
+pub fn read_text(path: &str) -> std::io::Result<String> {
+    let bytes = std::fs::read(path)?;
+    Ok(match String::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(error) => error.into_bytes().into_iter().map(char::from).collect(),
+    })
+}
@@ -0,0 +1,692 @@
+//! Import of beancount and ledger/hledger journals
+//!
+//! Both formats are close enough to be read by the same parser: dated
+//! directives start at the beginning of a line, and the postings and
+//! metadata of a transaction are indented below it.
+//!
+//! - `open` directives and ledger `account` declarations become accounts.
+//!   Accounts under `Assets` and `Liabilities`, or declared with an asset or
+//!   liability type, are asset accounts, the rest are flows.
+//! - Transactions keep their tags, and metadata lines or `key: value`
+//!   comments become metadata. Other comments become notes. Beancount links
+//!   are kept in the `links` metadata.
+//! - `price` and `P` directives are returned as prices.
+//! - Every other directive is reported as a warning with its line number.
+//!
+//! Amounts of flow accounts are negated, because ledger makes postings add
+//! up to zero while flows here have the same sign as the assets they move.
+
+use accounters_lib::data::{
+    account::{Account, AccountType},
+    datetime::DateTime,
+    money::{Amount, Currency},
+    tags::Tag,
+    transaction::Transaction
+};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::read_to_string;
+use std::str::FromStr;
+
+use crate::imported::{Imported, Price, Warning};
+
+/// Directives that are understood by ledger or beancount, but have nothing
+/// to become in the database
+const UNSUPPORTED: &[&str] = &[
+    "option", "plugin", "include", "pushtag", "poptag", "commodity", "alias", "apply", "end",
+    "year", "Y", "D", "N", "tag", "payee", "=", "~", "decimal-mark", "close", "balance", "pad",
+    "note", "document", "event", "query", "custom",
+];
+
+pub fn import_journal(path: &str) -> Result<Imported, std::io::Error> {
+    Ok(parse_journal(&read_to_string(path)?))
+}
+
+pub fn parse_journal(text: &str) -> Imported {
+    let mut parser = Parser::default();
+    for (index, line) in text.lines().enumerate() {
+        parser.parse_line(index + 1, line);
+    }
+    parser.finish()
+}
+
+struct PendingTransaction {
+    line: usize,
+    date: String,
+    time: Option<String>,
+    name: String,
+    notes: Vec<String>,
+    tags: BTreeSet<String>,
+    metadata: BTreeMap<String, String>,
+    postings: Vec<(String, Option<Amount>)>,
+    /// Why the transaction cannot be imported, if it cannot
+    skip: Option<String>,
+}
+
+#[derive(Default)]
+struct Parser {
+    /// Declared accounts, with the line where they first appear and their
+    /// type if the declaration tells it
+    declared: BTreeMap<String, (usize, Option<AccountType>)>,
+    current: Option<PendingTransaction>,
+    /// Complete transactions, with amounts as they are in the journal
+    transactions: Vec<(PendingTransaction, Vec<(String, Amount)>)>,
+    prices: Vec<Price>,
+    warnings: Vec<Warning>,
+}
+
+impl Parser {
+    fn parse_line(&mut self, line_number: usize, line: &str) {
+        let line = line.trim_end();
+        if line.is_empty() {
+            self.finish_transaction();
+            return
+        }
+
+        if line.starts_with(char::is_whitespace) {
+            // Indented lines belong to the last directive. Only the ones of
+            // transactions matter.
+            if let Some(transaction) = self.current.as_mut() {
+                parse_transaction_line(transaction, line_number, line.trim());
+            }
+            return
+        }
+        self.finish_transaction();
+
+        if line.starts_with([';', '#', '%', '|', '*']) {
+            return
+        }
+
+        let (first, rest) = split_token(line);
+        if first == "account" {
+            let (name, comment) = split_comment(rest);
+            let account_type = comment
+                .and_then(|comment| comment.split(',').find_map(|part| part.trim().strip_prefix("type:")))
+                .and_then(|account_type| match account_type.trim().chars().next() {
+                    Some('A' | 'L' | 'C') => Some(AccountType::Asset),
+                    Some(_) => Some(AccountType::Flow),
+                    None => None,
+                });
+            self.declared.insert(name.trim().to_owned(), (line_number, account_type));
+        } else if first == "P" {
+            let (date, rest) = split_token(rest);
+            let (time, rest) = match split_token(rest) {
+                (time, rest) if time.contains(':') => (Some(time), rest),
+                _ => (None, rest),
+            };
+            let (commodity, price) = split_token(rest);
+            self.add_price(line_number, date, time, commodity, price);
+        } else if UNSUPPORTED.contains(&first) {
+            self.warnings.push(Warning::new(line_number, format!("unsupported directive '{first}'")));
+        } else if first.starts_with(|c: char| c.is_ascii_digit()) {
+            self.parse_dated(line_number, first, rest);
+        } else {
+            self.warnings.push(Warning::new(line_number, format!("unrecognised line '{line}'")));
+        }
+    }
+
+    fn parse_dated(&mut self, line_number: usize, date: &str, rest: &str) {
+        // The auxiliary date of ledger is ignored
+        let date = date.split('=').next().unwrap().replace('/', "-");
+        if DateTime::from_str(&date).is_err() {
+            self.warnings.push(Warning::new(line_number, format!("invalid date '{date}'")));
+            return
+        }
+
+        let (keyword, arguments) = split_token(rest);
+        match keyword {
+            "open" => {
+                let (account, _) = split_token(arguments);
+                self.declared.entry(account.to_owned()).or_insert((line_number, None));
+            },
+            "price" => {
+                let (commodity, price) = split_token(arguments);
+                self.add_price(line_number, &date, None, commodity, price);
+            },
+            keyword if UNSUPPORTED.contains(&keyword) => {
+                self.warnings.push(Warning::new(line_number, format!("unsupported directive '{keyword}'")));
+            },
+            _ => self.current = Some(parse_transaction_header(line_number, date, rest)),
+        }
+    }
+
+    fn add_price(&mut self, line_number: usize, date: &str, time: Option<&str>, commodity: &str, price: &str) {
+        let date = date.replace('/', "-");
+        let datetime = match time {
+            Some(time) => DateTime::from_str(&format!("{date} {time}")),
+            None => DateTime::from_str(&date),
+        };
+        match (datetime, parse_amount(price)) {
+            (Ok(datetime), Some(price)) => self.prices.push(Price {
+                datetime,
+                commodity: Currency::new(&commodity_code(commodity)),
+                price,
+            }),
+            _ => self.warnings.push(Warning::new(line_number, "could not read the price")),
+        }
+    }
+
+    fn finish_transaction(&mut self) {
+        let Some(transaction) = self.current.take() else {
+            return
+        };
+        if let Some(reason) = &transaction.skip {
+            self.warnings.push(Warning::new(transaction.line, format!("transaction skipped: {reason}")));
+            return
+        }
+
+        let mut total = Amount::default();
+        let mut elided = None;
+        let mut postings = Vec::new();
+        for (account, amount) in transaction.postings.iter() {
+            match amount {
+                Some(amount) => {
+                    total = total + amount;
+                    postings.push((account.to_owned(), amount.to_owned()));
+                },
+                None if elided.is_none() => elided = Some(account.to_owned()),
+                None => {
+                    self.warnings.push(Warning::new(
+                        transaction.line,
+                        "transaction skipped: more than one posting without amount"
+                    ));
+                    return
+                }
+            }
+        }
+        if let Some(account) = elided {
+            postings.push((account, -&total));
+        }
+
+        self.transactions.push((transaction, postings));
+    }
+
+    fn finish(mut self) -> Imported {
+        self.finish_transaction();
+
+        for (pending, postings) in self.transactions.iter() {
+            for (account, _) in postings {
+                self.declared.entry(account.to_owned()).or_insert((pending.line, None));
+            }
+        }
+
+        // Names that only differ in the root, like Assets:Bank and
+        // asset:Bank, are the same account in the database. The first one
+        // gives it its type.
+        let mut types = BTreeMap::<String, (&str, AccountType)>::new();
+        for (name, (line, account_type)) in self.declared.iter() {
+            let account_type = account_type.unwrap_or_else(|| type_from_root(name));
+            let mapped = account_name(name);
+            match types.get(&mapped) {
+                None => { types.insert(mapped, (name, account_type)); },
+                Some((first, first_type)) => {
+                    let mut message = format!("{name} is imported as {mapped}, like {first}");
+                    if *first_type != account_type {
+                        message.push_str(&format!(", so it has the type of {first}"));
+                    }
+                    self.warnings.push(Warning::new(*line, message));
+                },
+            }
+        }
+
+        let accounts = types
+            .iter()
+            .map(|(name, (_, account_type))| Account::new(name, *account_type))
+            .collect();
+
+        let transactions = self.transactions
+            .into_iter()
+            .map(|(pending, postings)| {
+                let mut amounts = BTreeMap::<String, Amount>::new();
+                for (account, amount) in postings {
+                    let name = account_name(&account);
+                    let amount = match types[&name].1 {
+                        AccountType::Asset => amount,
+                        AccountType::Flow => -&amount,
+                    };
+                    let total = amounts.remove(&name).unwrap_or_default();
+                    amounts.insert(name, total + &amount);
+                }
+                build_transaction(pending, amounts)
+            })
+            .collect();
+
+        Imported {
+            accounts,
+            transactions,
+            prices: self.prices,
+            balances: Vec::new(),
+            warnings: self.warnings,
+        }
+    }
+}
+
+fn build_transaction(pending: PendingTransaction, amounts: BTreeMap<String, Amount>) -> Transaction {
+    let datetime = pending.time
+        .and_then(|time| DateTime::from_str(&format!("{} {}", pending.date, time)).ok())
+        .unwrap_or_else(|| DateTime::from_str(&pending.date).unwrap());
+    let amounts = amounts.into_iter().collect::<Vec<_>>();
+
+    let mut transaction = Transaction::from_amounts(&pending.name, &pending.notes.join("\n"), datetime, &amounts);
+    for tag in pending.tags {
+        transaction.add_tag(Tag::new(&tag));
+    }
+    for (key, value) in pending.metadata {
+        transaction.set_metadata(&key, &value);
+    }
+    transaction
+}
+
+fn parse_transaction_header(line_number: usize, date: String, rest: &str) -> PendingTransaction {
+    let mut transaction = PendingTransaction {
+        line: line_number,
+        date,
+        time: None,
+        name: String::new(),
+        notes: Vec::new(),
+        tags: BTreeSet::new(),
+        metadata: BTreeMap::new(),
+        postings: Vec::new(),
+        skip: None,
+    };
+
+    let mut rest = rest.trim();
+    if let ("txn" | "*" | "!", after) = split_token(rest) {
+        rest = after;
+    }
+    if let Some((code, after)) = rest.strip_prefix('(').and_then(|rest| rest.split_once(')')) {
+        transaction.metadata.insert(String::from("code"), code.to_owned());
+        rest = after.trim_start();
+    }
+
+    if rest.starts_with('"') {
+        // Beancount: "payee" "narration" #tags ^links
+        let (strings, rest) = quoted_strings(rest);
+        match strings.as_slice() {
+            [narration] => transaction.name = narration.to_owned(),
+            [payee, narration, ..] => {
+                transaction.name = payee.to_owned();
+                transaction.notes.push(narration.to_owned());
+            },
+            [] => {},
+        }
+        let mut links = Vec::new();
+        for word in rest.split_whitespace() {
+            if let Some(tag) = word.strip_prefix('#') {
+                transaction.tags.insert(tag.to_owned());
+            } else if let Some(link) = word.strip_prefix('^') {
+                links.push(link);
+            }
+        }
+        if !links.is_empty() {
+            transaction.metadata.insert(String::from("links"), links.join(", "));
+        }
+    } else {
+        // Ledger: payee | note ; comment
+        let (description, comment) = split_comment(rest);
+        let (payee, note) = match description.split_once('|') {
+            Some((payee, note)) => (payee.trim(), Some(note.trim())),
+            None => (description.trim(), None),
+        };
+        transaction.name = payee.to_owned();
+        transaction.notes.extend(note.map(str::to_owned));
+        if let Some(comment) = comment {
+            parse_comment(&mut transaction, comment);
+        }
+    }
+    transaction
+}
+
+fn parse_transaction_line(transaction: &mut PendingTransaction, line_number: usize, line: &str) {
+    if let Some(comment) = line.strip_prefix(';').or_else(|| line.strip_prefix('#')) {
+        parse_comment(transaction, comment);
+        return
+    }
+    if let Some((key, value)) = beancount_metadata(line) {
+        if key == "time" {
+            transaction.time = Some(value);
+        } else {
+            transaction.metadata.insert(key, value);
+        }
+        return
+    }
+
+    let line = line.strip_prefix(['*', '!']).unwrap_or(line).trim_start();
+    let (line, comment) = split_comment(line);
+    if let Some(comment) = comment {
+        parse_comment(transaction, comment);
+    }
+    let (account, amount) = match line.find("  ").or_else(|| line.find('\t')) {
+        Some(position) => (line[..position].trim(), line[position..].trim()),
+        None => (line.trim(), ""),
+    };
+
+    if account.starts_with(['(', '[']) {
+        transaction.skip.get_or_insert(format!("line {line_number}: virtual postings are not supported"));
+    } else if amount.contains(['@', '{']) {
+        transaction.skip.get_or_insert(format!("line {line_number}: prices and costs in postings are not supported"));
+    } else if amount.is_empty() {
+        transaction.postings.push((account.to_owned(), None));
+    } else {
+        match parse_amount(amount) {
+            Some(amount) => transaction.postings.push((account.to_owned(), Some(amount))),
+            None => {
+                transaction.skip.get_or_insert(format!("line {line_number}: could not read the amount '{amount}'"));
+            }
+        }
+    }
+}
+
+/// Read a comment, which may hold tags (`:tag1:tag2:` or `tag:`), values
+/// (`key: value`), or just text
+fn parse_comment(transaction: &mut PendingTransaction, comment: &str) {
+    let comment = comment.trim();
+    if comment.is_empty() {
+        return
+    }
+
+    if comment.len() > 1 && comment.starts_with(':') && comment.ends_with(':') && !comment.contains(' ') {
+        transaction.tags.extend(comment.split(':').filter(|tag| !tag.is_empty()).map(str::to_owned));
+        return
+    }
+
+    let parts = comment
+        .split(',')
+        .map(|part| {
+            let (name, value) = part.split_once(':')?;
+            let name = name.trim();
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                return None
+            }
+            Some((name, value.trim()))
+        })
+        .collect::<Option<Vec<_>>>();
+
+    let Some(parts) = parts else {
+        transaction.notes.push(comment.to_owned());
+        return
+    };
+    for (name, value) in parts {
+        if value.is_empty() {
+            transaction.tags.insert(name.to_owned());
+        } else if name == "time" {
+            transaction.time = Some(value.to_owned());
+        } else {
+            transaction.metadata.insert(name.to_owned(), value.to_owned());
+        }
+    }
+}
+
+/// A beancount metadata line, like `key: "value"`
+fn beancount_metadata(line: &str) -> Option<(String, String)> {
+    let (key, value) = line.split_once(": ")?;
+    let valid_key = key.starts_with(|c: char| c.is_ascii_lowercase())
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid_key {
+        return None
+    }
+    let value = value.trim();
+    let value = match quoted_strings(value) {
+        (strings, rest) if strings.len() == 1 && rest.trim().is_empty() => strings[0].to_owned(),
+        _ => value.to_owned(),
+    };
+    Some((key.to_owned(), value))
+}
+
+/// Read the quoted strings at the start of the text, and return them with
+/// what comes after
+fn quoted_strings(text: &str) -> (Vec<String>, &str) {
+    let mut strings = Vec::new();
+    let mut rest = text.trim_start();
+    while let Some(inside) = rest.strip_prefix('"') {
+        let mut string = String::new();
+        let mut chars = inside.char_indices();
+        let mut end = None;
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '\\' => string.extend(chars.next().map(|(_, c)| c)),
+                '"' => {
+                    end = Some(index + 1);
+                    break
+                },
+                c => string.push(c),
+            }
+        }
+        let Some(end) = end else {
+            break
+        };
+        strings.push(string);
+        rest = inside[end..].trim_start();
+    }
+    (strings, rest)
+}
+
+/// Read amounts like `-37.45 USD`, `$-37.45`, `-$37.45` or `1,000.00 EUR`
+fn parse_amount(text: &str) -> Option<Amount> {
+    let is_number = |c: char| c.is_ascii_digit() || ".,-+".contains(c);
+    let number = text.chars().filter(|c| is_number(*c)).collect::<String>();
+    let commodity = text.split(|c: char| is_number(c) || c.is_whitespace())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>();
+    let [commodity] = commodity.as_slice() else {
+        return None
+    };
+    if commodity.contains(['(', ')', '*', '/']) || number.is_empty() {
+        return None
+    }
+
+    let number = if number.contains('.') { number.replace(',', "") } else { number };
+    let number = number.strip_prefix('+').unwrap_or(&number);
+    Amount::from_str(&format!("{} {}", number, commodity_code(commodity))).ok()
+}
+
+fn commodity_code(commodity: &str) -> String {
+    match commodity {
+        "$" => String::from("USD"),
+        "€" => String::from("EUR"),
+        "£" => String::from("GBP"),
+        "¥" => String::from("JPY"),
+        commodity => commodity.trim_matches('"').to_owned(),
+    }
+}
+
+/// The name of an account in the database, following the names used by the
+/// other importers: `Expenses:Food` becomes `expense/Food`
+fn account_name(ledger_name: &str) -> String {
+    let (root, rest) = match ledger_name.split_once(':') {
+        Some((root, rest)) => (root, Some(rest)),
+        None => (ledger_name, None),
+    };
+    let root = match root {
+        "Assets" => "asset",
+        "Liabilities" => "liability",
+        "Expenses" => "expense",
+        "Income" => "income",
+        "Equity" => "equity",
+        root => root,
+    };
+    match rest {
+        Some(rest) => format!("{}/{}", root, rest.replace(':', "/")),
+        None => root.to_owned(),
+    }
+}
+
+fn type_from_root(ledger_name: &str) -> AccountType {
+    let root = ledger_name.split(':').next().unwrap().to_lowercase();
+    match root.as_str() {
+        "assets" | "asset" | "liabilities" | "liability" => AccountType::Asset,
+        _ => AccountType::Flow,
+    }
+}
+
+fn split_token(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    match text.split_once(char::is_whitespace) {
+        Some((token, rest)) => (token, rest.trim_start()),
+        None => (text, ""),
+    }
+}
+
+fn split_comment(text: &str) -> (&str, Option<&str>) {
+    match text.split_once(';') {
+        Some((before, comment)) => (before, Some(comment)),
+        None => (text, None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use accounters_lib::data::{account::AccountName, Database};
+
+    fn load(imported: &Imported) -> Database {
+        let mut database = Database::default();
+        for account in imported.accounts.iter() {
+            database.add_account(account.clone()).unwrap();
+        }
+        for transaction in imported.transactions.iter() {
+            database.add_transaction(transaction.clone()).unwrap();
+        }
+        database
+    }
+
+    mod beancount {
+        use super::*;
+
+        const EXAMPLE: &str = r#"option "title" "Example"
+
+2014-01-01 open Assets:US:BofA:Checking  USD
+2014-01-01 open Expenses:Food:Restaurant
+  description: "Eating out"
+
+* Some section
+2014-05-05 * "Cafe Mogador" "Lamb tagine with wine" #trip ^invoice-1
+  receipt: "scan-1.pdf"
+  Assets:US:BofA:Checking  -37.45 USD
+  Expenses:Food:Restaurant
+
+2014-05-06 balance Assets:US:BofA:Checking  -37.45 USD
+2014-07-09 price HOOL  579.18 USD
+
+2014-07-10 * "Exchange"
+  Assets:US:BofA:Checking  -100 USD
+  Assets:US:BofA:Checking  90 EUR @ 1.11 USD
+"#;
+
+        #[test]
+        fn importing() {
+            let imported = parse_journal(EXAMPLE);
+
+            assert_eq!(
+                vec![
+                    Warning::new(1, "unsupported directive 'option'"),
+                    Warning::new(13, "unsupported directive 'balance'"),
+                    Warning::new(16, "transaction skipped: line 18: prices and costs in postings are not supported"),
+                ],
+                imported.warnings
+            );
+            assert_eq!(1, imported.prices.len());
+            assert_eq!(Currency::new("HOOL"), imported.prices[0].commodity);
+
+            let database = load(&imported);
+            assert_eq!(
+                Ok(Amount::from_str("-37.45 USD").unwrap()),
+                database.get_account_balance(&AccountName::new("asset/US/BofA/Checking"), None, None)
+            );
+            assert_eq!(
+                Ok(Amount::from_str("-37.45 USD").unwrap()),
+                database.get_account_balance(&AccountName::new("expense/Food/Restaurant"), None, None)
+            );
+
+            let transaction = &imported.transactions[0];
+            assert_eq!("Cafe Mogador", transaction.get_name());
+            assert_eq!("Lamb tagine with wine", transaction.get_notes());
+            assert!(transaction.get_tags().contains(&Tag::new("trip")));
+            assert_eq!("scan-1.pdf", transaction.get_metadata()["receipt"]);
+            assert_eq!("invoice-1", transaction.get_metadata()["links"]);
+        }
+    }
+
+    mod ledger {
+        use super::*;
+        use accounters_lib::export::ledger::to_journal;
+
+        #[test]
+        fn round_trip() {
+            let mut database = Database::default();
+            database.add_account(Account::new("bank/ICA_Bank", AccountType::Asset)).unwrap();
+            database.add_account(Account::new("food/lunch", AccountType::Flow)).unwrap();
+            let mut transaction = Transaction::example_transaction(
+                "Lunch",
+                "With colleagues",
+                DateTime::from_str("2023-07-13 12:30").unwrap(),
+                &[("bank/ICA_Bank", "-100 SEK, -2 EUR"), ("food/lunch", "-100 SEK, -2 EUR")]
+            );
+            transaction.add_tag(Tag::new("work"));
+            transaction.set_metadata("source", "bank export");
+            database.add_transaction(transaction.clone()).unwrap();
+
+            let imported = parse_journal(&to_journal(&database).unwrap());
+            assert!(imported.warnings.is_empty());
+            assert_eq!(1, imported.transactions.len());
+            let read = &imported.transactions[0];
+            assert_eq!(transaction.generate_id(), read.generate_id());
+            assert_eq!(transaction.get_metadata(), read.get_metadata());
+
+            let read_database = load(&imported);
+            for name in ["bank/ICA_Bank", "food/lunch"] {
+                let name = AccountName::new(name);
+                assert_eq!(
+                    database.get_account_balance(&name, None, None),
+                    read_database.get_account_balance(&name, None, None)
+                );
+            }
+        }
+
+        #[test]
+        fn elided_amounts() {
+            let imported = parse_journal("\
+2023/07/13 * (42) Supermarket | weekly shopping  ; :food:home:
+    Expenses:Food    $12,345.50
+    Liabilities:Card
+");
+            assert!(imported.warnings.is_empty());
+            let database = load(&imported);
+            assert_eq!(
+                Ok(Amount::from_str("-12345.5 USD").unwrap()),
+                database.get_account_balance(&AccountName::new("liability/Card"), None, None)
+            );
+            let transaction = &imported.transactions[0];
+            assert_eq!("Supermarket", transaction.get_name());
+            assert_eq!("weekly shopping", transaction.get_notes());
+            assert_eq!("42", transaction.get_metadata()["code"]);
+            assert_eq!(2, transaction.get_tags().len());
+        }
+
+        #[test]
+        fn same_mapped_name() {
+            let imported = parse_journal("\
+account expense:Food  ; type:A
+
+2023/07/13 Supermarket
+    Expenses:Food    100 SEK
+    Assets:Bank
+
+2023/07/14 Bakery
+    expense:Food    20 SEK
+    asset:Bank
+");
+            assert_eq!(2, imported.accounts.len());
+            assert_eq!(2, imported.warnings.len());
+            assert_eq!(
+                "line 1: expense:Food is imported as expense/Food, like Expenses:Food, so it has the type of Expenses:Food",
+                imported.warnings[1].to_string()
+            );
+            let database = load(&imported);
+            assert_eq!(
+                Ok(Amount::from_str("-120 SEK").unwrap()),
+                database.get_account_balance(&AccountName::new("asset/Bank"), None, None)
+            );
+        }
+    }
+}
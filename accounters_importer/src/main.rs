@@ -1,44 +1,247 @@
-mod importer;
+mod camt;
+mod csv;
+mod imported;
+mod ledger;
+mod markup;
+mod merge;
+mod mt940;
+mod ofx;
+mod profile;
+mod qif;
 
 use accounters_lib::data::{
     Database,
-    account::{
-        Account,
-        AccountType
-    }
+    account::Account,
+    money::Currency,
+    transaction::Transaction
 };
 
+use imported::{Imported, StatementAccounts};
+
 use std::collections::HashSet;
+use std::io::Write;
+use std::path::Path;
 
+/// Import a file into a new database, or into an existing one
+///
+/// Usage: `accounters_importer [input] [output] [--account <name>]
+/// [--uncategorized <name>] [--currency <code>] [--profile <name>]
+/// [--profiles <path>] [--merge [--yes]]`. The format of the input is chosen by its
+/// extension: beancount and ledger journals, OFX, camt.053 (`.xml`) and
+/// MT940 (`.sta`) bank statements, QIF files, or CSV files.
+///
+/// CSV files are read with an import profile, `expenses_app` by default.
+/// More profiles can be declared in a JSON file, which is
+/// `files/import_profiles.json` if it exists.
+///
+/// The options give the accounts of bank statements: the one of the
+/// statement, and the one for the other side of its transactions. The
+/// currency is needed by the formats that do not tell it, like QIF.
+///
+/// With `--merge` the transactions are added to the output database instead
+/// of replacing it, leaving out the ones it already has, as told by
+/// [`merge`]. Each new account must be confirmed, unless `--yes` is given,
+/// and the transactions of the refused ones are not imported.
+///
+/// The rules of the database are applied to the imported transactions.
 fn main() {
-    let database = import_database("files/blue_trns.csv");
-    database.save_to_file("files/blue_database.json");
-}
+    let mut positional = Vec::new();
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--merge" => {
+                options.merge = true;
+                continue
+            },
+            "--yes" => {
+                options.yes = true;
+                continue
+            },
+            _ => (),
+        }
+        let value = match arg.as_str() {
+            "--account" | "--uncategorized" | "--currency" | "--profile" | "--profiles" => args.next().unwrap_or_else(|| {
+                eprintln!("{arg} needs a value");
+                std::process::exit(1);
+            }),
+            _ => {
+                positional.push(arg);
+                continue
+            }
+        };
+        match arg.as_str() {
+            "--account" => options.statement_accounts.asset = Some(value),
+            "--currency" => options.statement_accounts.currency = Some(Currency::new(&value)),
+            "--profile" => options.profile = Some(value),
+            "--profiles" => options.profiles = Some(value),
+            _ => options.statement_accounts.uncategorized = value,
+        }
+    }
+    let mut positional = positional.into_iter();
+    let input = positional.next().unwrap_or_else(|| String::from("files/blue_trns.csv"));
+    let output = positional.next().unwrap_or_else(|| String::from("files/blue_database.json"));
 
-fn import_database(path: &str) -> Database {
-    let mut database = Database::default();
+    let mut imported = match read_file(&input, &options) {
+        Ok(imported) => imported,
+        Err(error) => {
+            eprintln!("Could not read {input}: {error}");
+            std::process::exit(1);
+        }
+    };
+    for warning in imported.warnings.iter() {
+        eprintln!("{input}: {warning}");
+    }
+    // The database has nowhere to keep exchange rates yet
+    for price in imported.prices.iter() {
+        eprintln!(
+            "{input}: price of {} on {} ({}) not imported",
+            price.commodity.0,
+            price.datetime.get_date_string(),
+            price.price
+        );
+    }
 
-    let transactions = importer::import_transactions(path).unwrap();
+    let balances = std::mem::take(&mut imported.balances);
+    let database = if options.merge {
+        let mut database = match Path::new(&output).exists() {
+            true => Database::read_from_file(&output).unwrap_or_else(|error| {
+                eprintln!("Could not load the database: {error}");
+                std::process::exit(1);
+            }),
+            false => Database::default(),
+        };
+        merge_into(&mut database, imported, options.yes);
+        database
+    } else {
+        let mut database = Database::default();
+        add_to_database(&mut database, imported.accounts, imported.transactions);
+        database
+    };
+    for balance in balances {
+        if let Err(warning) = balance.verify(&database) {
+            eprintln!("{input}: {warning}");
+        }
+    }
+    if let Err(error) = database.save_to_file(&output) {
+        eprintln!("Could not save the database: {error}");
+        std::process::exit(1);
+    }
+}
 
-    let mut account_names = HashSet::new();
+#[derive(Default)]
+struct Options {
+    statement_accounts: StatementAccounts,
+    /// The import profile of CSV files
+    profile: Option<String>,
+    /// The file with more import profiles
+    profiles: Option<String>,
+    /// Whether to add to the output database instead of replacing it
+    merge: bool,
+    /// Whether to create new accounts without asking
+    yes: bool,
+}
 
-    for transaction in transactions.iter() {
-        for account_name in transaction.get_associated_accounts() {
-            account_names.insert(account_name);
+fn read_file(path: &str, options: &Options) -> Result<Imported, String> {
+    let statement_accounts = &options.statement_accounts;
+    let extension = Path::new(path).extension().and_then(|extension| extension.to_str());
+    match extension.map(str::to_lowercase).as_deref() {
+        Some("beancount" | "bean" | "ledger" | "journal" | "hledger") => {
+            ledger::import_journal(path).map_err(|error| error.to_string())
+        },
+        Some("ofx" | "qfx") => ofx::import_ofx(path, statement_accounts),
+        Some("xml" | "camt" | "053") => camt::import_camt(path, statement_accounts),
+        Some("sta" | "mt940" | "940") => mt940::import_mt940(path, statement_accounts),
+        Some("qif") => qif::import_qif(path, statement_accounts),
+        _ => {
+            let default_profiles = Path::new(DEFAULT_PROFILES).exists().then_some(DEFAULT_PROFILES);
+            let profiles = profile::load_profiles(options.profiles.as_deref().or(default_profiles))?;
+            let name = options.profile.as_deref().unwrap_or("expenses_app");
+            let profile = profiles.get(name).ok_or_else(|| format!("there is no import profile {name}"))?;
+            csv::import_csv(path, profile)
         }
     }
+}
 
-    for account_name in account_names {
-        let account_class = match account_name.as_ref().split_once('/').unwrap().0 {
-            "asset" => AccountType::Asset,
-            _ => AccountType::Flow
-        };
-        database.add_account(Account::new(account_name.as_ref(), account_class)).unwrap();
+/// The file with the import profiles, if no other is given
+const DEFAULT_PROFILES: &str = "files/import_profiles.json";
+
+/// Add the transactions that the database does not have yet
+fn merge_into(database: &mut Database, imported: Imported, yes: bool) {
+    let mut refused = HashSet::new();
+    let mut accounts = Vec::new();
+    for account in merge::new_accounts(database, imported.accounts) {
+        if yes || confirm(&format!("Create the account {}?", account.get_name().as_ref())) {
+            accounts.push(account);
+        } else {
+            refused.insert(account.get_name().clone());
+        }
     }
 
-    for transaction in transactions {
-        database.add_transaction(transaction).unwrap();
+    let merge = merge::merge(database, imported.transactions);
+    for (transaction, duplicate) in merge.duplicates.iter() {
+        let existing = database.get_transaction(&duplicate.existing()).map(describe).unwrap_or_default();
+        match duplicate {
+            merge::Duplicate::SourceId(_) => println!("{} already imported as {existing}", describe(transaction)),
+            merge::Duplicate::Similar(_) => println!("{} looks like {existing}, skipped", describe(transaction)),
+        }
+    }
+    let (transactions, skipped): (Vec<_>, Vec<_>) = merge.new
+        .into_iter()
+        .partition(|transaction| !transaction.get_associated_accounts().any(|account| refused.contains(account)));
+    for transaction in skipped {
+        eprintln!("Transaction {} skipped: its account was not created", describe(&transaction));
     }
 
-    database
+    println!("{} new transactions, {} already in the database", transactions.len(), merge.duplicates.len());
+    add_to_database(database, accounts, transactions);
+}
+
+/// Ask a yes or no question, where no is the default
+fn confirm(question: &str) -> bool {
+    print!("{question} [y/N] ");
+    std::io::stdout().flush().unwrap();
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).unwrap();
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+fn describe(transaction: &Transaction) -> String {
+    format!("{} on {}", transaction.get_name(), transaction.get_datetime().get_date_string())
+}
+
+fn add_to_database(database: &mut Database, accounts: Vec<Account>, transactions: Vec<Transaction>) {
+    // The whole import is a single step, so it can be undone at once
+    let imported = database.grouped(|database| {
+        for account in accounts {
+            database.add_account(account)?;
+        }
+
+        let mut added = Vec::new();
+        for transaction in transactions {
+            let description = describe(&transaction);
+            let id = transaction.generate_id();
+            match database.add_transaction(transaction) {
+                Ok(()) => added.push(id),
+                Err(error) => eprintln!("Transaction {description} skipped: {error}"),
+            }
+        }
+
+        match database.apply_rules(&added) {
+            Ok(applied) => {
+                if !applied.changed.is_empty() {
+                    println!("{} transactions changed by the rules", applied.changed.len());
+                }
+                for transaction in applied.duplicates.iter().filter_map(|id| database.get_transaction(id)) {
+                    eprintln!("Transaction {} left as it was: the rules make it a copy of another one", describe(transaction));
+                }
+            },
+            Err(error) => eprintln!("The rules could not be applied: {error}"),
+        }
+        Ok::<(), accounters_lib::data::Error>(())
+    });
+    if let Err(error) = imported {
+        eprintln!("Could not import: {error}");
+        std::process::exit(1);
+    }
 }
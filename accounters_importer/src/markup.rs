@@ -0,0 +1,219 @@
+//! A forgiving reader of XML and of the SGML used by OFX 1
+//!
+//! It only builds a tree of elements with their attributes and text, which
+//! is all the statement importers need. Namespace prefixes are dropped,
+//! and elements with a value and no closing tag, as in SGML, are closed by
+//! the next tag.
+
+use std::collections::BTreeMap;
+
+/// An element of the file, with the line where it starts
+#[derive(Debug)]
+pub struct Element {
+    pub name: String,
+    pub line: usize,
+    pub attributes: BTreeMap<String, String>,
+    pub value: Option<String>,
+    pub children: Vec<Element>,
+}
+
+impl Element {
+    fn new(name: &str, line: usize) -> Self {
+        Element {
+            name: local_name(name).to_owned(),
+            line,
+            attributes: BTreeMap::new(),
+            value: None,
+            children: Vec::new(),
+        }
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    /// Every child with that name, in order
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    /// The element found by following the path of names from this one
+    pub fn path(&self, names: &[&str]) -> Option<&Element> {
+        names.iter().try_fold(self, |element, name| element.child(name))
+    }
+
+    /// The value of a child, if it has one that is not empty
+    pub fn value_of(&self, name: &str) -> Option<&str> {
+        self.path(&[name]).and_then(Element::text)
+    }
+
+    /// The value of the element, if it is not empty
+    pub fn text(&self) -> Option<&str> {
+        self.value.as_deref().filter(|value| !value.is_empty())
+    }
+
+    /// Every element with that name inside this one, in order, without
+    /// looking inside the ones found
+    pub fn descendants(&self, name: &str) -> Vec<&Element> {
+        let mut found = Vec::new();
+        for child in self.children.iter() {
+            if child.name == name {
+                found.push(child);
+            } else {
+                found.extend(child.descendants(name));
+            }
+        }
+        found
+    }
+}
+
+/// Build the tree of elements of the text, whose first line has the given
+/// number
+///
+/// The result is an element with no name that has the top level elements
+/// as children. A closing tag closes every element opened after the one it
+/// names.
+pub fn parse_markup(text: &str, first_line: usize) -> Result<Element, String> {
+    let mut line = first_line;
+    let mut stack = vec![Element::new("", 0)];
+    let mut rest = text;
+
+    while let Some(open) = rest.find('<') {
+        let content = rest[..open].trim();
+        line += rest[..open].matches('\n').count();
+        rest = &rest[open..];
+        if !content.is_empty() {
+            set_value(&mut stack, decode_entities(content));
+        }
+
+        if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+            let end = cdata.find("]]>").ok_or_else(|| format!("line {line}: unfinished CDATA section"))?;
+            set_value(&mut stack, cdata[..end].trim().to_owned());
+            line += cdata[..end].matches('\n').count();
+            rest = &cdata[end + 3..];
+            continue
+        }
+
+        // Comments, declarations and processing instructions are skipped
+        let (end_marker, skip) = if rest.starts_with("<!--") {
+            ("-->", true)
+        } else {
+            (">", rest.starts_with("<?") || rest.starts_with("<!"))
+        };
+        let Some(end) = rest.find(end_marker) else {
+            return Err(format!("line {line}: unfinished tag"))
+        };
+        let tag = &rest[1..end];
+        let tag_line = line;
+        line += tag.matches('\n').count();
+        rest = &rest[end + end_marker.len()..];
+        if skip {
+            continue
+        }
+
+        // Elements with a value and no closing tag end here
+        let closing = tag.strip_prefix('/').map(|name| local_name(name.trim()));
+        let top = stack.last().unwrap();
+        if stack.len() > 1 && top.value.is_some() && closing != Some(top.name.as_str()) {
+            close(&mut stack);
+        }
+
+        if let Some(name) = closing {
+            if let Some(position) = stack.iter().rposition(|element| element.name == name) {
+                while stack.len() > position {
+                    close(&mut stack);
+                }
+            }
+        } else {
+            let (tag, empty) = match tag.strip_suffix('/') {
+                Some(tag) => (tag, true),
+                None => (tag, false),
+            };
+            let (name, attributes) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+            let mut element = Element::new(name, tag_line);
+            element.attributes = parse_attributes(attributes);
+            stack.push(element);
+            if empty {
+                close(&mut stack);
+            }
+        }
+    }
+
+    while stack.len() > 1 {
+        close(&mut stack);
+    }
+    Ok(stack.pop().unwrap())
+}
+
+fn set_value(stack: &mut [Element], value: String) {
+    if let [_, .., top] = stack {
+        if top.children.is_empty() && top.value.is_none() {
+            top.value = Some(value);
+        }
+    }
+}
+
+fn close(stack: &mut Vec<Element>) {
+    let element = stack.pop().unwrap();
+    stack.last_mut().unwrap().children.push(element);
+}
+
+/// The name without its namespace prefix
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+/// Read attributes like `Ccy="EUR" xmlns:a='...'`, ignoring the ones
+/// without a quoted value
+fn parse_attributes(text: &str) -> BTreeMap<String, String> {
+    let mut attributes = BTreeMap::new();
+    let mut rest = text;
+    while let Some((name, after)) = rest.split_once('=') {
+        let after = after.trim_start();
+        let Some(quote) = after.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            break
+        };
+        let Some(end) = after[1..].find(quote) else {
+            break
+        };
+        attributes.insert(local_name(name.trim()).to_owned(), decode_entities(&after[1..end + 1]));
+        rest = &after[end + 2..];
+    }
+    attributes
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    mod parsing {
+        use super::*;
+
+        #[test]
+        fn xml_and_sgml() {
+            let text = "<?xml version=\"1.0\"?>\n<a:Doc xmlns:a=\"urn:x\">\n  <Amt Ccy='EUR'>1.50</Amt>\n  <Empty/>\n  <Note><![CDATA[x < y]]></Note>\n</a:Doc>";
+            let root = parse_markup(text, 1).unwrap();
+            let document = root.child("Doc").unwrap();
+            assert_eq!(document.line, 2);
+            let amount = document.child("Amt").unwrap();
+            assert_eq!((amount.text(), amount.line), (Some("1.50"), 3));
+            assert_eq!(amount.attributes["Ccy"], "EUR");
+            assert!(document.child("Empty").is_some());
+            assert_eq!(document.value_of("Note"), Some("x < y"));
+
+            let root = parse_markup("<A><B>1<C>two &amp; three</A>", 1).unwrap();
+            let a = root.child("A").unwrap();
+            assert_eq!(a.value_of("B"), Some("1"));
+            assert_eq!(a.value_of("C"), Some("two & three"));
+        }
+    }
+}
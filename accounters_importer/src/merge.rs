@@ -0,0 +1,182 @@
+//! Addition of imported transactions to a database that has some already
+//!
+//! An imported transaction is already in the database when a transaction
+//! there shares one of its accounts and either:
+//!
+//! - has the same `source_id`, the reference given by the bank, or
+//! - moves the same amount through that account within a few days, with a
+//!   similar name. Names are similar when the words of one are in the other,
+//!   so `ICA` matches `ICA MAXI STOCKHOLM`, and an empty name matches any.
+//!
+//! Each transaction of the database can only be matched once, so two equal
+//! payments on the same day are both kept if only one was there.
+
+use accounters_lib::data::{
+    account::{Account, AccountName},
+    transaction::{Transaction, TransactionId},
+    Database,
+};
+
+use std::collections::{BTreeMap, HashSet};
+
+/// How far apart the dates of the same payment can be, as the bank and the
+/// person entering it by hand may not agree
+const MAX_DAYS_APART: i64 = 3;
+
+/// Why an imported transaction is considered to be in the database
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Duplicate {
+    SourceId(TransactionId),
+    Similar(TransactionId),
+}
+
+impl Duplicate {
+    /// The transaction of the database that was matched
+    pub fn existing(&self) -> TransactionId {
+        match self {
+            Duplicate::SourceId(id) | Duplicate::Similar(id) => *id,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Merge {
+    /// The transactions that are not in the database
+    pub new: Vec<Transaction>,
+    /// The ones that are, with the transaction they match
+    pub duplicates: Vec<(Transaction, Duplicate)>,
+}
+
+/// Split the imported transactions into the new ones and the ones that are
+/// already in the database
+pub fn merge(database: &Database, transactions: Vec<Transaction>) -> Merge {
+    let mut by_account: BTreeMap<&AccountName, Vec<(&TransactionId, &Transaction)>> = BTreeMap::new();
+    for id in database.get_transaction_ids() {
+        let Some(transaction) = database.get_transaction(id) else {
+            continue
+        };
+        for account in transaction.get_associated_accounts() {
+            by_account.entry(account).or_default().push((id, transaction));
+        }
+    }
+
+    let mut matched = HashSet::new();
+    let mut merge = Merge::default();
+    for transaction in transactions {
+        let candidates = || transaction
+            .get_associated_accounts()
+            .filter_map(|account| Some((account, by_account.get(account)?)))
+            .flat_map(|(account, existing)| existing.iter().map(move |(id, other)| (account, **id, *other)))
+            .filter(|(_, id, _)| !matched.contains(id));
+
+        let source_id = transaction.get_metadata().get("source_id");
+        let by_source_id = candidates()
+            .find(|(_, _, other)| source_id.is_some() && other.get_metadata().get("source_id") == source_id)
+            .map(|(_, id, _)| Duplicate::SourceId(id));
+        let duplicate = by_source_id.or_else(|| {
+            candidates()
+                // Different references are different payments, however
+                // alike they look
+                .filter(|(_, _, other)| {
+                    let other_source_id = other.get_metadata().get("source_id");
+                    source_id.is_none() || other_source_id.is_none() || other_source_id == source_id
+                })
+                .find(|(account, _, other)| is_similar(&transaction, other, account))
+                .map(|(_, id, _)| Duplicate::Similar(id))
+        });
+
+        match duplicate {
+            Some(duplicate) => {
+                matched.insert(duplicate.existing());
+                merge.duplicates.push((transaction, duplicate));
+            },
+            None => merge.new.push(transaction),
+        }
+    }
+    merge
+}
+
+/// The accounts that the database does not have yet
+pub fn new_accounts(database: &Database, accounts: Vec<Account>) -> Vec<Account> {
+    accounts.into_iter().filter(|account| database.get_account(account.get_name()).is_none()).collect()
+}
+
+/// Whether two transactions look like the same payment to an account
+fn is_similar(imported: &Transaction, existing: &Transaction, account: &AccountName) -> bool {
+    let same_amount = match (imported.get_amount(account), existing.get_amount(account)) {
+        (Ok(imported), Ok(existing)) => (imported.clone() - existing).is_zero(),
+        _ => false,
+    };
+    let days_apart = (*imported.get_datetime().get_date() - *existing.get_datetime().get_date()).whole_days();
+    same_amount && days_apart.abs() <= MAX_DAYS_APART && similar_names(imported.get_name(), existing.get_name())
+}
+
+fn similar_names(first: &str, second: &str) -> bool {
+    let words = |name: &str| name
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<HashSet<_>>();
+    let (first, second) = (words(first), words(second));
+    first.is_subset(&second) || second.is_subset(&first)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use accounters_lib::data::{account::AccountType, datetime::DateTime};
+    use std::str::FromStr;
+
+    mod merging {
+        use super::*;
+
+        fn transaction(name: &str, date: &str, amount: &str, source_id: Option<&str>) -> Transaction {
+            let mut transaction = Transaction::example_transaction(
+                name,
+                "",
+                DateTime::from_str(date).unwrap(),
+                &[("asset/bank", amount), ("expense/food", amount)],
+            );
+            if let Some(source_id) = source_id {
+                transaction.set_metadata("source_id", source_id);
+            }
+            transaction
+        }
+
+        #[test]
+        fn duplicates() {
+            let mut database = Database::default();
+            database.add_account(Account::new("asset/bank", AccountType::Asset)).unwrap();
+            database.add_account(Account::new("expense/food", AccountType::Flow)).unwrap();
+            let imported = transaction("ICA", "2024-03-01", "-100 SEK", Some("A1"));
+            let hand_entered = transaction("Ica Maxi", "2024-03-04", "-250 SEK", None);
+            let coffee = transaction("Coffee", "2024-03-05", "-30 SEK", None);
+            for existing in [&imported, &hand_entered, &coffee] {
+                database.add_transaction(existing.clone()).unwrap();
+            }
+
+            let merge = merge(&database, vec![
+                // The name was changed after the import
+                transaction("Groceries", "2024-03-01", "-100 SEK", Some("A1")),
+                transaction("ICA MAXI STOCKHOLM", "2024-03-02", "-250 SEK", Some("A2")),
+                transaction("Coffee", "2024-03-05", "-30 SEK", Some("A3")),
+                transaction("Coffee", "2024-03-05", "-30 SEK", Some("A4")),
+                transaction("Coffee", "2024-03-15", "-30 SEK", Some("A5")),
+                transaction("Bakery", "2024-03-04", "-250 SEK", Some("A6")),
+                transaction("ICA", "2024-03-01", "-100 SEK", Some("A7")),
+            ]);
+            assert_eq!(merge.duplicates.iter().map(|(_, duplicate)| *duplicate).collect::<Vec<_>>(), vec![
+                Duplicate::SourceId(imported.generate_id()),
+                Duplicate::Similar(hand_entered.generate_id()),
+                Duplicate::Similar(coffee.generate_id()),
+            ]);
+            let new = merge.new.iter().map(|transaction| &transaction.get_metadata()["source_id"]).collect::<Vec<_>>();
+            assert_eq!(new, vec!["A4", "A5", "A6", "A7"]);
+
+            let accounts = vec![Account::new("asset/bank", AccountType::Asset), Account::new("expense/fika", AccountType::Flow)];
+            let new_accounts = new_accounts(&database, accounts);
+            assert_eq!(new_accounts.len(), 1);
+            assert_eq!(new_accounts[0].get_name(), &AccountName::new("expense/fika"));
+        }
+    }
+}
@@ -0,0 +1,370 @@
+//! Import of SWIFT MT940 bank statements
+//!
+//! A statement is a list of fields like `:61:` that start at the beginning
+//! of a line and can continue in the next ones. Each `:61:` statement line
+//! becomes a transaction between the asset account and the uncategorized
+//! one, with the information of the `:86:` field after it.
+//!
+//! - The transaction is dated on the entry date if there is one, and on the
+//!   value date otherwise. The value date is kept in the `value_date`
+//!   metadata, and the reference of the bank in `source_id`.
+//! - `:86:` fields are read as the `?20` subfields used by German banks,
+//!   as `/NAME/.../REMI/...` codes, or as free text for the notes.
+//! - The opening (`:60F:`) and closing (`:62F:`) balances are checked at the
+//!   end of their day.
+
+use accounters_lib::data::{
+    account::AccountName,
+    datetime::DateTime,
+    money::{Amount, Currency},
+    transaction::Transaction,
+};
+
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+use crate::imported::{read_text, BalanceCheck, Imported, StatementAccounts, Warning};
+
+pub fn import_mt940(path: &str, accounts: &StatementAccounts) -> Result<Imported, String> {
+    let text = read_text(path).map_err(|error| error.to_string())?;
+    parse_mt940(&text, accounts)
+}
+
+pub fn parse_mt940(text: &str, accounts: &StatementAccounts) -> Result<Imported, String> {
+    let fields = split_fields(text);
+    if !fields.iter().any(|field| field.tag == "61" || field.tag.starts_with("60")) {
+        return Err(String::from("it is not an MT940 statement"))
+    }
+
+    let mut imported = Imported::default();
+    let mut assets = BTreeSet::new();
+    let mut asset = accounts.asset_for("unknown");
+    // Statement lines have no currency, it is the one of the opening balance
+    let mut currency = accounts.currency.clone();
+    let mut pending: Option<(usize, Result<Entry, String>)> = None;
+
+    for field in fields.iter() {
+        // The information of a statement line is in the field right after
+        if let Some((line, entry)) = pending.take() {
+            let information = (field.tag == "86").then_some(field.content.as_str());
+            match entry {
+                Ok(entry) => imported.transactions.push(entry.into_transaction(&asset, information, accounts)),
+                Err(message) => imported.warnings.push(Warning::new(line, format!("entry skipped: {message}"))),
+            }
+        }
+
+        match field.tag.as_str() {
+            "25" => {
+                asset = accounts.asset_for(field.content.trim());
+                assets.insert(asset.clone());
+            },
+            "61" => {
+                let entry = match &currency {
+                    Some(currency) => parse_entry(&field.content, currency),
+                    None => Err(String::from("there is no opening balance with the currency")),
+                };
+                pending = Some((field.line, entry));
+            },
+            "60F" | "60M" | "62F" | "62M" => match parse_balance(&field.content) {
+                Some((date, balance_currency, amount)) => {
+                    if field.tag.starts_with("60") {
+                        currency = Some(balance_currency);
+                    }
+                    imported.balances.push(BalanceCheck {
+                        line: field.line,
+                        account: AccountName::new(&asset),
+                        date,
+                        amount,
+                    });
+                },
+                None => imported.warnings.push(Warning::new(field.line, "balance not checked: it could not be read")),
+            },
+            _ => (),
+        }
+    }
+    if let Some((line, entry)) = pending {
+        match entry {
+            Ok(entry) => imported.transactions.push(entry.into_transaction(&asset, None, accounts)),
+            Err(message) => imported.warnings.push(Warning::new(line, format!("entry skipped: {message}"))),
+        }
+    }
+
+    // Statements without a :25: field go to the asset account given
+    if !imported.transactions.is_empty() && assets.is_empty() {
+        assets.insert(asset);
+    }
+    imported.accounts = accounts.accounts(assets.iter());
+    Ok(imported)
+}
+
+struct Field {
+    line: usize,
+    tag: String,
+    content: String,
+}
+
+/// The fields of the text, without the header blocks of SWIFT messages
+fn split_fields(text: &str) -> Vec<Field> {
+    let mut fields: Vec<Field> = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = match line.find("{4:") {
+            Some(start) => &line[start + 3..],
+            None => line,
+        };
+        let line = line.trim_end();
+        let tag = line
+            .strip_prefix(':')
+            .and_then(|rest| rest.split_once(':'))
+            .filter(|(tag, _)| (2..=3).contains(&tag.len()) && tag.starts_with(|c: char| c.is_ascii_digit()));
+        if let Some((tag, content)) = tag {
+            fields.push(Field { line: index + 1, tag: tag.to_owned(), content: content.to_owned() });
+        } else if line == "-" || line.starts_with("-}") || line.starts_with('{') {
+            continue
+        } else if let Some(field) = fields.last_mut() {
+            field.content.push('\n');
+            field.content.push_str(line);
+        }
+    }
+    fields
+}
+
+struct Entry {
+    date: DateTime,
+    value_date: DateTime,
+    amount: Amount,
+    reference: Option<String>,
+    details: Option<String>,
+}
+
+impl Entry {
+    fn into_transaction(self, asset: &str, information: Option<&str>, accounts: &StatementAccounts) -> Transaction {
+        let information = information.map(parse_information).unwrap_or_default();
+        let (name, notes) = match (information.name, information.remittance) {
+            (Some(name), remittance) => (name, remittance.unwrap_or_default()),
+            (None, Some(remittance)) => (remittance, String::new()),
+            (None, None) => (self.details.unwrap_or_default(), String::new()),
+        };
+
+        let mut transaction = accounts.transaction(asset, &name, &notes, self.date, self.amount);
+        transaction.set_metadata("value_date", &self.value_date.get_date_string());
+        if let Some(reference) = self.reference {
+            transaction.set_metadata("source_id", &reference);
+        }
+        transaction
+    }
+}
+
+/// Read a statement line like `2401020102D12,50NTRFNONREF//B2401020001`
+fn parse_entry(content: &str, currency: &Currency) -> Result<Entry, String> {
+    let (first_line, details) = match content.split_once('\n') {
+        Some((first_line, details)) => (first_line, Some(details.trim().to_owned())),
+        None => (content, None),
+    };
+    let value_date = first_line.get(..6).and_then(parse_date).ok_or("invalid value date")?;
+    let mut rest = &first_line[6..];
+
+    let entry_date = rest
+        .get(..4)
+        .filter(|digits| digits.chars().all(|c| c.is_ascii_digit()))
+        .map(|digits| {
+            rest = &rest[4..];
+            entry_date(&value_date, digits)
+        })
+        .transpose()?;
+
+    let (negative, after_mark) = if let Some(after) = rest.strip_prefix("RC") {
+        (true, after)
+    } else if let Some(after) = rest.strip_prefix("RD") {
+        (false, after)
+    } else if let Some(after) = rest.strip_prefix('C') {
+        (false, after)
+    } else if let Some(after) = rest.strip_prefix('D') {
+        (true, after)
+    } else {
+        return Err(String::from("it is neither a credit nor a debit"))
+    };
+    // The third letter of the currency may come before the amount
+    let after_mark = after_mark.strip_prefix(|c: char| c.is_ascii_alphabetic()).unwrap_or(after_mark);
+    let number_length = after_mark.find(|c: char| !c.is_ascii_digit() && c != ',').unwrap_or(after_mark.len());
+    let number = &after_mark[..number_length];
+    let sign = if negative { "-" } else { "" };
+    let amount = Amount::from_str(&format!("{sign}{} {}", number.trim_end_matches(','), currency.0))
+        .map_err(|_| format!("invalid amount '{number}'"))?;
+
+    // A type code of four characters, then the references
+    let references = after_mark[number_length..].get(4..).unwrap_or_default();
+    let (customer, bank) = references.split_once("//").unwrap_or((references, ""));
+    let reference = [bank.trim(), customer.trim()]
+        .into_iter()
+        .find(|reference| !reference.is_empty() && *reference != "NONREF")
+        .map(str::to_owned);
+
+    Ok(Entry { date: entry_date.unwrap_or(value_date), value_date, amount, reference, details })
+}
+
+/// The entry date from its month and day, in the year closest to the value
+/// date
+fn entry_date(value_date: &DateTime, digits: &str) -> Result<DateTime, String> {
+    let year = value_date.get_date().year();
+    let month = digits[..2].parse::<i32>().map_err(|_| "invalid entry date")?;
+    let value_month = value_date.get_date().month() as i32;
+    let year = match month - value_month {
+        difference if difference > 6 => year - 1,
+        difference if difference < -6 => year + 1,
+        _ => year,
+    };
+    DateTime::from_str(&format!("{year}-{}-{}", &digits[..2], &digits[2..])).map_err(|_| String::from("invalid entry date"))
+}
+
+/// Read a date like `240102`
+fn parse_date(digits: &str) -> Option<DateTime> {
+    if !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None
+    }
+    DateTime::from_str(&format!("20{}-{}-{}", &digits[..2], &digits[2..4], &digits[4..6])).ok()
+}
+
+/// Read a balance like `C240131EUR1000,00`, with its currency apart since
+/// a zero amount has none
+fn parse_balance(content: &str) -> Option<(DateTime, Currency, Amount)> {
+    let content = content.trim();
+    let sign = match content.get(..1)? {
+        "C" => "",
+        "D" => "-",
+        _ => return None,
+    };
+    let date = parse_date(content.get(1..7)?)?;
+    let currency = content.get(7..10)?;
+    let number = content.get(10..)?.trim_end_matches(',');
+    let amount = Amount::from_str(&format!("{sign}{number} {currency}")).ok()?;
+    Some((date, Currency::new(currency), amount))
+}
+
+#[derive(Default)]
+struct Information {
+    name: Option<String>,
+    remittance: Option<String>,
+}
+
+/// Read the information of an `:86:` field
+fn parse_information(content: &str) -> Information {
+    let content = content.replace('\n', "");
+    let non_empty = |parts: Vec<&str>| Some(parts.join(" ")).filter(|text| !text.trim().is_empty());
+
+    if content.get(3..4) == Some("?") {
+        // Subfields like ?20 for the remittance and ?32 for the name
+        let subfields = content[3..]
+            .split('?')
+            .filter_map(|subfield| Some((subfield.get(..2)?, subfield.get(2..)?.trim())))
+            .collect::<Vec<_>>();
+        let select = |codes: &[&str]| {
+            subfields.iter().filter(|(code, _)| codes.contains(code)).map(|(_, text)| *text).collect::<Vec<_>>()
+        };
+        let remittance_codes = ["20", "21", "22", "23", "24", "25", "26", "27", "28", "29", "60", "61", "62", "63"];
+        return Information {
+            name: non_empty(select(&["32", "33"])),
+            remittance: non_empty(select(&remittance_codes)).or_else(|| non_empty(select(&["00"]))),
+        };
+    }
+
+    if content.starts_with('/') {
+        // Codes like /NAME/Shop/REMI/Invoice 12
+        let parts = content.split('/').skip(1).collect::<Vec<_>>();
+        let value_of = |code: &str| {
+            parts.iter().position(|part| *part == code).and_then(|index| parts.get(index + 1)).map(|text| text.trim())
+        };
+        return Information {
+            name: value_of("NAME").filter(|name| !name.is_empty()).map(str::to_owned),
+            remittance: value_of("REMI").or_else(|| value_of("USTD")).filter(|text| !text.is_empty()).map(str::to_owned),
+        };
+    }
+
+    Information { name: None, remittance: non_empty(vec![content.trim()]) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use accounters_lib::data::Database;
+
+    mod statement {
+        use super::*;
+
+        const EXAMPLE: &str = "{1:F01BANKDEFFXXXX0000000000}{2:I940BANKDEFFXXXXN}{4:
+:20:STARTUMS
+:25:ES7921000813610123456789
+:28C:00001/001
+:60F:C231229EUR1000,00
+:61:2312291230D12,50NTRFNONREF//B2312290001
+:86:166?00SEPA-UEBERWEISUNG?20Invoice 42?21December?32Hardware
+?33Store GmbH
+:61:240102C250,NMSCREF77
+:86:/NAME/Client SL/REMI/Factura 7/
+:61:240105D9,99NDDTNONREF
+:86:Monthly subscription
+:61:24XX05D1,NMSCNONREF
+:62F:C240105EUR1227,51
+-}
+";
+
+        #[test]
+        fn importing() {
+            let accounts = StatementAccounts {
+                asset: None,
+                uncategorized: String::from("expense/to_sort"),
+                currency: None,
+            };
+            let imported = parse_mt940(EXAMPLE, &accounts).unwrap();
+            assert_eq!(imported.warnings, vec![Warning::new(13, "entry skipped: invalid value date")]);
+
+            let [store, client, subscription] = imported.transactions.as_slice() else {
+                panic!("there should be three transactions")
+            };
+            assert_eq!(store.get_name(), "Hardware Store GmbH");
+            assert_eq!(store.get_notes(), "Invoice 42 December");
+            assert_eq!(store.get_datetime(), &DateTime::from_str("2023-12-30").unwrap());
+            assert_eq!(store.get_metadata()["value_date"], "2023-12-29");
+            assert_eq!(store.get_metadata()["source_id"], "B2312290001");
+            assert_eq!(client.get_name(), "Client SL");
+            assert_eq!(client.get_notes(), "Factura 7");
+            assert_eq!(client.get_metadata()["source_id"], "REF77");
+            assert_eq!(subscription.get_name(), "Monthly subscription");
+            assert!(subscription.get_metadata().get("source_id").is_none());
+
+            let asset = AccountName::new("asset/ES7921000813610123456789");
+            assert_eq!(store.get_amount(&asset).unwrap(), &Amount::from_str("-12.5 EUR").unwrap());
+
+            let mut database = Database::default();
+            for account in imported.accounts.iter() {
+                database.add_account(account.clone()).unwrap();
+            }
+            let [opening, closing] = imported.balances.as_slice() else {
+                panic!("there should be two balances")
+            };
+            assert!(opening.verify(&database).is_err());
+
+            database.add_transaction(Transaction::example_transaction(
+                "Opening",
+                "",
+                DateTime::from_str("2023-12-01").unwrap(),
+                &[(asset.as_ref(), "1000 EUR"), ("expense/to_sort", "1000 EUR")],
+            )).unwrap();
+            for transaction in imported.transactions.iter() {
+                database.add_transaction(transaction.clone()).unwrap();
+            }
+            assert!(opening.verify(&database).is_ok());
+            assert!(closing.verify(&database).is_ok());
+        }
+
+        #[test]
+        fn zero_opening_balance() {
+            let text = ":25:NL91ABNA0417164300\n:60F:C240101EUR0,00\n:61:240102C250,NMSCREF77\n:62F:C240102EUR250,00\n";
+            let imported = parse_mt940(text, &StatementAccounts::default()).unwrap();
+            assert!(imported.warnings.is_empty());
+            assert_eq!(
+                imported.transactions[0].get_amount(&AccountName::new("asset/NL91ABNA0417164300")).unwrap(),
+                &Amount::from_str("250 EUR").unwrap()
+            );
+        }
+    }
+}
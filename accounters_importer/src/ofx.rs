@@ -0,0 +1,283 @@
+//! Import of OFX and QFX bank statements
+//!
+//! Version 1 files are SGML, where elements with a value usually have no
+//! closing tag, and version 2 files are XML. Both are read with
+//! [`parse_markup`], ignoring the headers.
+//!
+//! - Every `STMTTRN` of a bank (`STMTRS`) or credit card (`CCSTMTRS`)
+//!   statement becomes a transaction between the asset account and the
+//!   uncategorized one. Its `FITID` is kept in the `source_id` metadata.
+//! - Only the date of `DTPOSTED` is used, since banks usually fill the time
+//!   with a placeholder.
+//! - `LEDGERBAL` becomes a balance to check.
+
+use accounters_lib::data::{
+    account::AccountName,
+    datetime::DateTime,
+    money::Amount,
+};
+
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+use crate::imported::{read_text, BalanceCheck, Imported, StatementAccounts, Warning};
+use crate::markup::{parse_markup, Element};
+
+pub fn import_ofx(path: &str, accounts: &StatementAccounts) -> Result<Imported, String> {
+    let text = read_text(path).map_err(|error| error.to_string())?;
+    parse_ofx(&text, accounts)
+}
+
+pub fn parse_ofx(text: &str, accounts: &StatementAccounts) -> Result<Imported, String> {
+    let root = parse_elements(text)?;
+    let mut imported = Imported::default();
+    let mut assets = BTreeSet::new();
+
+    let statements = root.descendants("STMTRS").into_iter().chain(root.descendants("CCSTMTRS"));
+    for statement in statements {
+        let account_number = statement
+            .child("BANKACCTFROM")
+            .or_else(|| statement.child("CCACCTFROM"))
+            .and_then(|from| from.value_of("ACCTID"));
+        let Some(account_number) = account_number else {
+            imported.warnings.push(Warning::new(statement.line, "statement skipped: it has no account number"));
+            continue
+        };
+        let Some(currency) = statement.value_of("CURDEF") else {
+            imported.warnings.push(Warning::new(statement.line, "statement skipped: it has no currency"));
+            continue
+        };
+        let asset = accounts.asset_for(account_number);
+
+        for entry in statement.descendants("STMTTRN") {
+            match read_entry(entry, &asset, currency, accounts) {
+                Ok(transaction) => imported.transactions.push(transaction),
+                Err(message) => imported.warnings.push(Warning::new(entry.line, format!("transaction skipped: {message}"))),
+            }
+        }
+
+        if let Some(balance) = statement.child("LEDGERBAL") {
+            let date = balance.value_of("DTASOF").and_then(parse_date);
+            let amount = balance.value_of("BALAMT").and_then(|amount| parse_amount(amount, currency));
+            match (date, amount) {
+                (Some(date), Some(amount)) => imported.balances.push(BalanceCheck {
+                    line: balance.line,
+                    account: AccountName::new(&asset),
+                    date,
+                    amount,
+                }),
+                _ => imported.warnings.push(Warning::new(balance.line, "could not read the ledger balance")),
+            }
+        }
+        assets.insert(asset);
+    }
+
+    if assets.is_empty() {
+        return Err(String::from("there are no statements in the file"))
+    }
+    imported.accounts = accounts.accounts(assets.iter());
+    Ok(imported)
+}
+
+fn read_entry(
+    entry: &Element,
+    asset: &str,
+    currency: &str,
+    accounts: &StatementAccounts,
+) -> Result<accounters_lib::data::transaction::Transaction, String> {
+    let date = entry.value_of("DTPOSTED").ok_or("it has no posted date")?;
+    let date = parse_date(date).ok_or_else(|| format!("invalid date '{date}'"))?;
+    let currency = entry.child("CURRENCY").and_then(|currency| currency.value_of("CURSYM")).unwrap_or(currency);
+    let amount = entry.value_of("TRNAMT").ok_or("it has no amount")?;
+    let amount = parse_amount(amount, currency).ok_or_else(|| format!("invalid amount '{amount}'"))?;
+
+    let memo = entry.value_of("MEMO");
+    let payee = entry
+        .value_of("NAME")
+        .or_else(|| entry.child("PAYEE").and_then(|payee| payee.value_of("NAME")));
+    let (name, notes) = match (payee, memo) {
+        (Some(payee), memo) => (payee, memo.unwrap_or_default()),
+        (None, Some(memo)) => (memo, ""),
+        (None, None) => (entry.value_of("TRNTYPE").unwrap_or_default(), ""),
+    };
+
+    let mut transaction = accounts.transaction(asset, name, notes, date, amount);
+    if let Some(id) = entry.value_of("FITID") {
+        transaction.set_metadata("source_id", id);
+    }
+    if let Some(number) = entry.value_of("CHECKNUM") {
+        transaction.set_metadata("check_number", number);
+    }
+    Ok(transaction)
+}
+
+/// Read a date like `20240115`, `20240115120000` or
+/// `20240115120000.000[-5:EST]`, keeping only the day
+fn parse_date(text: &str) -> Option<DateTime> {
+    let digits = text.get(..8).filter(|digits| digits.chars().all(|c| c.is_ascii_digit()))?;
+    DateTime::from_str(&format!("{}-{}-{}", &digits[..4], &digits[4..6], &digits[6..])).ok()
+}
+
+fn parse_amount(text: &str, currency: &str) -> Option<Amount> {
+    Amount::from_str(&format!("{} {}", text.trim(), currency.trim())).ok()
+}
+
+/// The tree of elements that starts at the `<OFX>` tag, skipping the
+/// headers
+fn parse_elements(text: &str) -> Result<Element, String> {
+    let start = text.find("<OFX>").ok_or("it is not an OFX file")?;
+    let first_line = 1 + text[..start].matches('\n').count();
+    parse_markup(&text[start..], first_line)?
+        .children
+        .into_iter()
+        .next()
+        .ok_or_else(|| String::from("it is not an OFX file"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use accounters_lib::data::Database;
+
+    fn load(imported: &Imported) -> Database {
+        let mut database = Database::default();
+        for account in imported.accounts.iter() {
+            database.add_account(account.clone()).unwrap();
+        }
+        for transaction in imported.transactions.iter() {
+            database.add_transaction(transaction.clone()).unwrap();
+        }
+        database
+    }
+
+    mod sgml {
+        use super::*;
+
+        const EXAMPLE: &str = "OFXHEADER:100
+DATA:OFXSGML
+VERSION:102
+CHARSET:1252
+
+<OFX>
+<SIGNONMSGSRSV1><SONRS><STATUS><CODE>0<SEVERITY>INFO</STATUS>
+<DTSERVER>20240201120000<LANGUAGE>ENG</SONRS></SIGNONMSGSRSV1>
+<BANKMSGSRSV1><STMTTRNRS><TRNUID>1
+<STMTRS>
+<CURDEF>USD
+<BANKACCTFROM><BANKID>121000248<ACCTID>12345678<ACCTTYPE>CHECKING</BANKACCTFROM>
+<BANKTRANLIST>
+<DTSTART>20240101<DTEND>20240131
+<STMTTRN>
+<TRNTYPE>DEBIT
+<DTPOSTED>20240115120000.000[-5:EST]
+<TRNAMT>-42.50
+<FITID>2024011501
+<NAME>Joe's Diner &amp; Grill
+<MEMO>Card 1234
+</STMTTRN>
+<STMTTRN>
+<TRNTYPE>CREDIT
+<DTPOSTED>20240125
+<TRNAMT>1500.00
+<FITID>2024012502
+<MEMO>Salary
+</STMTTRN>
+<STMTTRN>
+<TRNTYPE>DEBIT
+<DTPOSTED>2024-01-28
+<TRNAMT>-3.00
+</STMTTRN>
+</BANKTRANLIST>
+<LEDGERBAL><BALAMT>1457.50<DTASOF>20240131</LEDGERBAL>
+</STMTRS>
+</STMTTRNRS></BANKMSGSRSV1>
+</OFX>
+";
+
+        #[test]
+        fn importing() {
+            let imported = parse_ofx(EXAMPLE, &StatementAccounts::default()).unwrap();
+            assert_eq!(imported.warnings, vec![Warning::new(30, "transaction skipped: invalid date '2024-01-28'")]);
+            assert_eq!(imported.transactions.len(), 2);
+
+            let diner = &imported.transactions[0];
+            assert_eq!(diner.get_name(), "Joe's Diner & Grill");
+            assert_eq!(diner.get_notes(), "Card 1234");
+            assert_eq!(diner.get_datetime(), &DateTime::from_str("2024-01-15").unwrap());
+            assert_eq!(diner.get_metadata()["source_id"], "2024011501");
+            assert_eq!(
+                diner.get_amount(&AccountName::new("flow/uncategorized")).unwrap(),
+                &Amount::from_str("-42.5 USD").unwrap()
+            );
+            assert_eq!(imported.transactions[1].get_name(), "Salary");
+
+            let database = load(&imported);
+            assert_eq!(imported.balances.len(), 1);
+            assert!(imported.balances[0].verify(&database).is_ok());
+        }
+    }
+
+    mod xml {
+        use super::*;
+
+        const EXAMPLE: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>
+<?OFX OFXHEADER="200" VERSION="220" SECURITY="NONE" OLDFILEUID="NONE" NEWFILEUID="NONE"?>
+<OFX>
+  <CREDITCARDMSGSRSV1>
+    <CCSTMTTRNRS>
+      <TRNUID>1</TRNUID>
+      <CCSTMTRS>
+        <CURDEF>EUR</CURDEF>
+        <CCACCTFROM><ACCTID>4000-1234</ACCTID></CCACCTFROM>
+        <BANKTRANLIST>
+          <!-- Only one this month -->
+          <STMTTRN>
+            <TRNTYPE>DEBIT</TRNTYPE>
+            <DTPOSTED>20240210</DTPOSTED>
+            <TRNAMT>-19,99</TRNAMT>
+            <FITID>A1</FITID>
+            <PAYEE><NAME>Bookshop</NAME></PAYEE>
+          </STMTTRN>
+        </BANKTRANLIST>
+        <LEDGERBAL>
+          <BALAMT>-25.00</BALAMT>
+          <DTASOF>20240229</DTASOF>
+        </LEDGERBAL>
+      </CCSTMTRS>
+    </CCSTMTTRNRS>
+  </CREDITCARDMSGSRSV1>
+</OFX>
+"#;
+
+        #[test]
+        fn importing() {
+            let accounts = StatementAccounts {
+                asset: Some(String::from("asset/visa")),
+                uncategorized: String::from("expense/unknown"),
+                currency: None,
+            };
+            let imported = parse_ofx(EXAMPLE, &accounts).unwrap();
+            assert!(imported.warnings.is_empty());
+            assert_eq!(imported.accounts.len(), 2);
+
+            let [transaction] = imported.transactions.as_slice() else {
+                panic!("there should be one transaction")
+            };
+            assert_eq!(transaction.get_name(), "Bookshop");
+            assert_eq!(
+                transaction.get_amount(&AccountName::new("asset/visa")).unwrap(),
+                &Amount::from_str("-19.99 EUR").unwrap()
+            );
+
+            let database = load(&imported);
+            let warning = imported.balances[0].verify(&database).unwrap_err();
+            assert_eq!(warning.line, 20);
+            assert!(warning.message.contains("-25.00 EUR in the file"), "{}", warning.message);
+        }
+
+        #[test]
+        fn not_ofx() {
+            assert!(parse_ofx("date,amount\n", &StatementAccounts::default()).is_err());
+        }
+    }
+}
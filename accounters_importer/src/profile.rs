@@ -0,0 +1,241 @@
+//! Descriptions of the CSV files of each bank or app
+//!
+//! Profiles are read from a JSON file that maps their names to their
+//! settings, like this one for a bank that writes debits as negative
+//! numbers with a decimal comma:
+//!
+//! ```json
+//! {
+//!     "my_bank": {
+//!         "delimiter": ";",
+//!         "columns": { "date": "Booking date", "name": "Text", "amount": "Amount" },
+//!         "date_format": "dd.mm.yyyy",
+//!         "decimal_separator": ",",
+//!         "currency": "SEK",
+//!         "asset_account": "asset/my_bank",
+//!         "counter_accounts": [{ "account": "flow/uncategorized" }]
+//!     }
+//! }
+//! ```
+//!
+//! Columns are given by their name in the header row or by their position,
+//! starting at 0. Besides `date`, `time`, `name`, `notes`, `amount`,
+//! `debit`, `credit`, `currency` and `id`, which have a meaning, any other
+//! name can be given to a column to use it in account names, like
+//! `expense/{category}`.
+//!
+//! The `expenses_app` profile, for the export of the expenses app, is
+//! always available.
+
+use serde::Deserialize;
+
+use std::collections::BTreeMap;
+
+/// The profiles that come with the importer
+const BUILT_IN: &str = include_str!("profiles.json");
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(default = "default_delimiter")]
+    pub delimiter: char,
+    /// Whether the first row has the names of the columns
+    #[serde(default = "default_header")]
+    pub header: bool,
+    pub columns: BTreeMap<String, Column>,
+    /// A pattern like `dd/mm/yyyy`, as in
+    /// [`DateFormat`](accounters_lib::data::datespec::DateFormat). Without
+    /// one, dates must look like `2024-01-31` or `2024-01-31 12:00`.
+    #[serde(default)]
+    pub date_format: Option<String>,
+    #[serde(default = "default_decimal_separator")]
+    pub decimal_separator: char,
+    #[serde(default)]
+    pub thousands_separator: Option<char>,
+    #[serde(default)]
+    pub sign: Sign,
+    /// The currency of the rows that do not have one
+    #[serde(default)]
+    pub currency: Option<String>,
+    /// The account of the file, like `asset/{account}`
+    pub asset_account: String,
+    /// The account of the other side of each row: the first one whose
+    /// condition is met
+    pub counter_accounts: Vec<CounterAccount>,
+}
+
+/// A column, by its name in the header or its position
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Column {
+    Index(usize),
+    Name(String),
+}
+
+/// How to tell money that comes in from money that goes out
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Sign {
+    /// Positive amounts come in
+    #[default]
+    AsIs,
+    /// Positive amounts go out
+    Negated,
+    /// The amount goes out when the condition is met, whatever its sign
+    NegativeWhen(Condition),
+}
+
+/// The value of a column is one of a list
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Condition {
+    pub column: String,
+    #[serde(rename = "is")]
+    pub values: Vec<String>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CounterAccount {
+    #[serde(default)]
+    pub when: Option<Condition>,
+    /// The name of the account, like `expense/{group}/{category}`
+    pub account: String,
+    /// Whether the amount of the account is the opposite of the asset one,
+    /// which is the case of transfers between asset accounts
+    #[serde(default)]
+    pub negate: bool,
+}
+
+fn default_delimiter() -> char {
+    ','
+}
+
+fn default_header() -> bool {
+    true
+}
+
+fn default_decimal_separator() -> char {
+    '.'
+}
+
+impl Profile {
+    /// Check that the profile can be used: the columns it needs are there
+    /// and the account names only use known columns
+    fn validate(&self) -> Result<(), String> {
+        let has = |column: &str| self.columns.contains_key(column);
+        if !has("date") {
+            return Err(String::from("there is no date column"))
+        }
+        if !has("amount") && !has("debit") && !has("credit") {
+            return Err(String::from("there is no amount, debit or credit column"))
+        }
+        if !self.header && self.columns.values().any(|column| matches!(column, Column::Name(_))) {
+            return Err(String::from("columns can only be given by name if there is a header"))
+        }
+        if self.currency.is_none() && !has("currency") {
+            return Err(String::from("there is no currency column, and no currency is given"))
+        }
+        if self.counter_accounts.is_empty() {
+            return Err(String::from("there are no counter accounts"))
+        }
+
+        let mut conditions = self.counter_accounts.iter().filter_map(|counter| counter.when.as_ref()).collect::<Vec<_>>();
+        if let Sign::NegativeWhen(condition) = &self.sign {
+            conditions.push(condition);
+        }
+        if let Some(condition) = conditions.into_iter().find(|condition| !has(&condition.column)) {
+            return Err(format!("the condition uses the unknown column '{}'", condition.column))
+        }
+        let templates = std::iter::once(&self.asset_account).chain(self.counter_accounts.iter().map(|counter| &counter.account));
+        for template in templates {
+            for column in template_columns(template)? {
+                if !has(column) {
+                    return Err(format!("the account '{template}' uses the unknown column '{column}'"))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The names of the columns used in an account name like
+/// `expense/{category}`
+pub fn template_columns(template: &str) -> Result<Vec<&str>, String> {
+    let mut columns = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..].find('}').ok_or_else(|| format!("unclosed '{{' in the account '{template}'"))?;
+        columns.push(&rest[start + 1..start + end]);
+        rest = &rest[start + end + 1..];
+    }
+    Ok(columns)
+}
+
+/// Replace the column names in an account name by their values
+pub fn fill_template(template: &str, value_of: impl Fn(&str) -> String) -> String {
+    let mut output = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            break
+        };
+        output.push_str(&rest[..start]);
+        output.push_str(&value_of(&rest[start + 1..start + end]));
+        rest = &rest[start + end + 1..];
+    }
+    output.push_str(rest);
+    output
+}
+
+/// The built-in profiles, and the ones of the file if it is given
+pub fn load_profiles(path: Option<&str>) -> Result<BTreeMap<String, Profile>, String> {
+    let mut profiles = parse_profiles(BUILT_IN).map_err(|error| format!("built-in profiles: {error}"))?;
+    if let Some(path) = path {
+        let text = std::fs::read_to_string(path).map_err(|error| format!("{path}: {error}"))?;
+        profiles.extend(parse_profiles(&text).map_err(|error| format!("{path}: {error}"))?);
+    }
+    Ok(profiles)
+}
+
+pub fn parse_profiles(text: &str) -> Result<BTreeMap<String, Profile>, String> {
+    let profiles: BTreeMap<String, Profile> = serde_json::from_str(text).map_err(|error| error.to_string())?;
+    for (name, profile) in profiles.iter() {
+        profile.validate().map_err(|error| format!("profile {name}: {error}"))?;
+    }
+    Ok(profiles)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    mod loading {
+        use super::*;
+
+        #[test]
+        fn built_in() {
+            let profiles = load_profiles(None).unwrap();
+            let profile = &profiles["expenses_app"];
+            assert_eq!(profile.columns["date"], Column::Index(1));
+            assert_eq!(profile.counter_accounts.len(), 3);
+        }
+
+        #[test]
+        fn invalid() {
+            let profile = |extra: &str| format!(
+                r#"{{"bank": {{"columns": {{"date": 0, "amount": "Amount"}}, "currency": "EUR",
+                "asset_account": "asset/bank", "counter_accounts": [{{"account": "expense/{{category}}"}}]{extra}}}}}"#
+            );
+            let error = parse_profiles(&profile("")).unwrap_err();
+            assert_eq!(error, "profile bank: the account 'expense/{category}' uses the unknown column 'category'");
+            let error = parse_profiles(&profile(r#", "header": false"#)).unwrap_err();
+            assert_eq!(error, "profile bank: columns can only be given by name if there is a header");
+            assert!(parse_profiles(&profile(r#", "delimter": ";""#)).unwrap_err().contains("unknown field"));
+
+            let valid = profile(r#", "sign": {"negative_when": {"column": "amount", "is": ["x"]}}"#)
+                .replace("{category}", "food");
+            assert!(parse_profiles(&valid).is_ok());
+        }
+    }
+}
@@ -0,0 +1,539 @@
+//! Import of QIF files, as exported by old desktop finance software
+//!
+//! A QIF file is made of sections that start with a `!Type:` line, with
+//! records of one field per line that end with `^`. The first letter of a
+//! line tells the field.
+//!
+//! - Records of `!Type:Bank`, `!Type:CCard` and the other cash accounts
+//!   become transactions of the asset account named by the last `!Account`
+//!   record, or of the one given if there is none.
+//! - Categories (`L` and `S` lines) become `expense/...` or `income/...`
+//!   accounts, as in the CSV export of the expenses app: `Food:Groceries`
+//!   is `expense/Food/Groceries`. They are incomes if they are declared so
+//!   in a `!Type:Cat` section or, if they are not declared, when their
+//!   first amount in the file is positive. The amounts of the other sign
+//!   stay in the same account with a warning. A class after `/` becomes a
+//!   tag.
+//! - Transfers, written `[Account]`, go to `asset/Account` with the
+//!   opposite amount. The copy of a transfer in the section of the other
+//!   account is skipped with a warning.
+//! - Splits (`S`, `E` and `$` lines) become postings of the same
+//!   transaction. What they leave of the total goes to the uncategorized
+//!   account.
+//! - Dates are read month first unless a day in the file does not fit,
+//!   which happens with the exports of European software.
+
+use accounters_lib::data::{
+    account::{Account, AccountType},
+    datetime::DateTime,
+    money::{Amount, Currency},
+    tags::Tag,
+    transaction::Transaction,
+};
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::str::FromStr;
+
+use crate::imported::{read_text, Imported, StatementAccounts, Warning};
+
+pub fn import_qif(path: &str, accounts: &StatementAccounts) -> Result<Imported, String> {
+    let text = read_text(path).map_err(|error| error.to_string())?;
+    parse_qif(&text, accounts)
+}
+
+pub fn parse_qif(text: &str, accounts: &StatementAccounts) -> Result<Imported, String> {
+    let currency = accounts.currency.clone().ok_or("QIF files do not tell their currency, it must be given")?;
+    let mut parser = Parser::default();
+    for (index, line) in text.lines().enumerate() {
+        parser.parse_line(index + 1, line.trim_start_matches('\u{feff}').trim_end(), accounts);
+    }
+    if !parser.seen_header {
+        return Err(String::from("it is not a QIF file"))
+    }
+    Ok(parser.finish(&currency, accounts))
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    #[default]
+    Ignored,
+    Transactions,
+    Account,
+    Categories,
+}
+
+struct Record {
+    line: usize,
+    fields: Vec<(char, String)>,
+}
+
+impl Record {
+    fn get(&self, field: char) -> Option<&str> {
+        self.fields.iter().find(|(name, _)| *name == field).map(|(_, value)| value.trim()).filter(|value| !value.is_empty())
+    }
+}
+
+#[derive(Default)]
+struct Parser {
+    seen_header: bool,
+    section: Section,
+    record: Option<Record>,
+    /// The asset account of the transactions that come next
+    account: Option<String>,
+    income_categories: BTreeSet<String>,
+    /// Records of transactions, with their asset account
+    entries: Vec<(String, Record)>,
+    warnings: Vec<Warning>,
+}
+
+impl Parser {
+    fn parse_line(&mut self, line_number: usize, line: &str, accounts: &StatementAccounts) {
+        if line.is_empty() {
+            return
+        }
+        if let Some(header) = line.strip_prefix('!') {
+            if let Some(record) = self.record.take() {
+                self.warnings.push(Warning::new(record.line, "record without an end (^)"));
+                self.finish_record(record, accounts);
+            }
+            self.seen_header = true;
+            self.section = match header.trim().to_lowercase().as_str() {
+                "type:bank" | "type:ccard" | "type:cash" | "type:oth a" | "type:oth l" => Section::Transactions,
+                "account" => Section::Account,
+                "type:cat" => Section::Categories,
+                "option:autoswitch" | "clear:autoswitch" => self.section,
+                _ => {
+                    self.warnings.push(Warning::new(line_number, format!("unsupported section '{header}'")));
+                    Section::Ignored
+                }
+            };
+            return
+        }
+
+        if line == "^" {
+            if let Some(record) = self.record.take() {
+                self.finish_record(record, accounts);
+            }
+            return
+        }
+        let mut chars = line.chars();
+        let field = chars.next().unwrap();
+        self.record
+            .get_or_insert_with(|| Record { line: line_number, fields: Vec::new() })
+            .fields
+            .push((field, chars.as_str().to_owned()));
+    }
+
+    fn finish_record(&mut self, record: Record, accounts: &StatementAccounts) {
+        match self.section {
+            Section::Transactions => {
+                let asset = accounts.asset.clone().unwrap_or_else(|| asset_name(self.account.as_deref().unwrap_or("qif")));
+                self.entries.push((asset, record));
+            },
+            Section::Account => self.account = record.get('N').map(str::to_owned),
+            Section::Categories => {
+                if let (Some(name), Some(_)) = (record.get('N'), record.get('I')) {
+                    self.income_categories.insert(name.to_owned());
+                }
+            },
+            Section::Ignored => (),
+        }
+    }
+
+    fn finish(mut self, currency: &Currency, accounts: &StatementAccounts) -> Imported {
+        if let Some(record) = self.record.take() {
+            self.finish_record(record, accounts);
+        }
+
+        // Month first, unless some date only makes sense with the day first
+        let day_first = self.entries
+            .iter()
+            .filter_map(|(_, record)| record.get('D'))
+            .any(|date| date_parts(date).is_some_and(|(first, _, _)| first > 12));
+
+        let mut imported = Imported::default();
+        let mut types = BTreeMap::new();
+        // Whether each undeclared category is an income, from its first amount
+        let mut undeclared = BTreeMap::new();
+        // Transfers imported from one account, with their line, until the
+        // section of the other account has them too
+        let mut transfers = HashMap::<_, Vec<(&str, usize)>>::new();
+        for (asset, record) in self.entries.iter() {
+            match self.build_transaction(asset, record, day_first, currency, accounts, &mut undeclared, &mut imported.warnings) {
+                Ok((transaction, is_transfer)) => {
+                    if is_transfer {
+                        let postings = transaction
+                            .get_amounts()
+                            .iter()
+                            .map(|(name, amount)| format!("{} {}", name.as_ref(), amount.to_plain_string()))
+                            .collect::<Vec<_>>();
+                        let unmatched = transfers.entry((transaction.get_datetime().get_date_string(), postings)).or_default();
+                        if let Some(index) = unmatched.iter().position(|(other, _)| other != asset) {
+                            let (_, line) = unmatched.remove(index);
+                            imported.warnings.push(Warning::new(
+                                record.line,
+                                format!("transfer skipped: it is the one of line {line}, seen from the other account")
+                            ));
+                            continue
+                        }
+                        unmatched.push((asset, record.line));
+                    }
+                    for account_name in transaction.get_associated_accounts() {
+                        let account_type = match account_name.as_ref().starts_with("asset/") {
+                            true => AccountType::Asset,
+                            false => AccountType::Flow,
+                        };
+                        types.insert(account_name.as_ref().to_owned(), account_type);
+                    }
+                    imported.transactions.push(transaction);
+                },
+                Err(message) => imported.warnings.push(Warning::new(record.line, format!("transaction skipped: {message}"))),
+            }
+        }
+
+        imported.accounts = types.into_iter().map(|(name, account_type)| Account::new(&name, account_type)).collect();
+        self.warnings.append(&mut imported.warnings);
+        imported.warnings = self.warnings;
+        imported.warnings.sort_by_key(|warning| warning.line);
+        imported
+    }
+
+    /// The transaction of a record, and whether it is a transfer
+    #[allow(clippy::too_many_arguments)]
+    fn build_transaction(
+        &self,
+        asset: &str,
+        record: &Record,
+        day_first: bool,
+        currency: &Currency,
+        accounts: &StatementAccounts,
+        undeclared: &mut BTreeMap<String, bool>,
+        warnings: &mut Vec<Warning>,
+    ) -> Result<(Transaction, bool), String> {
+        let date = record.get('D').ok_or("it has no date")?;
+        let date = parse_date(date, day_first).ok_or_else(|| format!("invalid date '{date}'"))?;
+        let total = record.get('T').or_else(|| record.get('U')).ok_or("it has no amount")?;
+        let total = parse_amount(total, currency).ok_or_else(|| format!("invalid amount '{total}'"))?;
+
+        let mut splits = Vec::new();
+        let mut notes = Vec::new();
+        for (field, value) in record.fields.iter() {
+            match field {
+                'S' => splits.push((value.trim().to_owned(), None)),
+                '$' => {
+                    let amount = parse_amount(value, currency).ok_or_else(|| format!("invalid split amount '{value}'"))?;
+                    match splits.last_mut() {
+                        Some((_, split_amount @ None)) => *split_amount = Some(amount),
+                        _ => return Err(String::from("split amount without a category")),
+                    }
+                },
+                'E' if !value.trim().is_empty() => notes.push(value.trim().to_owned()),
+                _ => (),
+            }
+        }
+
+        let mut amounts = BTreeMap::<String, Amount>::new();
+        let mut add = |account: String, amount: &Amount| {
+            let total = amounts.remove(&account).unwrap_or_default();
+            amounts.insert(account, total + amount);
+        };
+        add(asset.to_owned(), &total);
+
+        let mut tags = BTreeSet::new();
+        let mut is_transfer = false;
+        let mut category_posting = |category: &str, amount: &Amount| match self.category_account(category, amount, accounts, undeclared) {
+            Category::Flow(account, class, other_sign) => {
+                if other_sign {
+                    warnings.push(Warning::new(
+                        record.line,
+                        format!("{} is put in {account}, where the first amount of the category went", amount.to_plain_string())
+                    ));
+                }
+                tags.extend(class);
+                add(account, amount)
+            },
+            Category::Transfer(account) => {
+                is_transfer = true;
+                add(account, &-amount)
+            },
+        };
+        if splits.is_empty() {
+            category_posting(record.get('L').unwrap_or_default(), &total);
+        } else {
+            let mut remaining = total.clone();
+            for (category, amount) in splits.iter() {
+                let amount = amount.as_ref().ok_or_else(|| format!("split '{category}' has no amount"))?;
+                remaining = remaining - amount;
+                category_posting(category, amount);
+            }
+            if !remaining.is_zero() {
+                category_posting("", &remaining);
+            }
+        }
+
+        let memo = record.get('M');
+        let (name, memo) = match (record.get('P'), memo) {
+            (Some(payee), memo) => (payee, memo),
+            (None, Some(memo)) => (memo, None),
+            (None, None) => ("", None),
+        };
+        notes.splice(0..0, memo.map(str::to_owned));
+
+        let amounts = amounts.into_iter().collect::<Vec<_>>();
+        let mut transaction = Transaction::from_amounts(name, &notes.join("\n"), date, &amounts);
+        for tag in tags {
+            transaction.add_tag(Tag::new(&tag));
+        }
+        if let Some(number) = record.get('N') {
+            transaction.set_metadata("check_number", number);
+        }
+        Ok((transaction, is_transfer))
+    }
+
+    /// The account of a category like `Food:Groceries/Business`, or of a
+    /// transfer like `[Savings]`
+    ///
+    /// An undeclared category gets its type from its first amount, which is
+    /// kept in `undeclared`.
+    fn category_account(
+        &self,
+        category: &str,
+        amount: &Amount,
+        accounts: &StatementAccounts,
+        undeclared: &mut BTreeMap<String, bool>,
+    ) -> Category {
+        let category = category.trim();
+        if let Some(account) = category.strip_prefix('[').and_then(|rest| rest.split(']').next()) {
+            return Category::Transfer(asset_name(account))
+        }
+        let (category, class) = match category.split_once('/') {
+            Some((category, class)) => (category.trim(), Some(class.trim().to_owned()).filter(|class| !class.is_empty())),
+            None => (category, None),
+        };
+        if category.is_empty() {
+            return Category::Flow(accounts.uncategorized.clone(), class, false)
+        }
+
+        let (is_income, other_sign) = match self.income_categories.iter().any(|income| category_matches(category, income)) {
+            true => (true, false),
+            false => {
+                let is_positive = amount.currencies().iter().all(|currency| amount.in_currency(currency).is_nonnegative());
+                let is_income = *undeclared.entry(category.to_owned()).or_insert(is_positive);
+                (is_income, is_income != is_positive)
+            },
+        };
+        let root = if is_income { "income" } else { "expense" };
+        Category::Flow(format!("{}/{}", root, category.replace(':', "/")), class, other_sign)
+    }
+}
+
+enum Category {
+    /// An expense or income account, the class of the category, and whether
+    /// the amount has not the sign that gave the account its type
+    Flow(String, Option<String>, bool),
+    Transfer(String),
+}
+
+/// Whether the category is the declared one or one of its subcategories
+fn category_matches(category: &str, declared: &str) -> bool {
+    category == declared || category.strip_prefix(declared).is_some_and(|rest| rest.starts_with(':'))
+}
+
+fn asset_name(account: &str) -> String {
+    format!("asset/{}", account.trim().replace('/', "_"))
+}
+
+/// The numbers of a date like `1/15'24`, `01/15/2024` or `15.01.2024`, in
+/// the same order
+fn date_parts(text: &str) -> Option<(u32, u32, i32)> {
+    let short_year_base = if text.contains('\'') { 2000 } else { 1900 };
+    let text = text.replace(' ', "");
+    let parts = text.split(['/', '\'', '.', '-']).collect::<Vec<_>>();
+    let [first, second, third] = parts.as_slice() else {
+        return None
+    };
+    if first.len() == 4 {
+        return None
+    }
+    let year = third.parse::<i32>().ok()?;
+    let year = match third.len() {
+        1 | 2 if short_year_base == 1900 && year < 50 => 2000 + year,
+        1 | 2 => short_year_base + year,
+        _ => year,
+    };
+    Some((first.parse().ok()?, second.parse().ok()?, year))
+}
+
+fn parse_date(text: &str, day_first: bool) -> Option<DateTime> {
+    if let Ok(date) = DateTime::from_str(text.trim()) {
+        return Some(date)
+    }
+    let (first, second, year) = date_parts(text)?;
+    let (month, day) = match day_first {
+        true => (second, first),
+        false => (first, second),
+    };
+    DateTime::from_str(&format!("{year:04}-{month:02}-{day:02}")).ok()
+}
+
+/// Read an amount like `-1,234.56`
+fn parse_amount(text: &str, currency: &Currency) -> Option<Amount> {
+    let text = text.trim();
+    let number = if text.contains('.') { text.replace(',', "") } else { text.to_owned() };
+    Amount::from_str(&format!("{} {}", number, currency.0)).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use accounters_lib::data::account::AccountName;
+
+    mod sections {
+        use super::*;
+
+        const EXAMPLE: &str = "!Type:Cat
+NSalary
+I
+^
+NBonus
+E
+^
+!Account
+NChecking
+TBank
+^
+!Type:Bank
+D1/15'24
+T-1,250.00
+PLandlord
+MJanuary
+N1001
+LHousing:Rent/Home
+^
+D01/31/2024
+T2,000.00
+PEmployer
+SSalary
+$2,300.00
+STaxes:Income
+ETax withheld
+$-200.00
+^
+D2/3'24
+T-300.00
+PTo savings
+L[Savings]
+^
+D2/30/2024
+T-1.00
+^
+D02/05/2024
+PNo amount
+^
+!Account
+NSavings
+TBank
+^
+!Type:Bank
+D2/3'24
+T300.00
+PFrom checking
+L[Checking]
+^
+!Type:Invst
+D2/3'24
+NBuy
+^
+";
+
+        fn accounts() -> StatementAccounts {
+            StatementAccounts { currency: Some(Currency::new("USD")), ..Default::default() }
+        }
+
+        #[test]
+        fn importing() {
+            let imported = parse_qif(EXAMPLE, &accounts()).unwrap();
+            assert_eq!(imported.warnings, vec![
+                Warning::new(34, "transaction skipped: invalid date '2/30/2024'"),
+                Warning::new(37, "transaction skipped: it has no amount"),
+                Warning::new(45, "transfer skipped: it is the one of line 29, seen from the other account"),
+                Warning::new(50, "unsupported section 'Type:Invst'"),
+            ]);
+
+            let [rent, salary, transfer] = imported.transactions.as_slice() else {
+                panic!("there should be three transactions")
+            };
+            let amount = |transaction: &Transaction, account: &str| {
+                transaction.get_amount(&AccountName::new(account)).unwrap().to_plain_string()
+            };
+
+            assert_eq!(rent.get_name(), "Landlord");
+            assert_eq!(rent.get_notes(), "January");
+            assert_eq!(rent.get_datetime(), &DateTime::from_str("2024-01-15").unwrap());
+            assert_eq!(amount(rent, "expense/Housing/Rent"), "-1250.00 USD");
+            assert!(rent.get_tags().contains(&Tag::new("Home")));
+            assert_eq!(rent.get_metadata()["check_number"], "1001");
+
+            assert_eq!(amount(salary, "asset/Checking"), "2000.00 USD");
+            assert_eq!(amount(salary, "income/Salary"), "2300.00 USD");
+            assert_eq!(amount(salary, "expense/Taxes/Income"), "-200.00 USD");
+            assert_eq!(amount(salary, "flow/uncategorized"), "-100.00 USD");
+            assert_eq!(salary.get_notes(), "Tax withheld");
+
+            assert_eq!(amount(transfer, "asset/Checking"), "-300.00 USD");
+            assert_eq!(amount(transfer, "asset/Savings"), "300.00 USD");
+
+            let names = imported.accounts.iter().map(|account| account.get_name().as_ref()).collect::<Vec<_>>();
+            assert_eq!(names, [
+                "asset/Checking",
+                "asset/Savings",
+                "expense/Housing/Rent",
+                "expense/Taxes/Income",
+                "flow/uncategorized",
+                "income/Salary",
+            ]);
+        }
+
+        #[test]
+        fn identical_transfers() {
+            let transfer = "D2/3'24\nT-300.00\nL[Savings]\n^\n";
+            let text = format!("!Account\nNChecking\n^\n!Type:Bank\n{transfer}{transfer}");
+            let imported = parse_qif(&text, &accounts()).unwrap();
+            assert_eq!(2, imported.transactions.len());
+            assert!(imported.warnings.is_empty());
+
+            let text = format!("{text}!Account\nNSavings\n^\n!Type:Bank\nD2/3'24\nT300.00\nL[Checking]\n^\n");
+            let imported = parse_qif(&text, &accounts()).unwrap();
+            assert_eq!(2, imported.transactions.len());
+            assert_eq!(imported.warnings, vec![
+                Warning::new(17, "transfer skipped: it is the one of line 5, seen from the other account"),
+            ]);
+        }
+
+        #[test]
+        fn undeclared_category_sign() {
+            let text = "!Type:Bank\nD1/2'24\nT-50.00\nLFood\n^\nD1/9'24\nT10.00\nLFood\n^\n";
+            let imported = parse_qif(text, &accounts()).unwrap();
+            for transaction in imported.transactions.iter() {
+                assert!(transaction.get_amount(&AccountName::new("expense/Food")).is_ok());
+            }
+            assert_eq!(imported.warnings, vec![
+                Warning::new(6, "10.00 USD is put in expense/Food, where the first amount of the category went"),
+            ]);
+            assert_eq!(imported.accounts.len(), 2);
+        }
+
+        #[test]
+        fn day_first_and_currency() {
+            let text = "!Type:CCard\nD05.02.2024\nT-10,50\nLFood\n^\nD25.02.2024\nT-1\n^\n";
+            let imported = parse_qif(text, &accounts()).unwrap();
+            assert_eq!(imported.transactions[0].get_datetime(), &DateTime::from_str("2024-02-05").unwrap());
+            assert_eq!(
+                imported.transactions[0].get_amount(&AccountName::new("asset/qif")).unwrap(),
+                &Amount::from_str("-10.5 USD").unwrap()
+            );
+
+            assert!(parse_qif(text, &StatementAccounts::default()).is_err());
+        }
+    }
+}
@@ -1,16 +1,17 @@
 use serde::{Deserialize, Serialize};
 
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
 use std::convert::AsRef;
 
 use crate::data::transaction::TransactionId;
 use crate::data::tags::Tag;
+use crate::data::Error;
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct Account {
     name: AccountName,
     account_type: AccountType,
-    tags: HashSet<Tag>,
+    tags: BTreeSet<Tag>,
     #[serde(skip)]
     transactions: HashSet<TransactionId>,
 }
@@ -21,7 +22,7 @@ pub enum AccountType {
     Flow,
 }
 
-#[derive(Deserialize, Serialize, Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(Deserialize, Serialize, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct AccountName(String);
 
 impl AccountName {
@@ -40,7 +41,7 @@ impl Account {
     pub fn new(account_name: &str, account_type: AccountType) -> Self {
         Account {
             name: AccountName(account_name.to_owned()),
-            tags: HashSet::new(),
+            tags: BTreeSet::new(),
             account_type,
             transactions: HashSet::default(),
         }
@@ -54,6 +55,10 @@ impl Account {
         &self.name
     }
 
+    pub fn get_tags(&self) -> &BTreeSet<Tag> {
+        &self.tags
+    }
+
     pub fn get_account_type(&self) -> &AccountType {
         &self.account_type
     }
@@ -62,12 +67,12 @@ impl Account {
         self.transactions.iter()
     }
 
-    pub fn remove_transaction(&mut self, transaction_id: &TransactionId) -> Result<(), &'static str> {
+    pub fn remove_transaction(&mut self, transaction_id: &TransactionId) -> Result<(), Error> {
         let was_there = self.transactions.remove(transaction_id);
         if was_there {
             Ok(())
         } else {
-            Err("Transaction ID not associated")
+            Err(Error::AccountNotAssociatedWithTransaction((self.name.clone(), *transaction_id)))
         }
     }
 
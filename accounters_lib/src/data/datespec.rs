@@ -0,0 +1,451 @@
+//! Parsing of the dates typed by people or found in imported files
+//!
+//! Besides complete datetimes, a [`DateParser`] understands relative dates
+//! (`today`, `-3d`, `mon`, `last month`), partial dates (`2023-08`, `2023`,
+//! `2023-Q3`, `2023-W07`) and custom formats like `dd/mm/yyyy`.
+
+use std::str::FromStr;
+
+use time::{Date, Duration, Month, Time, Weekday};
+
+use crate::data::datetime::{
+    calendar_date, month_length, offset_in, DateTime, DateTimeError, Period, PeriodKind, Quarter, Week
+};
+
+/// What a date typed by the user refers to, either a single point in time
+/// or a whole period
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DateSpec {
+    Date(DateTime),
+    Range(Period),
+}
+
+impl DateSpec {
+    /// The first instant referred to
+    pub fn start(&self) -> DateTime {
+        match self {
+            DateSpec::Date(datetime) => *datetime,
+            DateSpec::Range(period) => period.start(),
+        }
+    }
+
+    /// The last instant referred to
+    ///
+    /// For a date without time it is the end of the day.
+    pub fn end(&self) -> DateTime {
+        match self {
+            DateSpec::Date(datetime) if datetime.get_time().is_some() => *datetime,
+            DateSpec::Date(datetime) => Period::Day(*datetime.get_date()).end(),
+            DateSpec::Range(period) => period.end(),
+        }
+    }
+
+    /// The limits to use in
+    /// [`Database::get_account_balance`](crate::data::Database::get_account_balance)
+    pub fn bounds(&self) -> (Option<DateTime>, Option<DateTime>) {
+        (Some(self.start()), Some(self.end()))
+    }
+}
+
+/// A date format described with a pattern like `dd/mm/yyyy HH:MM`
+///
+/// The recognised fields are `yyyy`, `yy`, `mm`, `m`, `dd`, `d` for the
+/// date and `HH`, `MM`, `SS` for the time. Single letter fields accept one
+/// or two digits. Any other character must appear as is.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DateFormat {
+    pattern: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Field {
+    Year,
+    ShortYear,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+}
+
+impl DateFormat {
+    pub fn new(pattern: &str) -> Self {
+        Self { pattern: pattern.to_owned() }
+    }
+
+    /// Split the pattern in fields, with their minimum and maximum number of
+    /// digits, and literal characters
+    fn tokens(&self) -> Vec<Result<(Field, usize, usize), char>> {
+        const FIELDS: [(&str, Field, usize, usize); 10] = [
+            ("yyyy", Field::Year, 4, 4),
+            ("yy", Field::ShortYear, 2, 2),
+            ("mm", Field::Month, 2, 2),
+            ("m", Field::Month, 1, 2),
+            ("dd", Field::Day, 2, 2),
+            ("d", Field::Day, 1, 2),
+            ("HH", Field::Hour, 2, 2),
+            ("MM", Field::Minute, 2, 2),
+            ("SS", Field::Second, 2, 2),
+            ("H", Field::Hour, 1, 2),
+        ];
+
+        let mut tokens = Vec::new();
+        let mut rest = self.pattern.as_str();
+        'outer: while let Some(c) = rest.chars().next() {
+            for (text, field, min, max) in FIELDS {
+                if let Some(remaining) = rest.strip_prefix(text) {
+                    tokens.push(Ok((field, min, max)));
+                    rest = remaining;
+                    continue 'outer
+                }
+            }
+            tokens.push(Err(c));
+            rest = &rest[c.len_utf8()..];
+        }
+        tokens
+    }
+
+    pub fn parse(&self, text: &str) -> Result<DateTime, DateTimeError> {
+        let mut values = [None; 7];
+        let trimmed = text.trim();
+        let mut rest = trimmed;
+        let mismatch = |rest: &str| DateTimeError::Unparsable {
+            text: text.to_owned(),
+            position: offset_in(text, rest),
+            expected: format!("a date like {}", self.pattern),
+        };
+
+        for token in self.tokens() {
+            match token {
+                Err(literal) => {
+                    rest = rest.strip_prefix(literal).ok_or_else(|| mismatch(rest))?;
+                },
+                Ok((field, min, max)) => {
+                    let n_digits = rest.chars().take(max).take_while(|c| c.is_ascii_digit()).count();
+                    if n_digits < min {
+                        return Err(mismatch(rest))
+                    }
+                    let (digits, remaining) = rest.split_at(n_digits);
+                    values[field as usize] = Some(digits.parse::<i32>().map_err(|_| mismatch(rest))?);
+                    rest = remaining;
+                }
+            }
+        }
+        if !rest.is_empty() {
+            return Err(mismatch(rest))
+        }
+
+        let invalid_format = || DateTimeError::InvalidFormat(self.pattern.clone());
+        let year = match (values[Field::Year as usize], values[Field::ShortYear as usize]) {
+            (Some(year), _) => year,
+            (None, Some(short_year)) => 2000 + short_year,
+            (None, None) => return Err(invalid_format()),
+        };
+        let month = values[Field::Month as usize].ok_or_else(invalid_format)? as u8;
+        let day = values[Field::Day as usize].ok_or_else(invalid_format)? as u8;
+        if !(1..=12).contains(&month) || day == 0 || day > month_length(year, month) {
+            return Err(DateTimeError::OutOfRange(trimmed.to_owned()))
+        }
+        let date = calendar_date(year, month, day);
+
+        let time = match values[Field::Hour as usize] {
+            None => None,
+            Some(hour) => Some(Time::from_hms(
+                hour as u8,
+                values[Field::Minute as usize].unwrap_or(0) as u8,
+                values[Field::Second as usize].unwrap_or(0) as u8,
+            ).map_err(|_| DateTimeError::OutOfRange(trimmed.to_owned()))?),
+        };
+
+        Ok(DateTime::new(date, time))
+    }
+}
+
+/// Turns text into [`DateSpec`]s, resolving relative dates against `today`
+#[derive(Clone, Debug)]
+pub struct DateParser {
+    today: Date,
+    formats: Vec<DateFormat>,
+}
+
+impl DateParser {
+    pub fn new(today: Date) -> Self {
+        Self { today, formats: Vec::new() }
+    }
+
+    /// A parser that resolves relative dates against the current date in UTC
+    pub fn today() -> Self {
+        Self::new(time::OffsetDateTime::now_utc().date())
+    }
+
+    /// Also accept dates in the given format, which is tried before the
+    /// default ones
+    pub fn with_format(mut self, format: DateFormat) -> Self {
+        self.formats.push(format);
+        self
+    }
+
+    pub fn parse(&self, input: &str) -> Result<DateSpec, DateTimeError> {
+        let input = input.trim();
+
+        let mut format_error = None;
+        for format in self.formats.iter() {
+            match format.parse(input) {
+                Ok(datetime) => return Ok(DateSpec::Date(datetime)),
+                Err(error) => { format_error.get_or_insert(error); }
+            }
+        }
+        let datetime_error = match DateTime::from_str(input) {
+            Ok(datetime) => return Ok(DateSpec::Date(datetime)),
+            Err(error) => error,
+        };
+        if let Some(date) = self.parse_relative_day(&input.to_lowercase()) {
+            let date = date.ok_or_else(|| DateTimeError::OutOfRange(input.to_owned()))?;
+            return Ok(DateSpec::Date(DateTime::new(date, None)))
+        }
+        if let Some(period) = self.parse_relative_period(&input.to_lowercase()) {
+            return Ok(DateSpec::Range(period))
+        }
+
+        // Partial dates are shorter than complete ones, so for long texts
+        // the most useful error is the one about complete dates
+        parse_partial(input).map(DateSpec::Range).map_err(|partial_error| {
+            match format_error {
+                Some(error) => error,
+                None if input.len() >= 10 => datetime_error,
+                None => partial_error,
+            }
+        })
+    }
+
+    /// Parse text that must refer to a single point in time, like a date
+    /// column of an imported file
+    pub fn parse_datetime(&self, input: &str) -> Result<DateTime, DateTimeError> {
+        match self.parse(input)? {
+            DateSpec::Date(datetime) => Ok(datetime),
+            DateSpec::Range(_) => Err(DateTimeError::NotASingleDate(input.trim().to_owned())),
+        }
+    }
+
+    /// `today`, `yesterday`, `tomorrow`, offsets like `-3d` or `+2w` and
+    /// weekdays like `mon`, which refer to the last one up to today
+    ///
+    /// The inner option is empty when the day is out of the calendar.
+    fn parse_relative_day(&self, input: &str) -> Option<Option<Date>> {
+        match input {
+            "today" => return Some(Some(self.today)),
+            "yesterday" => return Some(self.today.previous_day()),
+            "tomorrow" => return Some(self.today.next_day()),
+            _ => {}
+        }
+
+        if let Some(weekday) = parse_weekday(input) {
+            let days_back = (self.today.weekday().number_days_from_monday() + 7
+                - weekday.number_days_from_monday()) % 7;
+            return Some(self.today.checked_sub(Duration::days(days_back as i64)))
+        }
+
+        let sign = match input.chars().next()? {
+            '-' => -1,
+            '+' => 1,
+            _ => return None
+        };
+        let unit = input.chars().last()?;
+        let amount = sign * input.get(1..input.len() - 1)?.parse::<i64>().ok()?;
+        // Larger offsets are out of the calendar anyway, and would overflow
+        // the duration
+        let small_amount = i32::try_from(amount).ok().map(i64::from);
+        let day = match unit {
+            'd' => small_amount.and_then(|days| self.today.checked_add(Duration::days(days))),
+            'w' => small_amount.and_then(|weeks| self.today.checked_add(Duration::weeks(weeks))),
+            'm' => add_months(self.today, amount),
+            'y' => amount.checked_mul(12).and_then(|months| add_months(self.today, months)),
+            _ => return None
+        };
+        Some(day)
+    }
+
+    /// `this`, `last` or `next` followed by `week`, `month`, `quarter` or
+    /// `year`
+    fn parse_relative_period(&self, input: &str) -> Option<Period> {
+        let (which, kind) = input.split_once(' ')?;
+        let kind = match kind.trim() {
+            "week" => PeriodKind::Week,
+            "month" => PeriodKind::Month,
+            "quarter" => PeriodKind::Quarter,
+            "year" => PeriodKind::Year,
+            _ => return None
+        };
+        let current = Period::containing(self.today, kind);
+        match which {
+            "this" => Some(current),
+            "last" => Some(current.previous()),
+            "next" => Some(current.next()),
+            _ => None
+        }
+    }
+}
+
+fn parse_weekday(input: &str) -> Option<Weekday> {
+    const NAMES: [(&str, Weekday); 7] = [
+        ("monday", Weekday::Monday),
+        ("tuesday", Weekday::Tuesday),
+        ("wednesday", Weekday::Wednesday),
+        ("thursday", Weekday::Thursday),
+        ("friday", Weekday::Friday),
+        ("saturday", Weekday::Saturday),
+        ("sunday", Weekday::Sunday),
+    ];
+    if input.len() < 3 {
+        return None
+    }
+    NAMES.iter().find(|(name, _)| name.starts_with(input)).map(|(_, weekday)| *weekday)
+}
+
+/// Move the date some months, keeping the day unless the month is shorter
+fn add_months(date: Date, months: i64) -> Option<Date> {
+    let index = (date.year() as i64 * 12 + date.month() as i64 - 1).checked_add(months)?;
+    let year = i32::try_from(index.div_euclid(12)).ok()?;
+    let month = index.rem_euclid(12) as u8 + 1;
+    let day = date.day().min(month_length(year, month));
+    Date::from_calendar_date(year, Month::try_from(month).ok()?, day).ok()
+}
+
+/// `2023`, `2023-08`, `2023-Q3` or `2023-W07`
+fn parse_partial(input: &str) -> Result<Period, DateTimeError> {
+    let unparsable = |position| DateTimeError::Unparsable {
+        text: input.to_owned(),
+        position,
+        expected: String::from("a date like 2023-08-15, 2023-08, 2023-Q3, 2023-W07, 2023, today, -3d, mon or last month"),
+    };
+
+    let (year, rest) = match input.split_once('-') {
+        Some((year, rest)) => (year, Some(rest)),
+        None => (input, None),
+    };
+    if let Some(index) = year.find(|c: char| !c.is_ascii_digit()) {
+        return Err(unparsable(index))
+    }
+    if year.len() != 4 {
+        return Err(unparsable(0))
+    }
+    let year: i32 = year.parse().map_err(|_| unparsable(0))?;
+
+    let Some(rest) = rest else {
+        return Ok(Period::Year(year))
+    };
+    let number = |text: &str| text.parse::<u8>().map_err(|_| unparsable(input.len() - text.len()));
+
+    let period = if let Some(quarter) = rest.strip_prefix(['Q', 'q']) {
+        Quarter::new(number(quarter)?).map(|quarter| Period::Quarter { year, quarter })
+    } else if let Some(week) = rest.strip_prefix(['W', 'w']) {
+        Week::new(year, number(week)?).map(Period::Week)
+    } else {
+        Month::try_from(number(rest)?).ok().map(|month| Period::Month { year, month })
+    };
+    period.ok_or_else(|| DateTimeError::OutOfRange(input.to_owned()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use time::format_description::well_known::Iso8601;
+
+    fn date(text: &str) -> Date {
+        Date::parse(text, &Iso8601::DEFAULT).unwrap()
+    }
+
+    fn day(text: &str) -> DateSpec {
+        DateSpec::Date(DateTime::new(date(text), None))
+    }
+
+    mod relative {
+        use super::*;
+
+        #[test]
+        fn days() {
+            // A Wednesday
+            let parser = DateParser::new(date("2023-08-16"));
+            assert_eq!(Ok(day("2023-08-16")), parser.parse("today"));
+            assert_eq!(Ok(day("2023-08-15")), parser.parse("Yesterday"));
+            assert_eq!(Ok(day("2023-08-13")), parser.parse("-3d"));
+            assert_eq!(Ok(day("2023-08-30")), parser.parse("+2w"));
+            assert_eq!(Ok(day("2023-08-14")), parser.parse("mon"));
+            assert_eq!(Ok(day("2023-08-16")), parser.parse("wednesday"));
+            assert_eq!(Ok(day("2023-08-10")), parser.parse("thu"));
+            assert_eq!(Err(DateTimeError::OutOfRange(String::from("+10000y"))), parser.parse("+10000y"));
+            assert_eq!(Err(DateTimeError::OutOfRange(String::from("-99999999999d"))), parser.parse("-99999999999d"));
+
+            let parser = DateParser::new(date("2024-03-31"));
+            assert_eq!(Ok(day("2024-02-29")), parser.parse("-1m"));
+            assert_eq!(Ok(day("2023-03-31")), parser.parse("-1y"));
+        }
+
+        #[test]
+        fn periods() {
+            let parser = DateParser::new(date("2023-01-16"));
+            assert_eq!(Ok(DateSpec::Range(Period::Month { year: 2022, month: Month::December })), parser.parse("last month"));
+            assert_eq!(Ok(DateSpec::Range(Period::Quarter { year: 2023, quarter: Quarter::new(1).unwrap() })), parser.parse("this quarter"));
+            assert_eq!(Ok(DateSpec::Range(Period::Year(2024))), parser.parse("next year"));
+            assert!(parser.parse("last decade").is_err());
+        }
+    }
+
+    mod partial {
+        use super::*;
+
+        #[test]
+        fn periods() {
+            let parser = DateParser::new(date("2023-01-16"));
+            assert_eq!(Ok(DateSpec::Range(Period::Month { year: 2023, month: Month::August })), parser.parse("2023-08"));
+            assert_eq!(Ok(DateSpec::Range(Period::Year(2023))), parser.parse("2023"));
+            assert_eq!(Ok(DateSpec::Range(Period::Quarter { year: 2023, quarter: Quarter::new(3).unwrap() })), parser.parse("2023-Q3"));
+            assert_eq!(Ok(DateSpec::Range(Period::Week(Week::new(2020, 53).unwrap()))), parser.parse("2020-W53"));
+            assert_eq!(Err(DateTimeError::OutOfRange(String::from("2021-W53"))), parser.parse("2021-W53"));
+            assert_eq!(Err(DateTimeError::OutOfRange(String::from("2023-13"))), parser.parse("2023-13"));
+            assert_eq!(Err(DateTimeError::OutOfRange(String::from("2023-Q0"))), parser.parse("2023-Q0"));
+            assert!(matches!(parser.parse("23"), Err(DateTimeError::Unparsable { position: 0, .. })));
+            assert!(matches!(parser.parse("2023-0x"), Err(DateTimeError::Unparsable { position: 5, .. })));
+
+            let spec = parser.parse("2023-08").unwrap();
+            assert_eq!(DateTime::from_str("2023-08-01").unwrap(), spec.start());
+            assert!(spec.end() > DateTime::from_str("2023-08-31T23:59:59").unwrap());
+        }
+    }
+
+    mod format {
+        use super::*;
+
+        #[test]
+        fn custom_formats() {
+            assert_eq!(
+                Ok(DateTime::simple((2023, 8, 5), None)),
+                DateFormat::new("dd/mm/yyyy").parse("05/08/2023")
+            );
+            assert_eq!(
+                Ok(DateTime::simple((2023, 8, 5), Some((7, 3)))),
+                DateFormat::new("d.m.yy H:MM").parse("5.8.23 7:03")
+            );
+            assert_eq!(
+                Err(DateTimeError::OutOfRange(String::from("31/02/2023"))),
+                DateFormat::new("dd/mm/yyyy").parse("31/02/2023")
+            );
+            assert!(matches!(
+                DateFormat::new("dd/mm/yyyy").parse("2023-08-05"),
+                Err(DateTimeError::Unparsable { position: 2, .. })
+            ));
+            assert_eq!(
+                Err(DateTimeError::InvalidFormat(String::from("mm/yyyy"))),
+                DateFormat::new("mm/yyyy").parse("08/2023")
+            );
+
+            let parser = DateParser::new(date("2023-01-16")).with_format(DateFormat::new("mm/dd/yyyy"));
+            assert_eq!(Ok(day("2023-12-01")), parser.parse("12/01/2023"));
+            assert_eq!(Ok(day("2023-12-01")), parser.parse("2023-12-01"));
+            assert_eq!(
+                Err(DateTimeError::NotASingleDate(String::from("2023-12"))),
+                parser.parse_datetime("2023-12")
+            );
+        }
+    }
+}
@@ -1,13 +1,22 @@
-use serde::{Deserialize, Serialize};
+use serde::{
+    de::Visitor,
+    Deserialize, Deserializer, Serialize, Serializer
+};
 
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
 use std::str::FromStr;
 use time::{
     Date,
     Time,
     Month,
+    PrimitiveDateTime,
+    UtcOffset,
+    Weekday,
     format_description::well_known::Iso8601,
     macros::format_description
 };
+use time_tz::{timezones, Offset, PrimitiveDateTimeExt, TimeZone};
 
 const MONTH_DAYS: [u8; 13] = [
     0,
@@ -16,13 +25,118 @@ const MONTH_DAYS: [u8; 13] = [
 ];
 
 fn year_is_leap(year: u16) -> bool {
-    year % 400 == 0 || (year % 4 == 0 && year % 100 != 0)
+    year.is_multiple_of(400) || (year.is_multiple_of(4) && !year.is_multiple_of(100))
+}
+
+/// Number of days of the month (from 1 to 12) in the given year
+pub(crate) fn month_length(year: i32, month: u8) -> u8 {
+    if month == 2 && year_is_leap(year.rem_euclid(400) as u16) {
+        29
+    } else {
+        MONTH_DAYS[month as usize]
+    }
 }
 
-#[derive(Deserialize, Serialize, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub(crate) fn calendar_date(year: i32, month: u8, day: u8) -> Date {
+    Date::from_calendar_date(year, Month::January.nth_next(month - 1), day).unwrap()
+}
+
+/// A point in time, as precise as it is known
+///
+/// The time of the day and the zone are optional. Two datetimes with a zone
+/// are compared by the instant they represent, while a datetime without a
+/// zone is compared as if it was in UTC. A datetime without time goes before
+/// any datetime with time in the same day.
+#[derive(Clone, Copy, Debug)]
 pub struct DateTime {
     date: Date,
     time: Option<Time>,
+    zone: Option<Zone>,
+}
+
+/// The zone in which a [`DateTime`] is expressed
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Zone {
+    /// A fixed offset from UTC, like `+02:00`
+    Offset(UtcOffset),
+    /// A zone of the IANA database, like `Europe/Stockholm`
+    Named(&'static str),
+}
+
+impl Zone {
+    /// Find a zone of the IANA database by its name
+    pub fn named(name: &str) -> Option<Self> {
+        timezones::get_by_name(name).map(|tz| Zone::Named(tz.name()))
+    }
+
+    /// The offset from UTC that the zone has at the given local time
+    ///
+    /// When the local time happens twice, the earliest is used. When it does
+    /// not happen at all, the offset before the transition is used.
+    pub fn offset_at(&self, local: PrimitiveDateTime) -> UtcOffset {
+        match self {
+            Zone::Offset(offset) => *offset,
+            Zone::Named(name) => {
+                let tz = timezones::get_by_name(name).expect("Zone names are always valid");
+                match local.assume_timezone(tz) {
+                    time_tz::OffsetResult::Some(x) => x.offset(),
+                    time_tz::OffsetResult::Ambiguous(x, _) => x.offset(),
+                    time_tz::OffsetResult::None => {
+                        tz.get_offset_utc(&local.assume_utc()).to_utc()
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for Zone {
+    type Err = DateTimeError;
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        if str == "Z" || str == "UTC" {
+            return Ok(Zone::Offset(UtcOffset::UTC))
+        }
+        if !str.starts_with(['+', '-']) {
+            return Zone::named(str).ok_or_else(|| DateTimeError::UnknownZone(str.to_owned()))
+        }
+
+        let unparsable = |position| DateTimeError::Unparsable {
+            text: str.to_owned(),
+            position,
+            expected: String::from("an offset like +02:00")
+        };
+
+        let sign = if str.starts_with('-') { -1 } else { 1 };
+        if let Some(index) = str[1..].find(|c: char| !c.is_ascii_digit() && c != ':') {
+            return Err(unparsable(index + 1))
+        }
+        let digits = str[1..].replace(':', "");
+        let (hours, minutes) = match digits.len() {
+            2 => (&digits[..], "0"),
+            4 => digits.split_at(2),
+            _ => return Err(unparsable(1))
+        };
+        let hours: i8 = hours.parse().map_err(|_| unparsable(1))?;
+        let minutes: i8 = minutes.parse().map_err(|_| unparsable(1))?;
+
+        UtcOffset::from_hms(sign * hours, sign * minutes, 0)
+            .map(Zone::Offset)
+            .map_err(|_| DateTimeError::OutOfRange(str.to_owned()))
+    }
+}
+
+impl std::fmt::Display for Zone {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Zone::Offset(offset) => {
+                let text = offset
+                    .format(format_description!("[offset_hour sign:mandatory]:[offset_minute]"))
+                    .map_err(|_| std::fmt::Error)?;
+                text.fmt(f)
+            },
+            Zone::Named(name) => name.fmt(f)
+        }
+    }
 }
 
 impl DateTime {
@@ -30,9 +144,34 @@ impl DateTime {
         DateTime {
             date: Date::from_calendar_date(date.0, Month::January.nth_next(date.1 - 1) , date.2).unwrap(),
             time: time.map(|(h, m)| Time::from_hms(h, m, 0).unwrap()),
+            zone: None,
+        }
+    }
+
+    pub fn new(date: Date, time: Option<Time>) -> Self {
+        DateTime { date, time, zone: None }
+    }
+
+    /// The current date and time in UTC, to the second
+    pub fn now() -> Self {
+        let now = time::OffsetDateTime::now_utc();
+        DateTime {
+            date: now.date(),
+            time: Some(now.time().replace_nanosecond(0).unwrap()),
+            zone: Some(Zone::Offset(UtcOffset::UTC)),
         }
     }
 
+    /// The same local date and time, expressed in the given zone
+    ///
+    /// The zone is ignored if there is no time.
+    pub fn with_zone(mut self, zone: Zone) -> Self {
+        if self.time.is_some() {
+            self.zone = Some(zone);
+        }
+        self
+    }
+
     pub fn get_date(&self) -> &Date {
         &self.date
     }
@@ -45,47 +184,499 @@ impl DateTime {
         &self.time
     }
 
+    pub fn get_zone(&self) -> &Option<Zone> {
+        &self.zone
+    }
+
     pub fn get_time_string(&self, fallback: char) -> String {
         self.time.map_or_else(
             || String::from(fallback),
-            |time| time.format(format_description!("[hour]:[minute]")).unwrap()
+            |time| if time.second() == 0 {
+                time.format(format_description!("[hour]:[minute]")).unwrap()
+            } else {
+                time.format(format_description!("[hour]:[minute]:[second]")).unwrap()
+            }
         )
     }
+
+    /// The date and time in UTC, if both the time and the zone are known
+    pub fn to_utc(&self) -> Option<PrimitiveDateTime> {
+        let local = PrimitiveDateTime::new(self.date, self.time?);
+        let offset = self.zone?.offset_at(local);
+        let utc = local.assume_offset(offset).to_offset(UtcOffset::UTC);
+        Some(PrimitiveDateTime::new(utc.date(), utc.time()))
+    }
+
+    /// The date and time used to compare datetimes
+    fn sort_key(&self) -> (Date, Option<Time>) {
+        match self.to_utc() {
+            Some(utc) => (utc.date(), Some(utc.time())),
+            None => (self.date, self.time)
+        }
+    }
+
+    /// Compare with a limit of an interval. A limit without zone, like the
+    /// ones of a [`Period`], is compared with the local date and time, so
+    /// that a zoned datetime belongs to the day of its calendar.
+    fn cmp_limit(&self, limit: &DateTime) -> Ordering {
+        match (self.zone, limit.zone) {
+            (Some(_), None) => (self.date, self.time).cmp(&(limit.date, limit.time)),
+            _ => self.cmp(limit),
+        }
+    }
+
+    /// Whether the datetime is between the limits given, both included,
+    /// see [`cmp_limit`](DateTime::cmp_limit)
+    pub fn is_between(&self, start: Option<&DateTime>, end: Option<&DateTime>) -> bool {
+        start.is_none_or(|start| self.cmp_limit(start).is_ge())
+            && end.is_none_or(|end| self.cmp_limit(end).is_le())
+    }
+
+    /// Text that sorts in the same order as the datetimes, for storages
+    /// that can only compare text
+    pub(crate) fn sort_string(&self) -> String {
+        match self.sort_key() {
+            (date, None) => date.to_string(),
+            (date, Some(time)) => format!(
+                "{}T{:02}:{:02}:{:02}.{:09}",
+                date, time.hour(), time.minute(), time.second(), time.nanosecond()
+            ),
+        }
+    }
+
+    /// Text representation that can be parsed back, like
+    /// `2023-08-15T15:23:00+02:00` or `2023-08-15T15:23:00[Europe/Stockholm]`
+    pub fn to_iso_string(&self) -> String {
+        let Some(time) = self.time else {
+            return self.get_date_string()
+        };
+        let mut output = format!(
+            "{}T{}",
+            self.date,
+            time.format(format_description!("[hour]:[minute]:[second]")).unwrap()
+        );
+        match self.zone {
+            Some(Zone::Offset(offset)) if offset.is_utc() => output.push('Z'),
+            Some(zone @ Zone::Offset(_)) => output.push_str(&zone.to_string()),
+            Some(Zone::Named(name)) => output.push_str(&format!("[{}]", name)),
+            None => {}
+        }
+        output
+    }
+}
+
+impl PartialEq for DateTime {
+    fn eq(&self, other: &Self) -> bool {
+        self.sort_key() == other.sort_key()
+    }
+}
+
+impl Eq for DateTime {}
+
+impl PartialOrd for DateTime {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for DateTime {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.sort_key().cmp(&other.sort_key())
+    }
+}
+
+impl Hash for DateTime {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.sort_key().hash(state);
+    }
+}
+
+/// Split the text after the date in the time and the zone, if any
+fn split_zone(str: &str) -> (&str, Option<&str>) {
+    if let Some((time, zone)) = str.split_once('[') {
+        return (time, Some(zone.trim_end_matches(']')))
+    }
+    if let Some((time, zone)) = str.split_once(' ') {
+        return (time, Some(zone.trim()))
+    }
+    if let Some(time) = str.strip_suffix('Z') {
+        return (time, Some("Z"))
+    }
+    match str.find(['+', '-']) {
+        Some(index) => (&str[..index], Some(&str[index..])),
+        None => (str, None)
+    }
+}
+
+/// Byte offset of `inner`, which must be a slice of `outer`
+pub(crate) fn offset_in(outer: &str, inner: &str) -> usize {
+    inner.as_ptr() as usize - outer.as_ptr() as usize
+}
+
+fn parse_date(text: &str) -> Result<Date, DateTimeError> {
+    Date::parse(text, &Iso8601::DEFAULT).map_err(|error| match error {
+        time::error::Parse::TryFromParsed(_) => DateTimeError::OutOfRange(text.to_owned()),
+        _ => DateTimeError::Unparsable {
+            text: text.to_owned(),
+            position: 0,
+            expected: String::from("a date like 2023-08-15"),
+        }
+    })
+}
+
+fn parse_time(text: &str) -> Result<Time, DateTimeError> {
+    Time::parse(text, &Iso8601::DEFAULT).map_err(|error| match error {
+        time::error::Parse::TryFromParsed(_) => DateTimeError::OutOfRange(text.to_owned()),
+        _ => DateTimeError::Unparsable {
+            text: text.to_owned(),
+            position: 0,
+            expected: String::from("a time like 15:23 or 15:23:45"),
+        }
+    })
 }
 
 impl FromStr for DateTime {
-    type Err = &'static str;
-    fn from_str(str: &str) -> Result<Self, Self::Err> {
+    type Err = DateTimeError;
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
         // It should look like 2023-08-15 15:23
         // or
         // It should look like 2023-08-15
         // or
-        // It should look like 2023-08-15T15:23
+        // It should look like 2023-08-15T15:23:45
+        // and optionally be followed by a zone, like
+        // 2023-08-15T15:23+02:00, 2023-08-15 15:23 Europe/Madrid
+        // or 2023-08-15T15:23:00[Europe/Madrid]
 
-        let mut split = if str.contains('T') {
-            str.split('T')
-        } else if str.contains(' ') {
-            str.split(' ')
-        } else {
+        let str = input.trim();
+        let located = |part: &str, error: DateTimeError| error.within(input, offset_in(input, part));
+
+        let Some((date_string, rest)) = str.split_once(['T', ' ']) else {
             return Ok(DateTime {
-                date: Date::parse(str, &Iso8601::DEFAULT).map_err(|_| "Whoops")?,
+                date: parse_date(str).map_err(|e| located(str, e))?,
                 time: None,
+                zone: None,
             });
         };
-        let date_string = split.next().ok_or("What")?;
-        let time_string = split.next().ok_or("What")?;
+        let (time_string, zone_string) = split_zone(rest.trim());
+
+        let mut datetime = DateTime {
+            date: parse_date(date_string).map_err(|e| located(date_string, e))?,
+            time: Some(parse_time(time_string).map_err(|e| located(time_string, e))?),
+            zone: None,
+        };
+        if let Some(zone_string) = zone_string {
+            datetime = datetime.with_zone(zone_string.parse().map_err(|e| located(zone_string, e))?);
+        }
+
+        Ok(datetime)
+    }
+}
+
+/// Everything that can go wrong when reading dates, times and zones
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DateTimeError {
+    /// The text could not be read. The position is the byte offset where
+    /// the problem was found, and `expected` describes what should be there.
+    Unparsable { text: String, position: usize, expected: String },
+    /// The zone is not in the IANA database
+    UnknownZone(String),
+    /// The date, time or offset does not exist, like the 30th of February
+    OutOfRange(String),
+    /// The date format does not say where the year, month or day are
+    InvalidFormat(String),
+    /// A single date was expected, but the text refers to a period
+    NotASingleDate(String),
+}
 
-        Ok(DateTime {
-            date: Date::parse(date_string, &Iso8601::DEFAULT).map_err(|_| "Unparsable date")?,
-            time: Some(Time::parse(time_string, &Iso8601::DEFAULT).map_err(|_| "Unparsable time")?),
-        })
+impl DateTimeError {
+    /// Make the error refer to the complete `text`, knowing that the part
+    /// that failed started at `offset`
+    pub(crate) fn within(self, text: &str, offset: usize) -> Self {
+        match self {
+            DateTimeError::Unparsable { position, expected, .. } => DateTimeError::Unparsable {
+                text: text.to_owned(),
+                position: position + offset,
+                expected
+            },
+            other => other
+        }
     }
 }
 
+impl std::fmt::Display for DateTimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DateTimeError::Unparsable { text, position, expected } => {
+                write!(f, "cannot read \"{}\" at position {}, expected {}", text, position, expected)
+            },
+            DateTimeError::UnknownZone(zone) => write!(f, "unknown time zone \"{}\"", zone),
+            DateTimeError::OutOfRange(text) => write!(f, "\"{}\" does not exist in the calendar", text),
+            DateTimeError::InvalidFormat(format) => {
+                write!(f, "the date format \"{}\" needs a year, a month and a day", format)
+            },
+            DateTimeError::NotASingleDate(text) => {
+                write!(f, "\"{}\" is a period, but a single date was expected", text)
+            },
+        }
+    }
+}
+
+impl std::error::Error for DateTimeError {}
+
 impl std::fmt::Display for DateTime {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let time_string = self.time.map_or(String::from("-----"), |x| format!("{}", x));
-        write!(f, "{}  {}", self.date, time_string)
+        let time_string = self.time.map_or(String::from("-----"), |_| self.get_time_string('-'));
+        write!(f, "{}  {}", self.date, time_string)?;
+        if let Some(zone) = self.zone {
+            write!(f, " {}", zone)?;
+        }
+        Ok(())
+    }
+}
+
+/// A span of the calendar, used to group transactions in reports
+///
+/// A fiscal year is identified by the calendar year in which it starts.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Period {
+    Day(Date),
+    Week(Week),
+    Month { year: i32, month: Month },
+    Quarter { year: i32, quarter: Quarter },
+    Year(i32),
+    FiscalYear { year: i32, start_month: Month },
+}
+
+/// An ISO 8601 week, which starts on Monday
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Week {
+    year: i32,
+    number: u8,
+}
+
+impl Week {
+    /// The week with the given number, if the year has it
+    pub fn new(year: i32, number: u8) -> Option<Self> {
+        Date::from_iso_week_date(year, number, Weekday::Monday).ok()?;
+        Some(Week { year, number })
+    }
+
+    pub fn year(&self) -> i32 {
+        self.year
+    }
+
+    pub fn number(&self) -> u8 {
+        self.number
+    }
+
+    fn monday(&self) -> Date {
+        Date::from_iso_week_date(self.year, self.number, Weekday::Monday).expect("Weeks are checked when created")
+    }
+}
+
+/// A quarter of a year, numbered from 1 to 4
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Quarter(u8);
+
+impl Quarter {
+    pub fn new(number: u8) -> Option<Self> {
+        (1..=4).contains(&number).then_some(Quarter(number))
+    }
+
+    pub fn number(&self) -> u8 {
+        self.0
+    }
+}
+
+/// The different lengths a [`Period`] can have
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum PeriodKind {
+    Day,
+    Week,
+    Month,
+    Quarter,
+    Year,
+    /// A year that starts in the given month
+    FiscalYear(Month),
+}
+
+impl Period {
+    /// The period of the given kind that contains the date
+    pub fn containing(date: Date, kind: PeriodKind) -> Self {
+        let month = date.month();
+        match kind {
+            PeriodKind::Day => Period::Day(date),
+            PeriodKind::Week => {
+                let (year, number, _) = date.to_iso_week_date();
+                Period::Week(Week { year, number })
+            },
+            PeriodKind::Month => Period::Month { year: date.year(), month },
+            PeriodKind::Quarter => Period::Quarter { year: date.year(), quarter: Quarter((month as u8 - 1) / 3 + 1) },
+            PeriodKind::Year => Period::Year(date.year()),
+            PeriodKind::FiscalYear(start_month) => {
+                let year = if month as u8 >= start_month as u8 { date.year() } else { date.year() - 1 };
+                Period::FiscalYear { year, start_month }
+            }
+        }
+    }
+
+    pub fn kind(&self) -> PeriodKind {
+        match self {
+            Period::Day(_) => PeriodKind::Day,
+            Period::Week(_) => PeriodKind::Week,
+            Period::Month { .. } => PeriodKind::Month,
+            Period::Quarter { .. } => PeriodKind::Quarter,
+            Period::Year(_) => PeriodKind::Year,
+            Period::FiscalYear { start_month, .. } => PeriodKind::FiscalYear(*start_month),
+        }
+    }
+
+    pub fn first_day(&self) -> Date {
+        match *self {
+            Period::Day(date) => date,
+            Period::Week(week) => week.monday(),
+            Period::Month { year, month } => calendar_date(year, month as u8, 1),
+            Period::Quarter { year, quarter } => calendar_date(year, 3 * quarter.0 - 2, 1),
+            Period::Year(year) => calendar_date(year, 1, 1),
+            Period::FiscalYear { year, start_month } => calendar_date(year, start_month as u8, 1),
+        }
+    }
+
+    pub fn last_day(&self) -> Date {
+        match *self {
+            Period::Day(date) => date,
+            Period::Week(_) => self.first_day() + time::Duration::days(6),
+            Period::Month { year, month } => calendar_date(year, month as u8, month_length(year, month as u8)),
+            Period::Quarter { year, quarter } => {
+                let month = 3 * quarter.0;
+                calendar_date(year, month, month_length(year, month))
+            },
+            Period::Year(year) => calendar_date(year, 12, 31),
+            Period::FiscalYear { .. } => self.next().first_day().previous_day().unwrap(),
+        }
+    }
+
+    /// The first instant of the period
+    pub fn start(&self) -> DateTime {
+        DateTime::new(self.first_day(), None)
+    }
+
+    /// The last instant of the period
+    pub fn end(&self) -> DateTime {
+        DateTime::new(self.last_day(), Some(Time::from_hms_nano(23, 59, 59, 999_999_999).unwrap()))
+    }
+
+    /// The start and the end of the period, ready to be used as the limits
+    /// of [`Database::get_account_balance`](crate::data::Database::get_account_balance)
+    pub fn bounds(&self) -> (Option<DateTime>, Option<DateTime>) {
+        (Some(self.start()), Some(self.end()))
+    }
+
+    /// Whether the datetime is in the period, by its local date
+    pub fn contains(&self, datetime: &DateTime) -> bool {
+        datetime.is_between(Some(&self.start()), Some(&self.end()))
+    }
+
+    /// The period of the same kind that comes right after this one
+    pub fn next(&self) -> Self {
+        match *self {
+            Period::Month { year, month: Month::December } => Period::Month { year: year + 1, month: Month::January },
+            Period::Month { year, month } => Period::Month { year, month: month.next() },
+            Period::Quarter { year, quarter: Quarter(4) } => Period::Quarter { year: year + 1, quarter: Quarter(1) },
+            Period::Quarter { year, quarter } => Period::Quarter { year, quarter: Quarter(quarter.0 + 1) },
+            Period::Year(year) => Period::Year(year + 1),
+            Period::FiscalYear { year, start_month } => Period::FiscalYear { year: year + 1, start_month },
+            Period::Day(_) | Period::Week(_) => {
+                Period::containing(self.last_day().next_day().unwrap(), self.kind())
+            }
+        }
+    }
+
+    /// The period of the same kind that comes right before this one
+    pub fn previous(&self) -> Self {
+        match *self {
+            Period::Month { year, month: Month::January } => Period::Month { year: year - 1, month: Month::December },
+            Period::Month { year, month } => Period::Month { year, month: month.previous() },
+            Period::Quarter { year, quarter: Quarter(1) } => Period::Quarter { year: year - 1, quarter: Quarter(4) },
+            Period::Quarter { year, quarter } => Period::Quarter { year, quarter: Quarter(quarter.0 - 1) },
+            Period::Year(year) => Period::Year(year - 1),
+            Period::FiscalYear { year, start_month } => Period::FiscalYear { year: year - 1, start_month },
+            Period::Day(_) | Period::Week(_) => {
+                Period::containing(self.first_day().previous_day().unwrap(), self.kind())
+            }
+        }
+    }
+
+    /// Iterator over this period and all the following ones
+    pub fn iter(&self) -> Periods {
+        Periods { next: Some(*self), last: None }
+    }
+
+    /// Iterator from this period up to the one containing `last`, both
+    /// included
+    pub fn iter_until(&self, last: Date) -> Periods {
+        Periods { next: Some(*self), last: Some(Period::containing(last, self.kind())) }
+    }
+}
+
+impl std::fmt::Display for Period {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Period::Day(date) => write!(f, "{}", date),
+            Period::Week(week) => write!(f, "{}-W{:02}", week.year, week.number),
+            Period::Month { year, month } => write!(f, "{}-{:02}", year, *month as u8),
+            Period::Quarter { year, quarter } => write!(f, "{}-Q{}", year, quarter.0),
+            Period::Year(year) => write!(f, "{}", year),
+            Period::FiscalYear { year, start_month: Month::January } => write!(f, "FY{}", year),
+            Period::FiscalYear { year, .. } => write!(f, "FY{}/{:02}", year, (year + 1).rem_euclid(100)),
+        }
+    }
+}
+
+/// Consecutive periods, see [`Period::iter`]
+pub struct Periods {
+    next: Option<Period>,
+    last: Option<Period>,
+}
+
+impl Iterator for Periods {
+    type Item = Period;
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.next?;
+        self.next = if Some(current) == self.last {
+            None
+        } else {
+            Some(current.next())
+        };
+        Some(current)
+    }
+}
+
+/// Datetimes are stored as text, see [`DateTime::to_iso_string`]
+///
+impl Serialize for DateTime {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_iso_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for DateTime {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct DateTimeVisitor;
+
+        impl<'de> Visitor<'de> for DateTimeVisitor {
+            type Value = DateTime;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "a datetime like \"2023-08-15T15:23:00+02:00\"")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, text: &str) -> Result<Self::Value, E> {
+                text.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(DateTimeVisitor)
     }
 }
 
@@ -108,7 +699,136 @@ mod test {
 
         #[test]
         fn compare_datetime() {
-            assert!(DateTime::from_str("2008-08-04") < DateTime::from_str("2008-08-04T00:05"))
+            assert!(DateTime::from_str("2008-08-04").unwrap() < DateTime::from_str("2008-08-04T00:05").unwrap())
+        }
+
+        #[test]
+        fn compare_zones() {
+            let stockholm = DateTime::from_str("2023-07-14T00:30 Europe/Stockholm").unwrap();
+            let madrid = DateTime::from_str("2023-07-13T23:45+02:00").unwrap();
+            let utc = DateTime::from_str("2023-07-13T21:00Z").unwrap();
+            assert!(madrid < stockholm);
+            assert!(utc < madrid);
+            assert_eq!(
+                DateTime::from_str("2023-01-10T10:00:00[Europe/Madrid]").unwrap(),
+                DateTime::from_str("2023-01-10T09:00:00Z").unwrap()
+            );
+            assert!(DateTime::from_str("2023-07-13T23:45:01+02:00").unwrap() > madrid);
+        }
+    }
+
+    mod period {
+        use super::*;
+
+        fn date(text: &str) -> Date {
+            Date::parse(text, &Iso8601::DEFAULT).unwrap()
+        }
+
+        #[test]
+        fn containing() {
+            let day = date("2024-02-29");
+            assert_eq!(Period::Week(Week::new(2024, 9).unwrap()), Period::containing(day, PeriodKind::Week));
+            assert_eq!(Period::Quarter { year: 2024, quarter: Quarter(1) }, Period::containing(day, PeriodKind::Quarter));
+            assert_eq!(
+                Period::FiscalYear { year: 2023, start_month: Month::July },
+                Period::containing(day, PeriodKind::FiscalYear(Month::July))
+            );
+            assert_eq!(
+                Period::Week(Week::new(2020, 53).unwrap()),
+                Period::containing(date("2021-01-03"), PeriodKind::Week)
+            );
+        }
+
+        #[test]
+        fn limits() {
+            let february = Period::Month { year: 2024, month: Month::February };
+            assert_eq!(date("2024-02-29"), february.last_day());
+            assert_eq!(date("2023-02-28"), Period::Month { year: 2023, month: Month::February }.last_day());
+            assert_eq!(date("2000-02-29"), Period::Month { year: 2000, month: Month::February }.last_day());
+            assert_eq!(date("1900-02-28"), Period::Month { year: 1900, month: Month::February }.last_day());
+            assert_eq!(date("2023-09-30"), Period::Quarter { year: 2023, quarter: Quarter(3) }.last_day());
+            assert_eq!(date("2025-03-31"), Period::FiscalYear { year: 2024, start_month: Month::April }.last_day());
+            assert_eq!(date("2021-01-04"), Period::Week(Week::new(2021, 1).unwrap()).first_day());
+
+            assert_eq!(None, Quarter::new(0));
+            assert_eq!(None, Quarter::new(5));
+            assert_eq!(None, Week::new(2021, 53));
+            assert_eq!(None, Week::new(2021, 0));
+
+            assert!(february.contains(&DateTime::from_str("2024-02-29T23:59:59").unwrap()));
+            assert!(february.contains(&DateTime::from_str("2024-02-01").unwrap()));
+            assert!(!february.contains(&DateTime::from_str("2024-03-01").unwrap()));
+
+            // Zoned datetimes belong to their local day
+            let september = Period::Month { year: 2023, month: Month::September };
+            let after_midnight = DateTime::from_str("2023-09-01T00:30+02:00").unwrap();
+            assert!(september.contains(&after_midnight));
+            assert!(!september.previous().contains(&after_midnight));
+            assert!(september.contains(&DateTime::from_str("2023-09-30T23:30[Europe/Stockholm]").unwrap()));
+        }
+
+        #[test]
+        fn iterating() {
+            let months = Period::Month { year: 2023, month: Month::November }
+                .iter_until(date("2024-02-10"))
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>();
+            assert_eq!(vec!["2023-11", "2023-12", "2024-01", "2024-02"], months);
+
+            let weeks = Period::Week(Week::new(2020, 52).unwrap()).iter().take(3).map(|x| x.to_string()).collect::<Vec<_>>();
+            assert_eq!(vec!["2020-W52", "2020-W53", "2021-W01"], weeks);
+
+            let quarter = Period::Quarter { year: 2024, quarter: Quarter(1) };
+            assert_eq!(quarter, quarter.next().previous());
+            assert_eq!("FY2023/24", Period::FiscalYear { year: 2023, start_month: Month::July }.to_string());
+        }
+    }
+
+    mod parse {
+        use super::*;
+        #[test]
+        fn parsing() {
+            let datetime = DateTime::from_str("2023-08-15 15:23:45 +01:30").unwrap();
+            assert_eq!(Some(Time::from_hms(15, 23, 45).unwrap()), *datetime.get_time());
+            assert_eq!(Some(Zone::Offset(UtcOffset::from_hms(1, 30, 0).unwrap())), *datetime.get_zone());
+
+            let datetime = DateTime::from_str("2023-08-15T15:23-0500").unwrap();
+            assert_eq!(Some(Zone::Offset(UtcOffset::from_hms(-5, 0, 0).unwrap())), *datetime.get_zone());
+
+            assert_eq!(
+                Err(DateTimeError::UnknownZone(String::from("Europe/Nowhere"))),
+                DateTime::from_str("2023-08-15T15:23 Europe/Nowhere")
+            );
+            assert!(matches!(
+                DateTime::from_str("2023-08-15T15:23+2"),
+                Err(DateTimeError::Unparsable { position: 17, .. })
+            ));
+            assert!(matches!(
+                DateTime::from_str("2023-08-15 1x:23"),
+                Err(DateTimeError::Unparsable { position: 11, .. })
+            ));
+            assert_eq!(
+                Err(DateTimeError::OutOfRange(String::from("2023-02-30"))),
+                DateTime::from_str("2023-02-30")
+            );
+        }
+
+        #[test]
+        fn serializing() {
+            for text in [
+                "2023-08-15",
+                "2023-08-15T15:23:45",
+                "2023-08-15T15:23:00Z",
+                "2023-08-15T15:23:00-03:30",
+                "2023-08-15T15:23:00[Europe/Stockholm]"
+            ] {
+                let datetime = DateTime::from_str(text).unwrap();
+                assert_eq!(text, datetime.to_iso_string());
+                let json = serde_json::to_string(&datetime).unwrap();
+                let read: DateTime = serde_json::from_str(&json).unwrap();
+                assert_eq!(datetime.get_zone(), read.get_zone());
+                assert_eq!(datetime, read);
+            }
         }
     }
 }
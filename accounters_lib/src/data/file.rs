@@ -0,0 +1,136 @@
+//! Safe reading and writing of database files
+//!
+//! Saving never modifies the database file in place: the new content is
+//! written to a temporary file next to it, flushed to disk and then renamed
+//! over the old one, so a crash leaves either the old or the new version.
+//! The previous versions are kept as numbered backups (`name.1` is the most
+//! recent one).
+//!
+//! A [`LockedFile`] holds an advisory lock on the database while it is in
+//! use, so that two programs do not overwrite each other's changes.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::data::{Database, FileError};
+
+/// How a database file is saved
+#[derive(Debug, Clone)]
+pub struct SaveOptions {
+    /// Number of previous versions to keep
+    pub backups: usize,
+}
+
+impl Default for SaveOptions {
+    fn default() -> Self {
+        SaveOptions { backups: 3 }
+    }
+}
+
+/// A database file locked for the exclusive use of the holder
+///
+/// The lock is kept in a separate `.lock` file, because the database file
+/// itself is replaced on every save. It is released when this is dropped.
+pub struct LockedFile {
+    path: PathBuf,
+    options: SaveOptions,
+    _lock: File,
+}
+
+impl LockedFile {
+    /// Lock the database file, failing immediately if someone else holds it
+    pub fn lock<P: AsRef<Path>>(path: P) -> Result<Self, FileError> {
+        let path = path.as_ref();
+        let lock_path = with_suffix(path, ".lock");
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .map_err(|e| FileError::io(&lock_path, e))?;
+
+        match lock.try_lock() {
+            Ok(()) => {},
+            Err(std::fs::TryLockError::WouldBlock) => {
+                return Err(FileError::Locked { path: path.to_owned() })
+            },
+            Err(std::fs::TryLockError::Error(e)) => return Err(FileError::io(&lock_path, e)),
+        }
+
+        Ok(LockedFile { path: path.to_owned(), options: SaveOptions::default(), _lock: lock })
+    }
+
+    pub fn with_options(mut self, options: SaveOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn load(&self) -> Result<Database, FileError> {
+        Database::read_from_file(&self.path)
+    }
+
+    pub fn save(&self, database: &Database) -> Result<(), FileError> {
+        let text = serde_json::to_string_pretty(database).map_err(|e| FileError::format(&self.path, e))?;
+        write_atomically(&self.path, text.as_bytes(), self.options.backups)
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Replace the content of a file, keeping the given number of backups
+pub(crate) fn write_atomically(path: &Path, content: &[u8], backups: usize) -> Result<(), FileError> {
+    let temporary_path = with_suffix(path, ".tmp");
+    let mut temporary = File::create(&temporary_path).map_err(|e| FileError::io(&temporary_path, e))?;
+    temporary.write_all(content)
+        .and_then(|_| temporary.sync_all())
+        .map_err(|e| FileError::io(&temporary_path, e))?;
+    drop(temporary);
+
+    if backups > 0 && path.exists() {
+        rotate_backups(path, backups)?;
+    }
+
+    std::fs::rename(&temporary_path, path).map_err(|e| FileError::io(path, e))?;
+    sync_directory(path);
+    Ok(())
+}
+
+/// Shift `name.1` to `name.2` and so on, dropping the oldest one, and copy
+/// the current file to `name.1`
+///
+/// The current file is copied instead of moved, so that it is still there if
+/// the program stops before the new version is in place.
+fn rotate_backups(path: &Path, backups: usize) -> Result<(), FileError> {
+    let backup = |n: usize| with_suffix(path, &format!(".{n}"));
+
+    for n in (1..backups).rev() {
+        let from = backup(n);
+        if from.exists() {
+            std::fs::rename(&from, backup(n + 1)).map_err(|e| FileError::io(&from, e))?;
+        }
+    }
+    let first = backup(1);
+    std::fs::copy(path, &first).map_err(|e| FileError::io(&first, e))?;
+    Ok(())
+}
+
+/// Make the rename durable. Not every platform allows opening a directory,
+/// and the data is already safe in the file, so failures are ignored.
+fn sync_directory(path: &Path) {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if let Ok(directory) = File::open(directory) {
+        let _ = directory.sync_all();
+    }
+}
@@ -0,0 +1,124 @@
+//! Record of the changes made to a database, so that they can be undone
+//!
+//! Every change is stored as an [`Operation`], which knows its inverse.
+//! Operations are kept in steps: usually a step is a single operation, but
+//! several of them can be grouped, so that for instance a whole import is
+//! undone at once.
+
+use serde::{Deserialize, Serialize};
+
+use crate::data::{account::Account, rules::Rule, transaction::Transaction};
+
+/// A single change in a database
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Operation {
+    AddAccount(Account),
+    RemoveAccount(Account),
+    AddTransaction(Transaction),
+    RemoveTransaction(Transaction),
+    /// A rule inserted at a position of the list of rules
+    AddRule(usize, Rule),
+    /// The rule at a position of the list of rules is removed
+    RemoveRule(usize, Rule),
+}
+
+impl Operation {
+    /// The operation that reverts this one
+    pub fn inverse(&self) -> Operation {
+        match self {
+            Operation::AddAccount(account) => Operation::RemoveAccount(account.clone()),
+            Operation::RemoveAccount(account) => Operation::AddAccount(account.clone()),
+            Operation::AddTransaction(transaction) => Operation::RemoveTransaction(transaction.clone()),
+            Operation::RemoveTransaction(transaction) => Operation::AddTransaction(transaction.clone()),
+            Operation::AddRule(index, rule) => Operation::RemoveRule(*index, rule.clone()),
+            Operation::RemoveRule(index, rule) => Operation::AddRule(*index, rule.clone()),
+        }
+    }
+}
+
+/// The steps that can be undone and redone
+#[derive(Default)]
+pub struct History {
+    done: Vec<Vec<Operation>>,
+    undone: Vec<Vec<Operation>>,
+    /// Operations of the group being recorded, if any
+    group: Option<Vec<Operation>>,
+    /// Number of nested groups open
+    depth: usize,
+}
+
+impl History {
+    /// Store an operation that has just been applied
+    ///
+    /// Anything that had been undone can no longer be redone.
+    pub fn record(&mut self, operation: Operation) {
+        self.undone.clear();
+        match self.group.as_mut() {
+            Some(group) => group.push(operation),
+            None => self.done.push(vec![operation]),
+        }
+    }
+
+    /// Start grouping the following operations in a single step
+    ///
+    /// Groups can be nested, in which case everything is stored in a single
+    /// step when the outermost one ends.
+    pub fn begin_group(&mut self) {
+        self.depth += 1;
+        self.group.get_or_insert_with(Vec::new);
+    }
+
+    /// Stop grouping operations
+    pub fn end_group(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+        if self.depth == 0 {
+            if let Some(group) = self.group.take() {
+                if !group.is_empty() {
+                    self.done.push(group);
+                }
+            }
+        }
+    }
+
+    /// Take the operations recorded in the innermost open group, which is
+    /// closed
+    pub(crate) fn discard_group(&mut self, n_operations: usize) -> Vec<Operation> {
+        let discarded = match self.group.as_mut() {
+            Some(group) => group.split_off(group.len().saturating_sub(n_operations)),
+            None => Vec::new(),
+        };
+        self.end_group();
+        discarded
+    }
+
+    /// Number of operations in the group being recorded
+    pub(crate) fn group_len(&self) -> usize {
+        self.group.as_ref().map_or(0, |group| group.len())
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.done.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.undone.is_empty()
+    }
+
+    /// Take the last step, to undo it
+    pub(crate) fn pop_done(&mut self) -> Option<Vec<Operation>> {
+        self.done.pop()
+    }
+
+    /// Take the last undone step, to redo it
+    pub(crate) fn pop_undone(&mut self) -> Option<Vec<Operation>> {
+        self.undone.pop()
+    }
+
+    pub(crate) fn push_done(&mut self, step: Vec<Operation>) {
+        self.done.push(step);
+    }
+
+    pub(crate) fn push_undone(&mut self, step: Vec<Operation>) {
+        self.undone.push(step);
+    }
+}
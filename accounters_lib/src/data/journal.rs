@@ -0,0 +1,240 @@
+//! Append-only storage of a database
+//!
+//! Instead of rewriting the whole database on every save, every change is
+//! appended to a journal file together with the moment it was made. The
+//! database is rebuilt by replaying the journal, which also allows seeing how
+//! it was at any moment in the past.
+//!
+//! The journal is a text file with one JSON [`Entry`] per line. It always
+//! starts with a snapshot of the database, and more snapshots can be added
+//! later with [`Database::append_snapshot`] so that loading does not need to
+//! replay every change since the beginning. The file keeps growing, since
+//! the old entries are the history of the database.
+//!
+//! An open journal holds the same lock as a [`LockedFile`], so that two
+//! programs cannot append to it at the same time.
+
+use serde::{Deserialize, Serialize};
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::data::{
+    datetime::DateTime,
+    file::LockedFile,
+    history::Operation,
+    storage::ChangeSink,
+    Database,
+    FileError,
+};
+
+/// One line of the journal
+#[derive(Serialize, Deserialize)]
+pub enum Entry {
+    /// A change made to the database
+    Change { timestamp: DateTime, operation: Operation },
+    /// The complete database at some moment
+    Snapshot { timestamp: DateTime, database: Database },
+}
+
+impl Entry {
+    pub fn get_timestamp(&self) -> &DateTime {
+        match self {
+            Entry::Change { timestamp, .. } => timestamp,
+            Entry::Snapshot { timestamp, .. } => timestamp,
+        }
+    }
+}
+
+/// Only the timestamp of a snapshot, to find where to start replaying
+/// without building every snapshot in the file
+#[derive(Deserialize)]
+enum SnapshotHeader {
+    Snapshot { timestamp: DateTime },
+}
+
+/// A journal file open for appending, locked until it is dropped
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    _lock: LockedFile,
+}
+
+impl Journal {
+    /// Start a new journal with a snapshot of the database
+    ///
+    /// Fails if the file already exists.
+    pub fn create<P: AsRef<Path>>(path: P, database: &Database) -> Result<Self, FileError> {
+        let path = path.as_ref();
+        let lock = LockedFile::lock(path)?;
+        let file = OpenOptions::new()
+            .append(true)
+            .create_new(true)
+            .open(path)
+            .map_err(|e| FileError::io(path, e))?;
+
+        let mut journal = Journal { path: path.to_owned(), file, _lock: lock };
+        journal.append_snapshot(database).map_err(|e| FileError::io(path, e))?;
+        Ok(journal)
+    }
+
+    /// Fails if another program holds the lock of the journal.
+    pub(crate) fn open<P: AsRef<Path>>(path: P) -> Result<Self, FileError> {
+        let path = path.as_ref();
+        let lock = LockedFile::lock(path)?;
+        let file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .map_err(|e| FileError::io(path, e))?;
+        Ok(Journal { path: path.to_owned(), file, _lock: lock })
+    }
+
+    fn append(&mut self, entry: &Entry) -> std::io::Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()
+    }
+
+    fn append_change(&mut self, operation: &Operation) -> std::io::Result<()> {
+        // Entry owns its content, so the operation has to be cloned
+        self.append(&Entry::Change { timestamp: DateTime::now(), operation: operation.clone() })
+    }
+
+    pub(crate) fn append_snapshot(&mut self, database: &Database) -> std::io::Result<()> {
+        // Written by hand to avoid cloning the whole database
+        let mut line = format!(
+            "{{\"Snapshot\":{{\"timestamp\":{},\"database\":",
+            serde_json::to_string(&DateTime::now())?
+        );
+        line.push_str(&serde_json::to_string(database)?);
+        line.push_str("}}\n");
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()
+    }
+
+    /// Read every entry of a journal, to inspect when each change was made
+    pub fn read_entries<P: AsRef<Path>>(path: P) -> Result<Vec<Entry>, FileError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| FileError::io(path, e))?;
+        text.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| parse_line(path, index, line))
+            .collect()
+    }
+}
+
+/// Parse a line of the journal, reporting errors with the line number in the
+/// file
+fn parse_line<'a, T: Deserialize<'a>>(path: &Path, index: usize, line: &'a str) -> Result<T, FileError> {
+    serde_json::from_str(line).map_err(|e| match FileError::format(path, e) {
+        FileError::Format { path, column, message, .. } => FileError::Format {
+            path,
+            line: index + 1,
+            column,
+            message
+        },
+        other => other
+    })
+}
+
+/// Rebuild the database from the journal, with the changes made up to
+/// `until` (or all of them)
+fn replay(path: &Path, until: Option<&DateTime>) -> Result<Database, FileError> {
+    let text = std::fs::read_to_string(path).map_err(|e| FileError::io(path, e))?;
+    let lines = text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()).collect::<Vec<_>>();
+
+    // Start from the last snapshot taken before `until`
+    let mut start = None;
+    for (position, (index, line)) in lines.iter().enumerate().rev() {
+        if !line.starts_with("{\"Snapshot\"") {
+            continue
+        }
+        let SnapshotHeader::Snapshot { timestamp } = parse_line(path, *index, line)?;
+        if until.is_none_or(|until| &timestamp <= until) {
+            start = Some(position);
+            break
+        }
+    }
+    let Some(start) = start else {
+        return Err(FileError::Format {
+            path: path.to_owned(),
+            line: 1,
+            column: 1,
+            message: String::from("the journal has no snapshot old enough"),
+        })
+    };
+
+    let database_error = |source| FileError::Database { path: path.to_owned(), source };
+    let mut database = Database::default();
+
+    for (index, line) in lines[start..].iter() {
+        match parse_line(path, *index, line)? {
+            Entry::Snapshot { database: snapshot, .. } => {
+                database = snapshot;
+                database.build_account_transaction_map().map_err(database_error)?;
+            },
+            Entry::Change { timestamp, operation } => {
+                if until.is_some_and(|until| &timestamp > until) {
+                    break
+                }
+                database.apply_in_memory(&operation).map_err(database_error)?;
+            }
+        }
+    }
+
+    Ok(database)
+}
+
+impl Database {
+    /// Load a database from a journal, and keep writing every change to it
+    ///
+    /// Fails if another program holds the lock of the journal.
+    pub fn open_journal<P: AsRef<Path>>(path: P) -> Result<Database, FileError> {
+        // Locked first, so that nothing is appended while replaying
+        let journal = Journal::open(path.as_ref())?;
+        let mut database = replay(path.as_ref(), None)?;
+        database.sink = Some(Box::new(journal));
+        Ok(database)
+    }
+
+    /// The database as it was at the given moment, according to the journal
+    pub fn read_journal_at<P: AsRef<Path>>(path: P, moment: &DateTime) -> Result<Database, FileError> {
+        replay(path.as_ref(), Some(moment))
+    }
+
+    /// Start writing every change of the database to a new journal
+    pub fn start_journal<P: AsRef<Path>>(&mut self, path: P) -> Result<(), FileError> {
+        self.sink = Some(Box::new(Journal::create(path, self)?));
+        Ok(())
+    }
+
+    /// Add a snapshot of the database to its journal, so that the changes
+    /// before it do not need to be replayed when loading
+    ///
+    /// The previous entries are kept, so that the history is not lost.
+    pub fn append_snapshot(&mut self) -> Result<(), FileError> {
+        let Some(mut sink) = self.sink.take() else {
+            return Ok(())
+        };
+        let result = sink.checkpoint(self);
+        self.sink = Some(sink);
+        result
+    }
+}
+
+impl ChangeSink for Journal {
+    fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    fn write_change(&mut self, operation: &Operation) -> Result<(), String> {
+        self.append_change(operation).map_err(|e| e.to_string())
+    }
+
+    fn checkpoint(&mut self, database: &Database) -> Result<(), FileError> {
+        self.append_snapshot(database).map_err(|e| FileError::io(&self.path, e))
+    }
+}
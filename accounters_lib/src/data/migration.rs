@@ -0,0 +1,318 @@
+//! Versions of the database file format, and how to upgrade between them
+//!
+//! Every saved database carries the version of the format it was written
+//! with. Older files are upgraded on load by applying, one after the other,
+//! the migrations from their version to [`CURRENT_VERSION`]. Migrations work
+//! on the raw JSON, so they do not depend on how the current data structures
+//! look.
+//!
+//! Files written before versions were introduced have no version field, and
+//! are considered version 0.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use time::{Date, Time};
+
+/// The version of the format written by this library
+pub const CURRENT_VERSION: u32 = 1;
+
+/// A single step between two consecutive versions
+struct Migration {
+    /// The version it upgrades from, to the next one
+    from: u32,
+    description: &'static str,
+    /// Modifies the database, returning what was changed
+    apply: fn(&mut Value) -> Result<Vec<String>, String>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 0,
+        description: "amounts and dates are written as text",
+        apply: readable_values,
+    },
+];
+
+/// What a migration changed, or would change
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub from: u32,
+    pub to: u32,
+    /// One line for every change, in the order they were made
+    pub changes: Vec<String>,
+}
+
+impl Report {
+    /// Whether the file was already in the current format
+    pub fn is_up_to_date(&self) -> bool {
+        self.from == self.to
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_up_to_date() {
+            return write!(f, "already at version {}", self.to)
+        }
+        write!(f, "version {} to {}", self.from, self.to)?;
+        for change in self.changes.iter() {
+            write!(f, "\n\t{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MigrationError {
+    /// The file was written by a newer version of the library
+    TooNew { version: u32 },
+    /// The file does not look like a database of its version
+    Invalid { version: u32, message: String },
+}
+
+impl std::fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MigrationError::TooNew { version } => write!(
+                f,
+                "the format version {} is newer than the supported one ({})",
+                version, CURRENT_VERSION
+            ),
+            MigrationError::Invalid { version, message } => {
+                write!(f, "could not upgrade from version {}: {}", version, message)
+            },
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+/// The version of a database in its raw JSON form
+pub fn version_of(database: &Value) -> Result<u32, MigrationError> {
+    match database.get("version") {
+        None => Ok(0),
+        Some(version) => version
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| MigrationError::Invalid {
+                version: 0,
+                message: format!("{} is not a valid version", version),
+            }),
+    }
+}
+
+/// Upgrade a database in its raw JSON form to the current version
+pub fn migrate(database: &mut Value) -> Result<Report, MigrationError> {
+    let from = version_of(database)?;
+    if from > CURRENT_VERSION {
+        return Err(MigrationError::TooNew { version: from })
+    }
+
+    let mut changes = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|migration| migration.from >= from) {
+        let step_changes = (migration.apply)(database)
+            .map_err(|message| MigrationError::Invalid { version: migration.from, message })?;
+        changes.push(format!("{} -> {}: {}", migration.from, migration.from + 1, migration.description));
+        changes.extend(step_changes.into_iter().map(|change| format!("  {}", change)));
+    }
+
+    if let Value::Object(map) = database {
+        map.insert(String::from("version"), Value::from(CURRENT_VERSION));
+    }
+    Ok(Report { from, to: CURRENT_VERSION, changes })
+}
+
+/// The version field of a database, which is always written as the current
+/// one, because files are upgraded before being read
+#[derive(Default)]
+pub(crate) struct FormatVersion;
+
+impl Serialize for FormatVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(CURRENT_VERSION)
+    }
+}
+
+impl<'de> Deserialize<'de> for FormatVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        u32::deserialize(deserializer)?;
+        Ok(FormatVersion)
+    }
+}
+
+/// Version 0 to 1: amounts were maps of currencies to `{value, n_decimals}`
+/// and dates were `{date, time}` objects
+fn readable_values(database: &mut Value) -> Result<Vec<String>, String> {
+    let mut n_dates = 0;
+    let mut n_amounts = 0;
+
+    let transactions = match database.get_mut("transactions") {
+        Some(Value::Array(transactions)) => transactions,
+        None => return Ok(Vec::new()),
+        Some(_) => return Err(String::from("the transactions are not a list")),
+    };
+
+    for transaction in transactions.iter_mut() {
+        if let Some(datetime) = transaction.get_mut("datetime").filter(|value| value.is_object()) {
+            *datetime = Value::String(old_datetime(datetime)?);
+            n_dates += 1;
+        }
+
+        let Some(Value::Object(amounts)) = transaction.get_mut("amounts") else {
+            continue
+        };
+        for amount in amounts.values_mut().filter(|value| value.is_object()) {
+            *amount = Value::String(old_amount(amount)?);
+            n_amounts += 1;
+        }
+    }
+
+    Ok(vec![
+        format!("{} dates rewritten", n_dates),
+        format!("{} amounts rewritten", n_amounts),
+    ])
+}
+
+/// `{"date": [2023, 194], "time": [14, 54, 0, 0]}` as `2023-07-13T14:54:00`,
+/// the time being optional
+fn old_datetime(datetime: &Value) -> Result<String, String> {
+    let invalid = || format!("{} is not a valid datetime", datetime);
+    let numbers = |value: Option<&Value>| -> Option<Vec<i64>> {
+        value?.as_array()?.iter().map(Value::as_i64).collect()
+    };
+
+    let date = match numbers(datetime.get("date")).as_deref() {
+        Some(&[year, ordinal]) => i32::try_from(year)
+            .ok()
+            .zip(u16::try_from(ordinal).ok())
+            .and_then(|(year, ordinal)| Date::from_ordinal_date(year, ordinal).ok())
+            .ok_or_else(invalid)?,
+        _ => return Err(invalid()),
+    };
+    if datetime.get("time").is_none_or(Value::is_null) {
+        return Ok(date.to_string())
+    }
+    let time = match numbers(datetime.get("time")).as_deref() {
+        Some(&[hour, minute, second, nanosecond]) => {
+            let part = |number: i64| u8::try_from(number).ok();
+            let nanosecond = u32::try_from(nanosecond).ok();
+            match (part(hour), part(minute), part(second), nanosecond) {
+                (Some(hour), Some(minute), Some(second), Some(nanosecond)) => {
+                    Time::from_hms_nano(hour, minute, second, nanosecond).map_err(|_| invalid())?
+                },
+                _ => return Err(invalid()),
+            }
+        },
+        _ => return Err(invalid()),
+    };
+    Ok(format!("{}T{:02}:{:02}:{:02}", date, time.hour(), time.minute(), time.second()))
+}
+
+/// `{"amounts": {"SEK": {"value": -13250, "n_decimals": 2}}}` as
+/// `-132.50 SEK`
+fn old_amount(amount: &Value) -> Result<String, String> {
+    let invalid = || format!("{} is not a valid amount", amount);
+    let Some(Value::Object(numbers)) = amount.get("amounts") else {
+        return Err(invalid())
+    };
+
+    let mut parts = Vec::new();
+    for (currency, number) in numbers.iter() {
+        let value = number.get("value").and_then(Value::as_i64).ok_or_else(invalid)?;
+        // More decimals than the digits of an i64 cannot be stored
+        let n_decimals = number
+            .get("n_decimals")
+            .and_then(Value::as_u64)
+            .filter(|n_decimals| *n_decimals <= 18)
+            .ok_or_else(invalid)? as usize;
+        if value == 0 {
+            continue
+        }
+
+        let digits = format!("{:0>1$}", value.unsigned_abs(), n_decimals + 1);
+        let (units, decimals) = digits.split_at(digits.len() - n_decimals);
+        let sign = if value < 0 { "-" } else { "" };
+        match decimals.is_empty() {
+            true => parts.push(format!("{}{} {}", sign, units, currency)),
+            false => parts.push(format!("{}{}.{} {}", sign, units, decimals, currency)),
+        }
+    }
+
+    match parts.is_empty() {
+        true => Ok(String::from("0")),
+        false => Ok(parts.join(", ")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    mod migrate {
+        use super::*;
+
+        #[test]
+        fn from_unversioned() {
+            let mut database = serde_json::json!({
+                "accounts": [],
+                "transactions": [{
+                    "name": "Lunch",
+                    "notes": "",
+                    "tags": [],
+                    "datetime": {"date": [2023, 194], "time": [14, 54, 0, 0]},
+                    "amounts": {
+                        "bank/ICA_Bank": {"amounts": {"SEK": {"value": -13250, "n_decimals": 2}}},
+                        "food/lunch": "-132.50 SEK"
+                    }
+                }, {
+                    "name": "Fika",
+                    "notes": "",
+                    "tags": [],
+                    "datetime": {"date": [2024, 60], "time": null},
+                    "amounts": {
+                        "bank/ICA_Bank": {"amounts": {"EUR": {"value": 4, "n_decimals": 0}, "SEK": {"value": -5, "n_decimals": 3}}},
+                        "food/fika": {"amounts": {"SEK": {"value": 0, "n_decimals": 2}}}
+                    }
+                }]
+            });
+
+            let report = migrate(&mut database).unwrap();
+            assert_eq!(0, report.from);
+            assert_eq!(CURRENT_VERSION, report.to);
+            assert!(report.changes.contains(&String::from("  3 amounts rewritten")));
+            assert_eq!(serde_json::json!("2023-07-13T14:54:00"), database["transactions"][0]["datetime"]);
+            assert_eq!(serde_json::json!("-132.50 SEK"), database["transactions"][0]["amounts"]["bank/ICA_Bank"]);
+            assert_eq!(serde_json::json!("2024-02-29"), database["transactions"][1]["datetime"]);
+            assert_eq!(serde_json::json!("4 EUR, -0.005 SEK"), database["transactions"][1]["amounts"]["bank/ICA_Bank"]);
+            assert_eq!(serde_json::json!("0"), database["transactions"][1]["amounts"]["food/fika"]);
+            assert_eq!(serde_json::json!(CURRENT_VERSION), database["version"]);
+
+            // Migrating again does nothing
+            let report = migrate(&mut database).unwrap();
+            assert!(report.is_up_to_date());
+            assert!(report.changes.is_empty());
+        }
+
+        #[test]
+        fn invalid_values() {
+            for (datetime, amount) in [
+                (serde_json::json!({"date": [2023, 366]}), serde_json::json!("1 SEK")),
+                (serde_json::json!({"date": [2023, 1], "time": [25, 0, 0, 0]}), serde_json::json!("1 SEK")),
+                (serde_json::json!("2023-01-01"), serde_json::json!({"amounts": {"SEK": {"value": 1, "n_decimals": 40}}})),
+                (serde_json::json!("2023-01-01"), serde_json::json!({"amounts": {"SEK": {"value": "1"}}})),
+            ] {
+                let mut database = serde_json::json!({
+                    "accounts": [],
+                    "transactions": [{"datetime": datetime, "amounts": {"bank/ICA_Bank": amount}}]
+                });
+                assert!(matches!(migrate(&mut database), Err(MigrationError::Invalid { version: 0, .. })));
+            }
+        }
+
+        #[test]
+        fn too_new() {
+            let mut database = serde_json::json!({"version": CURRENT_VERSION + 1, "accounts": [], "transactions": []});
+            assert_eq!(Err(MigrationError::TooNew { version: CURRENT_VERSION + 1 }), migrate(&mut database));
+        }
+    }
+}
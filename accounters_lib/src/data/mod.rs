@@ -5,12 +5,24 @@
 
 pub mod account;
 pub mod datetime;
+pub mod datespec;
+pub mod file;
+pub mod history;
+pub mod journal;
+pub mod migration;
 pub mod money;
+pub mod query;
+pub mod rules;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod storage;
 pub mod transaction;
 pub mod tags;
+pub mod yearly;
 
 use std::collections::hash_map::Entry;
-use std::{collections::HashMap, io::Write};
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
 
 use serde::{de::Visitor, Deserialize, Deserializer, Serialize, Serializer};
 use serde_json;
@@ -22,6 +34,8 @@ use serde_json;
 /// accounts and transactions taking place between them.
 #[derive(Deserialize, Serialize, Default)]
 pub struct Database {
+    #[serde(default)]
+    version: migration::FormatVersion,
     #[serde(
         serialize_with = "serialize_accounts",
         deserialize_with = "deserialize_accounts"
@@ -32,10 +46,23 @@ pub struct Database {
         deserialize_with = "deserialize_transactions"
     )]
     transactions: HashMap<transaction::TransactionId, transaction::Transaction>,
+    /// The rules applied to imported transactions, in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    rules: Vec<rules::Rule>,
+    #[serde(skip)]
+    history: history::History,
+    #[serde(skip)]
+    sink: Option<Box<dyn storage::ChangeSink>>,
+    #[serde(skip)]
+    archive: Option<yearly::Archive>,
+    /// The first and last dates of the transactions, when only the ones
+    /// between them were loaded
+    #[serde(skip)]
+    loaded_range: Option<(Option<datetime::DateTime>, Option<datetime::DateTime>)>,
 }
 
 /// All the errors that can be returned when interacting with a database
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
     /// The account name is already in use
     AccountNameInUse(account::AccountName),
@@ -51,7 +78,123 @@ pub enum Error {
     /// given
     AccountNotAssociatedWithTransaction((account::AccountName, transaction::TransactionId)),
     /// The transaction is not balanced
-    UnbalancedTransaction
+    UnbalancedTransaction,
+    /// Some transactions refer to accounts that do not exist
+    MissingAccounts(Vec<(transaction::TransactionId, account::AccountName)>),
+    /// The change could not be written to the storage, so it was reverted
+    Storage(String),
+    /// There is already a rule with the same name
+    RuleNameInUse(String),
+    /// There is no rule with that name
+    UnknownRule(String),
+    /// The rule can never match, for the reason given
+    InvalidRule(String),
+    /// Only the transactions between the dates were loaded, so the ones
+    /// asked for are not all there
+    NotLoaded(Option<datetime::DateTime>, Option<datetime::DateTime>),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::AccountNameInUse(name) => write!(f, "there is already an account named {}", name.as_ref()),
+            Error::AccountHasTransactions(name) => write!(f, "the account {} still has transactions", name.as_ref()),
+            Error::TransactionIdInUse(id) => write!(f, "there is already a transaction with id {}", id.0),
+            Error::UnknownAccount(name) => write!(f, "there is no account named {}", name.as_ref()),
+            Error::UnknownTransaction(id) => write!(f, "there is no transaction with id {}", id.0),
+            Error::AccountNotAssociatedWithTransaction((name, id)) => {
+                write!(f, "the transaction {} does not affect the account {}", id.0, name.as_ref())
+            },
+            Error::UnbalancedTransaction => write!(f, "the transaction is not balanced"),
+            Error::MissingAccounts(references) => {
+                write!(f, "some transactions refer to accounts that do not exist:")?;
+                for (id, name) in references {
+                    write!(f, "\n\ttransaction {} refers to {}", id.0, name.as_ref())?;
+                }
+                Ok(())
+            },
+            Error::Storage(message) => write!(f, "the change could not be stored: {}", message),
+            Error::RuleNameInUse(name) => write!(f, "there is already a rule named {}", name),
+            Error::UnknownRule(name) => write!(f, "there is no rule named {}", name),
+            Error::InvalidRule(message) => write!(f, "invalid rule: {}", message),
+            Error::NotLoaded(start, end) => {
+                let date = |date: &Option<datetime::DateTime>, default: &str| {
+                    date.map_or_else(|| default.to_owned(), |date| date.to_string())
+                };
+                write!(f, "only the transactions from {} to {} are loaded", date(start, "the beginning"), date(end, "the end"))
+            },
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// All the errors that can be returned when reading or writing a database
+/// file
+#[derive(Debug)]
+pub enum FileError {
+    /// The file could not be read or written
+    Io { path: PathBuf, source: std::io::Error },
+    /// The content of the file is not a valid database. The line and column
+    /// are where the problem was found, starting at 1.
+    Format { path: PathBuf, line: usize, column: usize, message: String },
+    /// The content of the file is readable, but not consistent
+    Database { path: PathBuf, source: Error },
+    /// Another program is using the file
+    Locked { path: PathBuf },
+    /// The file is in a format version that cannot be upgraded
+    Migration { path: PathBuf, source: migration::MigrationError },
+    /// The storage backend failed for some other reason
+    Storage { path: PathBuf, message: String },
+}
+
+impl FileError {
+    pub(crate) fn io(path: &Path, source: std::io::Error) -> Self {
+        FileError::Io { path: path.to_owned(), source }
+    }
+
+    pub(crate) fn format(path: &Path, source: serde_json::Error) -> Self {
+        let message = source.to_string();
+        // Remove the position that serde_json appends, it is already stored
+        let message = match message.rsplit_once(" at line ") {
+            Some((message, _)) => message.to_owned(),
+            None => message,
+        };
+        FileError::Format {
+            path: path.to_owned(),
+            line: source.line(),
+            column: source.column(),
+            message
+        }
+    }
+}
+
+impl std::fmt::Display for FileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            FileError::Format { path, line, column, message } => {
+                write!(f, "{}:{}:{}: {}", path.display(), line, column, message)
+            },
+            FileError::Database { path, source } => write!(f, "{}: {}", path.display(), source),
+            FileError::Locked { path } => write!(f, "{}: the file is in use by another program", path.display()),
+            FileError::Migration { path, source } => write!(f, "{}: {}", path.display(), source),
+            FileError::Storage { path, message } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for FileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FileError::Io { source, .. } => Some(source),
+            FileError::Format { .. } => None,
+            FileError::Database { source, .. } => Some(source),
+            FileError::Locked { .. } => None,
+            FileError::Migration { source, .. } => Some(source),
+            FileError::Storage { .. } => None,
+        }
+    }
 }
 
 impl Database {
@@ -61,6 +204,193 @@ impl Database {
     /// database with the same name as the new one, in which case the
     /// operation is aborted.
     pub fn add_account(&mut self, new_acc: account::Account) -> Result<(), Error> {
+        self.apply(history::Operation::AddAccount(new_acc))
+    }
+
+    /// Remove an account from the database
+    ///
+    /// The function returns an error if the account has some transaction
+    /// associated to it.
+    pub fn remove_account(&mut self, account_name: account::AccountName) -> Result<(), Error> {
+        let Some(account) = self.accounts.get(&account_name) else {
+            return Err(Error::UnknownAccount(account_name))
+        };
+        self.apply(history::Operation::RemoveAccount(account.clone()))
+    }
+
+    /// Add a new transaction to the database
+    pub fn add_transaction(&mut self, new_trns: transaction::Transaction) -> Result<(), Error> {
+        self.apply(history::Operation::AddTransaction(new_trns))
+    }
+
+    pub fn modify_transaction(&mut self) -> Result<(), Error> {
+        unimplemented!();
+    }
+
+    /// Remove a transaction from the database
+    ///
+    /// The archived years of a [`yearly`] database are loaded if the
+    /// transaction is there.
+    pub fn remove_transaction(&mut self, transaction_id: transaction::TransactionId) -> Result<(), Error> {
+        if !self.transactions.contains_key(&transaction_id) {
+            self.load_archived(&transaction_id)?;
+        }
+        let Some(transaction) = self.transactions.get(&transaction_id) else {
+            return Err(Error::UnknownTransaction(transaction_id))
+        };
+        self.apply(history::Operation::RemoveTransaction(transaction.clone()))
+    }
+
+    /// Undo the last step of the history
+    ///
+    /// Returns whether there was anything to undo.
+    pub fn undo(&mut self) -> Result<bool, Error> {
+        let Some(step) = self.history.pop_done() else {
+            return Ok(false)
+        };
+        let inverses = step.iter().rev().map(|operation| operation.inverse()).collect::<Vec<_>>();
+        if let Err(error) = self.apply_all(&inverses) {
+            self.history.push_done(step);
+            return Err(error)
+        }
+        self.history.push_undone(step);
+        Ok(true)
+    }
+
+    /// Apply again the last step that was undone
+    ///
+    /// Returns whether there was anything to redo.
+    pub fn redo(&mut self) -> Result<bool, Error> {
+        let Some(step) = self.history.pop_undone() else {
+            return Ok(false)
+        };
+        if let Err(error) = self.apply_all(&step) {
+            self.history.push_undone(step);
+            return Err(error)
+        }
+        self.history.push_done(step);
+        Ok(true)
+    }
+
+    pub fn get_history(&self) -> &history::History {
+        &self.history
+    }
+
+    /// Start grouping the following changes in a single undoable step,
+    /// until [`end_group`](Database::end_group) is called
+    pub fn begin_group(&mut self) {
+        self.history.begin_group();
+    }
+
+    pub fn end_group(&mut self) {
+        self.history.end_group();
+    }
+
+    /// Run `changes` as a single undoable step
+    ///
+    /// If `changes` returns an error, everything it did is reverted, see
+    /// [`get_storage_path`](Database::get_storage_path) for when the storage
+    /// cannot take it. The archived years of a [`yearly`] database may be
+    /// needed to revert, and if they cannot be read, the changes that were
+    /// not reverted stay.
+    pub fn grouped<T, E, F>(&mut self, changes: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut Database) -> Result<T, E>,
+    {
+        self.begin_group();
+        let previous_len = self.history.group_len();
+        let output = changes(self);
+        match output {
+            Ok(_) => self.end_group(),
+            Err(_) => {
+                let n_operations = self.history.group_len() - previous_len;
+                let operations = self.history.discard_group(n_operations);
+                // The error of the changes says more than the one of reverting
+                let _ = self.revert(&operations);
+            }
+        }
+        output
+    }
+
+    /// Apply a change and record it in the history
+    fn apply(&mut self, operation: history::Operation) -> Result<(), Error> {
+        self.apply_unrecorded(&operation)?;
+        self.history.record(operation);
+        Ok(())
+    }
+
+    /// Apply a change without recording it in the history, but writing it
+    /// to the storage that follows the changes, if there is one
+    fn apply_unrecorded(&mut self, operation: &history::Operation) -> Result<(), Error> {
+        self.apply_in_memory(operation)?;
+
+        let Some(sink) = self.sink.as_mut() else {
+            return Ok(())
+        };
+        if let Err(error) = sink.write_change(operation) {
+            // If the change cannot be reverted either, the storage no longer
+            // matches the database
+            if let Err(revert_error) = self.apply_in_memory(&operation.inverse()) {
+                self.sink = None;
+                return Err(revert_error)
+            }
+            return Err(Error::Storage(error))
+        }
+        Ok(())
+    }
+
+    /// Apply several changes without recording them
+    ///
+    /// If one of them fails, the ones before it are reverted. The error is
+    /// the one of reverting if that fails too.
+    fn apply_all(&mut self, operations: &[history::Operation]) -> Result<(), Error> {
+        for (index, operation) in operations.iter().enumerate() {
+            if let Err(error) = self.apply_unrecorded(operation) {
+                self.revert(&operations[..index])?;
+                return Err(error)
+            }
+        }
+        Ok(())
+    }
+
+    /// Revert changes that were just applied, the last one first
+    ///
+    /// Reverting in memory only fails when an archived year cannot be read,
+    /// and then the changes before that one stay. If the storage that
+    /// follows the changes cannot take the reverts, it no longer matches the
+    /// database, so it stops following it.
+    fn revert(&mut self, operations: &[history::Operation]) -> Result<(), Error> {
+        for operation in operations.iter().rev() {
+            let inverse = operation.inverse();
+            self.apply_in_memory(&inverse)?;
+            if self.sink.as_mut().is_some_and(|sink| sink.write_change(&inverse).is_err()) {
+                self.sink = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// Where the changes to the database are being written as they happen,
+    /// if they are
+    ///
+    /// After a storage failed to take the revert of some changes it is not
+    /// followed anymore, and the database has to be saved as a whole.
+    pub fn get_storage_path(&self) -> Option<&Path> {
+        self.sink.as_ref().map(|sink| sink.get_path())
+    }
+
+    fn apply_in_memory(&mut self, operation: &history::Operation) -> Result<(), Error> {
+        match operation {
+            history::Operation::AddAccount(account) => self.insert_account(account.clone()),
+            history::Operation::RemoveAccount(account) => self.delete_account(account.get_name().to_owned()),
+            history::Operation::AddTransaction(trns) => self.insert_transaction(trns.clone()),
+            history::Operation::RemoveTransaction(trns) => self.delete_transaction(trns.generate_id()),
+            history::Operation::AddRule(index, rule) => self.insert_rule(*index, rule.clone()),
+            history::Operation::RemoveRule(index, rule) => self.delete_rule(*index, rule),
+        }
+    }
+
+    fn insert_account(&mut self, new_acc: account::Account) -> Result<(), Error> {
         // Check that the account name does not already exist. If it does not,
         // add the account name to the account map.
 
@@ -76,11 +406,7 @@ impl Database {
         Ok(())
     }
 
-    /// Remove an account from the database
-    ///
-    /// The function returns an error if the account has some transaction
-    /// associated to it.
-    pub fn remove_account(&mut self, account_name: account::AccountName) -> Result<(), Error> {
+    fn delete_account(&mut self, account_name: account::AccountName) -> Result<(), Error> {
         let Some(account) = self.accounts.get(&account_name) else {
             return Err(Error::UnknownAccount(account_name))
         };
@@ -88,34 +414,42 @@ impl Database {
         if account.has_transactions() {
             return Err(Error::AccountHasTransactions(account_name))
         }
+        if let Some(archive) = &self.archive {
+            if archive.uses_account(&account_name)? {
+                return Err(Error::AccountHasTransactions(account_name))
+            }
+        }
 
         self.accounts.remove(&account_name);
 
         Ok(())
     }
 
-    /// Add a new transaction to the database
-    pub fn add_transaction(&mut self, new_trns: transaction::Transaction) -> Result<(), Error> {
+    fn insert_transaction(&mut self, new_trns: transaction::Transaction) -> Result<(), Error> {
         let transaction_id = new_trns.generate_id();
-        if self.transactions.get(&transaction_id).is_some() {
+        if self.transactions.contains_key(&transaction_id) {
             return Err(Error::TransactionIdInUse(transaction_id));
         }
+        if let Some(archive) = &self.archive {
+            if archive.contains(&new_trns)? {
+                return Err(Error::TransactionIdInUse(transaction_id));
+            }
+        }
 
         for account_name in new_trns.get_associated_accounts() {
-            if self.accounts.get(account_name).is_none() {
+            if !self.accounts.contains_key(account_name) {
                 return Err(Error::UnknownAccount(account_name.to_owned()));
             }
         }
 
-        if !&self.get_transaction_balance(&new_trns).is_zero() {
+        if !&self.get_transaction_balance(&new_trns)?.is_zero() {
             return Err(Error::UnbalancedTransaction)
         }
 
         for account_name in new_trns.get_associated_accounts() {
-            self.accounts
-                .get_mut(account_name)
-                .unwrap()
-                .add_transaction(transaction_id);
+            if let Some(account) = self.accounts.get_mut(account_name) {
+                account.add_transaction(transaction_id);
+            }
         }
 
         self.transactions.insert(transaction_id, new_trns);
@@ -123,57 +457,93 @@ impl Database {
         Ok(())
     }
 
-    pub fn modify_transaction(&mut self) -> Result<(), Error> {
-        unimplemented!();
-    }
-
-    /// Remove a transaction from the database
-    pub fn remove_transaction(&mut self, transaction_id: transaction::TransactionId) -> Result<(), Error> {
+    fn delete_transaction(&mut self, transaction_id: transaction::TransactionId) -> Result<(), Error> {
         let Some(transaction) = self.transactions.get(&transaction_id) else {
             return Err(Error::UnknownTransaction(transaction_id))
         };
 
+        // Check everything before changing anything, so that an error does
+        // not leave the database half modified
         for account_name in transaction.get_associated_accounts() {
-            let account = self.accounts.get_mut(account_name).unwrap();
-            account.remove_transaction(&transaction_id)
-                .map_err(|_| Error::AccountNotAssociatedWithTransaction(
-                        (account_name.to_owned(), transaction_id)
-                    )
-                )?;
+            let account = self.accounts
+                .get(account_name)
+                .ok_or_else(|| Error::UnknownAccount(account_name.to_owned()))?;
+            if !account.get_transaction_ids().any(|id| id == &transaction_id) {
+                return Err(Error::AccountNotAssociatedWithTransaction(
+                    (account_name.to_owned(), transaction_id)
+                ))
+            }
+        }
+
+        for account_name in transaction.get_associated_accounts() {
+            if let Some(account) = self.accounts.get_mut(account_name) {
+                account.remove_transaction(&transaction_id)?;
+            }
+        }
+        self.transactions.remove(&transaction_id);
+
+        Ok(())
+    }
+
+    /// Fail if only some transactions are loaded and the interval is not in their dates
+    fn check_loaded(&self, start: Option<&datetime::DateTime>, end: Option<&datetime::DateTime>) -> Result<(), Error> {
+        if let Some((first, last)) = self.loaded_range {
+            let after_first = first.is_none_or(|first| start.is_some_and(|start| start >= &first));
+            let before_last = last.is_none_or(|last| end.is_some_and(|end| end <= &last));
+            if !(after_first && before_last) {
+                return Err(Error::NotLoaded(first, last))
+            }
         }
         Ok(())
     }
 
     /// Compute the variation of money in an account in the specified time
     /// interval
+    ///
+    /// If only some of the transactions were loaded, the interval must be
+    /// within their dates.
     pub fn get_account_balance(
         &self,
         account_name: &account::AccountName,
         start_date: Option<datetime::DateTime>,
         end_date: Option<datetime::DateTime>,
-    ) -> Result<money::Amount, &'static str> {
-        let total_amount: money::Amount = self.accounts
+    ) -> Result<money::Amount, Error> {
+        self.check_loaded(start_date.as_ref(), end_date.as_ref())?;
+        let mut total_amount = money::Amount::default();
+
+        let account = self.accounts
             .get(account_name)
-            .ok_or("Account not found")?
-            .get_transaction_ids()
-            .map(|id| self.transactions.get(id).unwrap())
-            .filter(|trns| {
-                start_date.as_ref().map_or(true, |date| trns.get_datetime() >= date)
-                &&
-                end_date.as_ref().map_or(true, |date| trns.get_datetime() <= date)
-            })
-            .map(|trns: &transaction::Transaction| {
-                let output: &money::Amount = trns.get_amount(account_name).unwrap();
-                output
-            }).fold(money::Amount::default(), |acc, x| acc + x);
+            .ok_or_else(|| Error::UnknownAccount(account_name.to_owned()))?;
+
+        for id in account.get_transaction_ids() {
+            let trns = self.transactions
+                .get(id)
+                .ok_or(Error::UnknownTransaction(*id))?;
+            if trns.get_datetime().is_between(start_date.as_ref(), end_date.as_ref()) {
+                total_amount = total_amount + trns.get_amount(account_name)?;
+            }
+        }
+
+        if let Some(archive) = &self.archive {
+            let archived = archive.get_balance(account_name, start_date.as_ref(), end_date.as_ref())?;
+            total_amount = total_amount + &archived;
+        }
 
         Ok(total_amount)
     }
 
-    pub fn get_transaction_balance(&self, transaction: &transaction::Transaction) -> money::Amount {
+    /// Compute how unbalanced a transaction is, which must be zero for it
+    /// to be accepted in the database
+    ///
+    /// Returns an error if the transaction refers to an account that is not
+    /// in the database.
+    pub fn get_transaction_balance(&self, transaction: &transaction::Transaction) -> Result<money::Amount, Error> {
         let mut total_balance = money::Amount::default();
         for (account_name, amount) in transaction.get_amounts() {
-            match self.accounts.get(account_name).unwrap().get_account_type() {
+            let account = self.accounts
+                .get(account_name)
+                .ok_or_else(|| Error::UnknownAccount(account_name.to_owned()))?;
+            match account.get_account_type() {
                 account::AccountType::Asset => {
                     total_balance = total_balance + amount;
                 },
@@ -182,70 +552,156 @@ impl Database {
                 }
             }
         }
-        total_balance
+        Ok(total_balance)
     }
 
+    /// Ids of the transactions in memory, which do not include the archived
+    /// years of a [`yearly`] database
     pub fn get_transaction_ids(&self) -> impl Iterator<Item=&transaction::TransactionId> {
         self.transactions.keys()
     }
 
-    pub fn get_transaction(&self, id: &transaction::TransactionId) -> &transaction::Transaction {
-        self.transactions.get(id).unwrap()
+    /// Get a transaction in memory, see
+    /// [`get_transaction_ids`](Database::get_transaction_ids)
+    pub fn get_transaction(&self, id: &transaction::TransactionId) -> Option<&transaction::Transaction> {
+        self.transactions.get(id)
+    }
+
+    /// Get a transaction, reading the archived years if it is not in memory
+    ///
+    /// Returns an error if an archived year cannot be read.
+    pub fn find_transaction(&self, id: &transaction::TransactionId) -> Result<Option<&transaction::Transaction>, Error> {
+        match (self.transactions.get(id), &self.archive) {
+            (Some(transaction), _) => Ok(Some(transaction)),
+            (None, Some(archive)) => archive.get_transaction(id),
+            (None, None) => Ok(None),
+        }
     }
 
     pub fn get_account_names(&self) -> impl Iterator<Item=&account::AccountName> {
         self.accounts.keys()
     }
 
-    pub fn get_account(&self, name: &account::AccountName) -> &account::Account {
-        self.accounts.get(name).unwrap()
+    pub fn get_account(&self, name: &account::AccountName) -> Option<&account::Account> {
+        self.accounts.get(name)
     }
 }
 
 impl Database {
-    pub fn save_to_file(&self, filename: &str) {
-        let text = serde_json::to_string_pretty(self).unwrap();
-
-        let mut file = std::fs::File::create(filename).unwrap();
-
-        file.write_all(text.as_bytes()).unwrap();
+    /// Save the database, keeping backups of the previous versions
+    ///
+    /// Fails if another program holds the lock of the file.
+    pub fn save_to_file<P: AsRef<Path>>(&self, filename: P) -> Result<(), FileError> {
+        file::LockedFile::lock(filename)?.save(self)
     }
 
-    pub fn read_from_file(filename: &str) -> Result<Self, &'static str> {
-        let text = std::fs::read_to_string(filename).map_err(|_| "The file does not exist")?;
-
-        let mut database: Database =
-            serde_json::from_str(&text).map_err(|_| "Erros in deserialization!!")?;
+    /// Load a database, upgrading it to the current format if it is older
+    ///
+    /// The file itself is not modified until the database is saved.
+    pub fn read_from_file<P: AsRef<Path>>(filename: P) -> Result<Self, FileError> {
+        let path = filename.as_ref();
+        let mut database = Self::parse_file(path)?;
 
         database
             .build_account_transaction_map()
-            .map_err(|_| "Whatever")?;
+            .map_err(|source| FileError::Database { path: path.to_owned(), source })?;
 
         Ok(database)
     }
 
+    /// Read a database file without checking that it is consistent, for
+    /// files that only hold part of a database
+    pub(crate) fn parse_file(path: &Path) -> Result<Self, FileError> {
+        let text = std::fs::read_to_string(path).map_err(|e| FileError::io(path, e))?;
+
+        #[derive(Deserialize)]
+        struct Header {
+            #[serde(default)]
+            version: u32,
+        }
+        let header: Header = serde_json::from_str(&text).map_err(|e| FileError::format(path, e))?;
+
+        // Reading the text directly keeps the positions of the errors, so the
+        // raw JSON is only used when there is something to upgrade
+        if header.version == migration::CURRENT_VERSION {
+            return serde_json::from_str(&text).map_err(|e| FileError::format(path, e))
+        }
+        let (upgraded, _) = Self::upgrade(path, &text)?;
+        serde_json::from_value(upgraded).map_err(|e| {
+            // The same problem is usually in the original text, where it
+            // can be located, unless what fails there is the old layout
+            match serde_json::from_str::<Database>(&text) {
+                Err(located) if located.to_string().starts_with(&e.to_string()) => FileError::format(path, located),
+                _ => FileError::format(path, e),
+            }
+        })
+    }
+
+    /// Upgrade a database file to the current format, keeping a backup of
+    /// the old one
+    ///
+    /// With `dry_run` the file is left untouched, and the report tells what
+    /// would change.
+    pub fn migrate_file<P: AsRef<Path>>(filename: P, dry_run: bool) -> Result<migration::Report, FileError> {
+        let path = filename.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| FileError::io(path, e))?;
+        let (upgraded, report) = Self::upgrade(path, &text)?;
+
+        if !dry_run && !report.is_up_to_date() {
+            // Going through the database checks that the upgraded file loads
+            let mut database: Database =
+                serde_json::from_value(upgraded).map_err(|e| FileError::format(path, e))?;
+            database
+                .build_account_transaction_map()
+                .map_err(|source| FileError::Database { path: path.to_owned(), source })?;
+            database.save_to_file(path)?;
+        }
+        Ok(report)
+    }
+
+    fn upgrade(path: &Path, text: &str) -> Result<(serde_json::Value, migration::Report), FileError> {
+        let mut raw: serde_json::Value = serde_json::from_str(text).map_err(|e| FileError::format(path, e))?;
+        let report = migration::migrate(&mut raw)
+            .map_err(|source| FileError::Migration { path: path.to_owned(), source })?;
+        Ok((raw, report))
+    }
+
+    /// Link every account with the transactions that affect it
+    ///
+    /// Returns an error listing all the references to accounts that do not
+    /// exist, if there is any.
     fn build_account_transaction_map(&mut self) -> Result<(), Error> {
+        let mut missing_accounts = Vec::new();
+
         for (trns_id, transaction) in self.transactions.iter() {
             for acc_name in transaction.get_associated_accounts() {
-                self.accounts
-                    .get_mut(acc_name)
-                    .unwrap()
-                    .add_transaction(*trns_id);
+                match self.accounts.get_mut(acc_name) {
+                    Some(account) => account.add_transaction(*trns_id),
+                    None => missing_accounts.push((*trns_id, acc_name.to_owned())),
+                }
             }
         }
 
-        Ok(())
+        if missing_accounts.is_empty() {
+            Ok(())
+        } else {
+            missing_accounts.sort_by(|a, b| (a.0.0, a.1.as_ref()).cmp(&(b.0.0, b.1.as_ref())));
+            Err(Error::MissingAccounts(missing_accounts))
+        }
     }
 }
 
-fn serialize_transactions<S: Serializer>(
+fn serialize_transactions<S>(
     map: &HashMap<transaction::TransactionId, transaction::Transaction>,
     serializer: S,
 ) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
-    serializer.collect_seq(map.iter().map(|(_, x)| x))
+    // Sorted, so that saving the same database always gives the same file
+    let mut transactions = map.iter().collect::<Vec<_>>();
+    transactions.sort_by_key(|(id, trns)| (*trns.get_datetime(), id.0));
+    serializer.collect_seq(transactions.into_iter().map(|(_, trns)| trns))
 }
 
 fn deserialize_transactions<'de, D>(
@@ -260,7 +716,7 @@ where
         type Value = HashMap<transaction::TransactionId, transaction::Transaction>;
 
         fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-            write!(f, "Whatttt")
+            write!(f, "a list of transactions")
         }
 
         fn visit_seq<S>(self, mut sequence: S) -> Result<Self::Value, S::Error>
@@ -279,14 +735,16 @@ where
     deserializer.deserialize_seq(SeqVisitor)
 }
 
-fn serialize_accounts<S: Serializer>(
+fn serialize_accounts<S>(
     map: &HashMap<account::AccountName, account::Account>,
     serializer: S,
 ) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
-    serializer.collect_seq(map.iter().map(|(_, x)| x))
+    let mut accounts = map.values().collect::<Vec<_>>();
+    accounts.sort_by_key(|account| account.get_name());
+    serializer.collect_seq(accounts)
 }
 
 fn deserialize_accounts<'de, D>(
@@ -301,7 +759,7 @@ where
         type Value = HashMap<account::AccountName, account::Account>;
 
         fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-            write!(f, "Whatttt")
+            write!(f, "a list of accounts")
         }
 
         fn visit_seq<S>(self, mut sequence: S) -> Result<Self::Value, S::Error>
@@ -1,26 +1,54 @@
-use serde::{Deserialize, Serialize};
+use serde::{
+    de::{MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer
+};
 
 use std::{
     str::FromStr,
-    collections::HashMap,
+    collections::BTreeMap,
     fmt::Write
 };
 
 const SEP_THOUSAND: &str = ",";
 const SEP_DEC: &str = ".";
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Default, Clone)]
+/// Separator placed between columns by [`AmountAlignment`]
+const COLUMN_SEP: &str = "   ";
+
+#[derive(Debug, PartialEq, Eq, Default, Clone)]
 pub struct Amount {
-    amounts: HashMap<Currency, Number>
+    amounts: BTreeMap<Currency, Number>
 }
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Default, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Default, Hash, Clone)]
 pub struct Currency(pub String);
 
 impl Currency {
     pub fn new(name: &str) -> Self {
         Self(name.to_owned())
     }
+
+    /// The usual symbol of the currency, if it has one that we know of
+    pub fn symbol(&self) -> Option<&'static str> {
+        match self.0.as_str() {
+            "EUR" => Some("€"),
+            "USD" => Some("$"),
+            "GBP" => Some("£"),
+            "JPY" => Some("¥"),
+            "SEK" | "NOK" | "DKK" => Some("kr"),
+            "PLN" => Some("zł"),
+            _ => None
+        }
+    }
+
+    /// The text used to identify the currency next to a number
+    pub fn label(&self, use_symbol: bool) -> &str {
+        if use_symbol {
+            self.symbol().unwrap_or(&self.0)
+        } else {
+            &self.0
+        }
+    }
 }
 
 impl From<&str> for Currency {
@@ -39,38 +67,111 @@ impl std::hash::Hash for Amount {
 }
 
 impl FromStr for Amount {
-    type Err= &'static str;
+    type Err = AmountError;
     fn from_str(input: &str) -> Result<Self, Self::Err> {
-        let mut output = HashMap::new();
-        
-        let splitted_input = input.split(", ");
-        
-        for element in splitted_input {
+        let mut output = BTreeMap::new();
+
+        let mut position = 0;
+        for element in input.split(", ") {
+            let element_position = position;
+            position += element.len() + 2;
+
             let mut split = element.split(' ');
-            let number: Number = split.next().ok_or("Format issue")?.parse().map_err(|_| "Parse error")?;
+            let number_text = split.next().unwrap_or_default();
+            let number: Number = number_text.parse().map_err(|source| AmountError::Number {
+                position: element_position,
+                source
+            })?;
             if number.is_zero() { continue }
 
-            let currency = split.next().ok_or("Format issue")?.into();
-
-            if split.next().is_some() {
-                return Err("What the hell???!")
+            let currency: Currency = split
+                .next()
+                .filter(|x| !x.is_empty())
+                .ok_or_else(|| AmountError::MissingCurrency {
+                    text: element.to_owned(),
+                    position: element_position + number_text.len()
+                })?
+                .into();
+
+            if let Some(extra) = split.next() {
+                return Err(AmountError::UnexpectedText {
+                    text: extra.to_owned(),
+                    position: element_position + number_text.len() + currency.0.len() + 2
+                })
             }
 
-            let overwritten = output.insert(currency, number).is_some();
-            if overwritten {
-                return Err("Same currency introduced twice")
+            if output.contains_key(&currency) {
+                return Err(AmountError::RepeatedCurrency { currency, position: element_position })
             }
+            output.insert(currency, number);
         }
 
         Ok(Amount { amounts: output })
     }
 }
 
+/// Everything that can go wrong when reading an [`Amount`]
+///
+/// The positions are byte offsets in the text that was read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AmountError {
+    /// One of the numbers could not be read
+    Number { position: usize, source: NumberError },
+    /// A number is not followed by its currency
+    MissingCurrency { text: String, position: usize },
+    /// There is something after the currency
+    UnexpectedText { text: String, position: usize },
+    /// The same currency appears twice
+    RepeatedCurrency { currency: Currency, position: usize },
+}
+
+impl std::fmt::Display for AmountError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AmountError::Number { position, source } => {
+                write!(f, "invalid amount at position {}: {}", position, source)
+            },
+            AmountError::MissingCurrency { text, position } => {
+                write!(f, "missing currency after \"{}\" at position {}", text, position)
+            },
+            AmountError::UnexpectedText { text, position } => {
+                write!(f, "unexpected \"{}\" at position {}, amounts look like \"12.50 EUR, -3 SEK\"", text, position)
+            },
+            AmountError::RepeatedCurrency { currency, position } => {
+                write!(f, "currency {} repeated at position {}", currency.0, position)
+            },
+        }
+    }
+}
+
+impl std::error::Error for AmountError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AmountError::Number { source, .. } => Some(source),
+            _ => None
+        }
+    }
+}
+
 impl Amount {
     pub fn is_zero(&self) -> bool {
         self.amounts.is_empty()
     }
 
+    /// Write the amount without thousand separators, in a way that can be
+    /// parsed back
+    pub fn to_plain_string(&self) -> String {
+        if self.is_zero() {
+            return String::from("0")
+        }
+
+        self.amounts
+            .iter()
+            .map(|(currency, number)| format!("{} {}", number.to_plain_string(), currency.0))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
     pub fn currencies(&self) -> Vec<Currency> {
         self.amounts.keys().cloned().collect()
     }
@@ -78,6 +179,89 @@ impl Amount {
     pub fn in_currency(&self, currency: &Currency) -> Number {
         self.amounts.get(currency).map_or(Number::default(), |x| x.clone())
     }
+
+    /// Write the amount following the given format
+    ///
+    /// A zero amount is written as `0`.
+    pub fn format_with(&self, format: &AmountFormat) -> String {
+        if self.is_zero() {
+            return String::from("0")
+        }
+
+        format
+            .sort_currencies(self.currencies())
+            .iter()
+            .map(|currency| format!(
+                "{} {}",
+                self.amounts[currency].format_sign(&format.sign),
+                currency.label(format.symbols)
+            ))
+            .collect::<Vec<_>>()
+            .join(&format.separator)
+    }
+}
+
+/// The order in which the currencies of an amount are displayed
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum CurrencyOrder {
+    /// Sorted by currency code
+    #[default]
+    Alphabetical,
+    /// The reporting currency goes first, the rest sorted by currency code
+    ReportingFirst(Currency),
+    /// The listed currencies go first and in the given order, the rest
+    /// sorted by currency code
+    Custom(Vec<Currency>),
+}
+
+/// How the sign of a number is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SignStyle {
+    /// `-12.00` and `12.00`
+    #[default]
+    Minus,
+    /// `(12.00)` and `12.00`, as usual in accounting
+    Parentheses,
+    /// `-12.00` and `+12.00`
+    Explicit,
+}
+
+/// Everything that can be configured when writing an [`Amount`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AmountFormat {
+    pub order: CurrencyOrder,
+    pub symbols: bool,
+    pub sign: SignStyle,
+    pub separator: String,
+}
+
+impl Default for AmountFormat {
+    fn default() -> Self {
+        Self {
+            order: CurrencyOrder::default(),
+            symbols: false,
+            sign: SignStyle::default(),
+            separator: String::from(", ")
+        }
+    }
+}
+
+impl AmountFormat {
+    /// Sort the currencies following the order of the format
+    pub fn sort_currencies(&self, mut currencies: Vec<Currency>) -> Vec<Currency> {
+        let priority: &[Currency] = match &self.order {
+            CurrencyOrder::Alphabetical => &[],
+            CurrencyOrder::ReportingFirst(currency) => std::slice::from_ref(currency),
+            CurrencyOrder::Custom(order) => order,
+        };
+
+        currencies.sort_by_cached_key(|currency| (
+            priority.iter().position(|x| x == currency).unwrap_or(priority.len()),
+            currency.clone()
+        ));
+        currencies.dedup();
+        currencies
+    }
 }
 
 impl std::ops::Add<&Amount> for Amount {
@@ -127,54 +311,137 @@ impl std::ops::Neg for &Amount {
 
 impl std::fmt::Display for Amount {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut output = String::new();
-        for (currency, number) in self.amounts.iter() {
-            write!(output, "{} {}, ", number, currency.0)?;
+        self.format_with(&AmountFormat::default()).fmt(f)
+    }
+}
+
+/// Amounts are stored as text, like `"-132.50 SEK, 15.00 EUR"`
+///
+/// When reading, a map from currency to number (`{"SEK": "-132.50"}`) is
+/// accepted as well. The old layout is upgraded by the
+/// [`migration`](crate::data::migration) of the file.
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_plain_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct AmountVisitor;
+
+        impl<'de> Visitor<'de> for AmountVisitor {
+            type Value = Amount;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "an amount like \"-132.50 SEK\" or a map from currency to number")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, text: &str) -> Result<Self::Value, E> {
+                text.parse().map_err(E::custom)
+            }
+
+            fn visit_map<M: MapAccess<'de>>(self, mut map: M) -> Result<Self::Value, M::Error> {
+                let mut amounts = BTreeMap::new();
+
+                while let Some(key) = map.next_key::<String>()? {
+                    amounts.insert(Currency(key), map.next_value::<Number>()?);
+                }
+                amounts.retain(|_, number| !number.is_zero());
+
+                Ok(Amount { amounts })
+            }
         }
-        output.fmt(f)
+
+        deserializer.deserialize_any(AmountVisitor)
+    }
+}
+
+/// Numbers are stored as text, like `"-132.50"`
+///
+/// When reading, JSON numbers are accepted as well.
+impl Serialize for Number {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_plain_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Number {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct NumberVisitor;
+
+        impl<'de> Visitor<'de> for NumberVisitor {
+            type Value = Number;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "a number like \"-132.50\"")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, text: &str) -> Result<Self::Value, E> {
+                text.parse().map_err(E::custom)
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, value: i64) -> Result<Self::Value, E> {
+                Ok(Number { value, n_decimals: 0 })
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, value: u64) -> Result<Self::Value, E> {
+                let value = i64::try_from(value).map_err(E::custom)?;
+                Ok(Number { value, n_decimals: 0 })
+            }
+
+            fn visit_f64<E: serde::de::Error>(self, value: f64) -> Result<Self::Value, E> {
+                value.to_string().parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(NumberVisitor)
     }
 }
 
-#[derive(Deserialize, Serialize, Hash, PartialEq, Eq, Debug, Default, Clone)]
+#[derive(Hash, PartialEq, Eq, Debug, Default, Clone)]
 pub struct Number {
     value: i64,
     n_decimals: u32,
 }
 
 impl FromStr for Number {
-    type Err = &'static str;
+    type Err = NumberError;
     fn from_str(str: &str) -> Result<Self, Self::Err> {
-        let mut split = if str.contains(',') {
-            str.split(',')
-        } else if str.contains('.') {
-            str.split('.')
-        } else {
-            return Ok(Number {
-                value: str.parse().map_err(|_| "Unparsable")?,
-                n_decimals: 0,
-            });
-        };
-
-        let units: i64 = split
-            .next()
-            .ok_or("What!")?
-            .parse()
-            .map_err(|_| "Unparsable")?;
+        // It should look like -1234.56, with either a point or a comma as
+        // the decimal separator
+        let unparsable = |position| NumberError::Unparsable { text: str.to_owned(), position };
 
-        let sign = if units >= 0 { 1 } else { -1};
-
-        let decimals_string = split.next().ok_or("What!")?.trim_end_matches('0');
-
-        let n_decimals = decimals_string.len() as u32;
+        let (sign, body) = match str.strip_prefix('-') {
+            Some(body) => (-1, body),
+            None => (1, str.strip_prefix('+').unwrap_or(str)),
+        };
+        let offset = str.len() - body.len();
 
-        let mut value = units * 10i64.pow(n_decimals);
-        let decimals = if n_decimals == 0 {
-            0
-        } else {
-            decimals_string.parse::<i64>().map_err(|_| "Unparsable")? * sign
+        let (units, decimals) = match body.find([',', '.']) {
+            Some(index) => (&body[..index], &body[index + 1..]),
+            None => (body, ""),
         };
+        if units.is_empty() {
+            return Err(unparsable(offset))
+        }
+        if let Some(index) = units.find(|c: char| !c.is_ascii_digit()) {
+            return Err(unparsable(offset + index))
+        }
+        if let Some(index) = decimals.find(|c: char| !c.is_ascii_digit()) {
+            return Err(unparsable(offset + units.len() + 1 + index))
+        }
+
+        let decimals = decimals.trim_end_matches('0');
+        let n_decimals = decimals.len() as u32;
 
-        value += decimals;
+        let overflow = || NumberError::Overflow(str.to_owned());
+        let value = format!("{}{}", units, decimals)
+            .parse::<i64>()
+            .map_err(|_| overflow())?
+            .checked_mul(sign)
+            .ok_or_else(overflow)?;
+        10i64.checked_pow(n_decimals).ok_or_else(overflow)?;
 
         Ok(Number {
             value,
@@ -183,6 +450,29 @@ impl FromStr for Number {
     }
 }
 
+/// Everything that can go wrong when reading a [`Number`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NumberError {
+    /// The text is not a number, the position is the byte offset of the
+    /// first character that does not fit
+    Unparsable { text: String, position: usize },
+    /// The number has too many digits to be stored
+    Overflow(String),
+}
+
+impl std::fmt::Display for NumberError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NumberError::Unparsable { text, position } => {
+                write!(f, "\"{}\" is not a number (unexpected character at position {})", text, position)
+            },
+            NumberError::Overflow(text) => write!(f, "\"{}\" has too many digits", text),
+        }
+    }
+}
+
+impl std::error::Error for NumberError {}
+
 impl std::ops::Add for Number {
     type Output = Number;
     fn add(self, other: Number) -> Self::Output {
@@ -275,31 +565,68 @@ impl Number {
         )
     }
 
+    /// Write the number without thousand separators, in a way that can be
+    /// parsed back
+    pub fn to_plain_string(&self) -> String {
+        let str_value = format!("{:0>1$}", self.value.abs(), self.n_decimals as usize + 1);
+        let (units, decimals) = str_value.split_at(str_value.len() - self.n_decimals as usize);
+        let sign = if self.is_nonnegative() { "" } else { "-" };
+
+        format!("{}{}{}{:0<2}", sign, units, SEP_DEC, decimals)
+    }
+
+    /// Write the number with the given sign style
+    pub fn format_sign(&self, style: &SignStyle) -> String {
+        let (opening, closing) = self.sign_strings(style);
+        let (_, value, decimals) = self.get_strings();
+
+        format!("{}{:0<1}{}{:0<2}{}", opening, value, SEP_DEC, decimals, closing)
+    }
+
+    /// The texts written before and after the number to show its sign
+    fn sign_strings(&self, style: &SignStyle) -> (&'static str, &'static str) {
+        match (style, self.is_nonnegative()) {
+            (SignStyle::Minus, true) => ("", ""),
+            (SignStyle::Minus, false) => ("-", ""),
+            (SignStyle::Parentheses, true) => ("", ""),
+            (SignStyle::Parentheses, false) => ("(", ")"),
+            (SignStyle::Explicit, true) => ("+", ""),
+            (SignStyle::Explicit, false) => ("-", ""),
+        }
+    }
+
     pub fn format(&self, alignment: &NumberAlignment) -> String {
-        let (mut sign, units, mut decimals) = self.get_strings();
+        let (opening, closing) = self.sign_strings(&alignment.sign);
+        let (_, units, decimals) = self.get_strings();
 
-        if sign.is_empty() { sign = " ".to_string() };
-        decimals = format!("{:0>2}", decimals);
+        let sign = if opening.is_empty() { " " } else { opening };
+        let closing = if alignment.sign == SignStyle::Parentheses && closing.is_empty() {
+            " "
+        } else {
+            closing
+        };
+        let units = if units.is_empty() { String::from("0") } else { units };
+        let decimals = format!("{:0<2}", decimals);
 
         if alignment.minus_alignment {
             format!(
-                "{}{:>3$}.{:<4$}",
-                sign, units, decimals,
+                "{}{:>5$}{}{:<6$}{}",
+                sign, units, SEP_DEC, decimals, closing,
                 alignment.unit_slots, alignment.decimal_slots
             )
         } else {
             format!(
-                "{:>2$}.{:<3$}",
-                format!("{}{}", sign, units), decimals,
+                "{:>4$}{}{:<5$}{}",
+                format!("{}{}", sign.trim(), units), SEP_DEC, decimals, closing,
                 alignment.unit_slots + 1, alignment.decimal_slots
             )
-
         }
     }
 }
 
 pub struct NumberAlignment {
     minus_alignment: bool,
+    sign: SignStyle,
     unit_slots: usize,
     decimal_slots: usize,
 }
@@ -311,47 +638,97 @@ impl NumberAlignment {
         for number in numbers {
             let (_, units, decimals) = number.get_strings();
             let n_decimals = decimals.len().max(2);
-            let n_units = units.len();
+            let n_units = units.len().max(1);
             unit_slots = unit_slots.max(n_units);
             decimal_slots = decimal_slots.max(n_decimals);
-            println!("Number: {}, n_units: {}", number, n_units);
         }
 
         Self {
             minus_alignment: true,
+            sign: SignStyle::Minus,
             unit_slots,
             decimal_slots
         }
     }
+
+    pub fn with_sign_style(mut self, sign: SignStyle) -> Self {
+        self.sign = sign;
+        self
+    }
+
+    /// The number of characters taken by every number formatted with this
+    /// alignment
+    pub fn width(&self) -> usize {
+        let closing = if self.sign == SignStyle::Parentheses { 1 } else { 0 };
+        1 + self.unit_slots + SEP_DEC.len() + self.decimal_slots + closing
+    }
+}
+
+/// Column layout to show several amounts one below the other, with one
+/// column per currency and the decimal separators aligned
+pub struct AmountAlignment {
+    columns: Vec<(Currency, NumberAlignment)>,
+    format: AmountFormat,
+    label_width: usize,
 }
 
-/*
 impl AmountAlignment {
+    pub fn from_amounts<'a, T: IntoIterator<Item=&'a Amount>>(amounts: T, format: AmountFormat) -> Self {
+        let amounts = amounts.into_iter().collect::<Vec<_>>();
+
+        let currencies = format.sort_currencies(
+            amounts.iter().flat_map(|amount| amount.currencies()).collect()
+        );
+
+        let columns = currencies.into_iter().map(|currency| {
+            let alignment = NumberAlignment::from_numbers(
+                amounts.iter().map(|amount| amount.in_currency(&currency))
+            ).with_sign_style(format.sign);
+            (currency, alignment)
+        }).collect::<Vec<_>>();
+
+        let label_width = columns.iter().map(|(currency, _)| {
+            currency.label(format.symbols).chars().count()
+        }).max().unwrap_or(0);
+
+        Self { columns, format, label_width }
+    }
+
+    pub fn currencies(&self) -> impl Iterator<Item=&Currency> {
+        self.columns.iter().map(|(currency, _)| currency)
+    }
+
+    /// The number of characters taken by every formatted amount
+    pub fn width(&self) -> usize {
+        self.columns.iter().map(|(_, alignment)| {
+            alignment.width() + 1 + self.label_width
+        }).sum::<usize>() + COLUMN_SEP.len() * self.columns.len().saturating_sub(1)
+    }
+
+    /// Write the amount in the columns, leaving blank the currencies the
+    /// amount does not have
     pub fn format_amount(&self, amount: &Amount) -> String {
         let mut output = String::new();
-        for (currency, units_size, decimal_size) in self.0.iter() {
+        for (index, (currency, alignment)) in self.columns.iter().enumerate() {
+            if index != 0 {
+                output.push_str(COLUMN_SEP);
+            }
             let number = amount.in_currency(currency);
-            let str_number = number.to_string();
-            let (units, decimals) = str_number.split_once(',').expect("Something with number formatting");
             if number.is_zero() {
-                write!(output, "{:<0$}", units_size + decimal_size + currency.0.len() + 2).unwrap();
-
+                write!(output, "{:1$}", "", alignment.width() + 1 + self.label_width).unwrap();
             } else {
                 write!(
                     output,
-                    "{:>3$}.{:<4$} {}",
-                    units,
-                    decimals,
-                    currency.0,
-                    units_size,
-                    decimal_size
-                ).unwrap()
+                    "{} {:<2$}",
+                    number.format(alignment),
+                    currency.label(self.format.symbols),
+                    self.label_width
+                ).unwrap();
             }
         }
         output
     }
 }
-*/
 
 #[cfg(test)]
 mod test {
@@ -366,15 +743,29 @@ mod test {
 
             println!("{:?}", second_amount);
 
-            let mut amounts = HashMap::new();
+            let mut amounts = BTreeMap::new();
             amounts.insert("EUR".into(), Number{ value: 128543, n_decimals: 3 });
             amounts.insert("PUM".into(), Number{ value: -670004, n_decimals: 4 });
             assert_eq!(
                 Ok(Amount{ amounts }),
                 first_amount
             );
-            assert!(second_amount.is_err());
-            assert!(third_amount.is_err());
+            assert_eq!(
+                Err(AmountError::UnexpectedText { text: String::from("2"), position: 6 }),
+                second_amount
+            );
+            assert_eq!(
+                Err(AmountError::RepeatedCurrency { currency: "EUR".into(), position: 16 }),
+                third_amount
+            );
+            assert_eq!(
+                Err(AmountError::MissingCurrency { text: String::from("12"), position: 9 }),
+                Amount::from_str("4 EUR, 12")
+            );
+            assert!(matches!(
+                Amount::from_str("4 EUR, 1x2 SEK"),
+                Err(AmountError::Number { position: 7, source: NumberError::Unparsable { position: 1, .. } })
+            ));
         }
 
         #[test]
@@ -413,6 +804,69 @@ mod test {
                 final_amount
             );
         }
+
+        #[test]
+        fn displaying() {
+            let amount = Amount::from_str("-43.2 SEK, 132 EUR, 0.5 USD").unwrap();
+            assert_eq!("132.00 EUR, -43.20 SEK, 0.50 USD", amount.to_string());
+            assert_eq!("0", Amount::default().to_string());
+
+            let format = AmountFormat {
+                order: CurrencyOrder::ReportingFirst("SEK".into()),
+                symbols: true,
+                sign: SignStyle::Parentheses,
+                separator: String::from(" | ")
+            };
+            assert_eq!("(43.20) kr | 132.00 € | 0.50 $", amount.format_with(&format));
+
+            let format = AmountFormat {
+                order: CurrencyOrder::Custom(vec!["USD".into(), "SEK".into()]),
+                sign: SignStyle::Explicit,
+                ..AmountFormat::default()
+            };
+            assert_eq!("+0.50 USD, -43.20 SEK, +132.00 EUR", amount.format_with(&format));
+        }
+
+        #[test]
+        fn aligning() {
+            let amounts = [
+                Amount::from_str("1500 SEK, -3.5 EUR").unwrap(),
+                Amount::from_str("-2.125 SEK").unwrap(),
+                Amount::from_str("12 EUR").unwrap(),
+            ];
+            let alignment = AmountAlignment::from_amounts(amounts.iter(), AmountFormat::default());
+
+            assert_eq!(
+                vec![&Currency::new("EUR"), &Currency::new("SEK")],
+                alignment.currencies().collect::<Vec<_>>()
+            );
+            let lines = amounts.iter().map(|x| alignment.format_amount(x)).collect::<Vec<_>>();
+            assert_eq!("- 3.50 EUR    1,500.00  SEK", lines[0]);
+            assert_eq!("             -    2.125 SEK", lines[1]);
+            assert_eq!(" 12.00 EUR                 ", lines[2]);
+            assert!(lines.iter().all(|x| x.chars().count() == alignment.width()));
+        }
+
+        #[test]
+        fn serializing() {
+            let amount = Amount::from_str("-132.5 SEK, 1234567.125 EUR").unwrap();
+            let text = serde_json::to_string(&amount).unwrap();
+            assert_eq!(r#""1234567.125 EUR, -132.50 SEK""#, text);
+            assert_eq!(amount, serde_json::from_str(&text).unwrap());
+
+            assert_eq!(r#""0""#, serde_json::to_string(&Amount::default()).unwrap());
+            assert_eq!(Amount::default(), serde_json::from_str::<Amount>(r#""0""#).unwrap());
+        }
+
+        #[test]
+        fn deserializing_maps() {
+            let expected = Amount::from_str("-132.5 SEK, 4 EUR").unwrap();
+            assert_eq!(
+                expected,
+                serde_json::from_str::<Amount>(r#"{"SEK": "-132.50", "EUR": 4}"#).unwrap()
+            );
+
+        }
     }
     mod number {
         use super::*;
@@ -430,6 +884,31 @@ mod test {
                 Number{ value: 4, n_decimals: 3 },
                 Number::from_str("0.00400").unwrap()
             );
+            assert_eq!(
+                Number{ value: -5, n_decimals: 1 },
+                Number::from_str("-0.5").unwrap()
+            );
+            assert_eq!(
+                Number{ value: 15, n_decimals: 1 },
+                Number::from_str("+1,50").unwrap()
+            );
+            assert_eq!(
+                Err(NumberError::Unparsable { text: String::from("-12.3.4"), position: 5 }),
+                Number::from_str("-12.3.4")
+            );
+            assert_eq!(
+                Err(NumberError::Unparsable { text: String::from(".5"), position: 0 }),
+                Number::from_str(".5")
+            );
+            assert!(matches!(Number::from_str("123456789012345678901"), Err(NumberError::Overflow(_))));
+        }
+
+        #[test]
+        fn plain_printing() {
+            assert_eq!("-123456.425", Number::from_str("-123456.425").unwrap().to_plain_string());
+            assert_eq!("0.0017", Number::from_str("0.0017").unwrap().to_plain_string());
+            assert_eq!("-0.50", Number::from_str("-0.5").unwrap().to_plain_string());
+            assert_eq!("12.00", Number::from_str("12").unwrap().to_plain_string());
         }
 
         #[test]
@@ -0,0 +1,73 @@
+//! Selection of transactions, for reports and exports
+
+use crate::data::{
+    account::AccountName,
+    datespec::DateSpec,
+    datetime::DateTime,
+    tags::Tag,
+    transaction::Transaction,
+    Database,
+    Error,
+};
+
+/// Conditions that transactions must meet to be selected
+///
+/// The default query selects everything.
+#[derive(Clone, Debug, Default)]
+pub struct Query {
+    pub start: Option<DateTime>,
+    pub end: Option<DateTime>,
+    /// Only the accounts whose name starts with this, like `expense/` or
+    /// `asset/ICA_Bank`
+    pub account: Option<String>,
+    /// Tags that must all be present
+    pub tags: Vec<Tag>,
+    /// Text that must be in the name or the notes, ignoring case
+    pub text: Option<String>,
+}
+
+impl Query {
+    /// Only the transactions in the dates given, as parsed by a
+    /// [`DateParser`](crate::data::datespec::DateParser)
+    pub fn during(mut self, dates: &DateSpec) -> Self {
+        (self.start, self.end) = dates.bounds();
+        self
+    }
+
+    pub fn matches_account(&self, account_name: &AccountName) -> bool {
+        self.account.as_ref().is_none_or(|prefix| account_name.as_ref().starts_with(prefix.as_str()))
+    }
+
+    /// Whether the transaction meets every condition, affecting at least one
+    /// of the selected accounts
+    pub fn matches(&self, transaction: &Transaction) -> bool {
+        let datetime = transaction.get_datetime();
+        let text = self.text.as_ref().is_none_or(|text| {
+            let text = text.to_lowercase();
+            transaction.get_name().to_lowercase().contains(&text)
+                || transaction.get_notes().to_lowercase().contains(&text)
+        });
+
+        datetime.is_between(self.start.as_ref(), self.end.as_ref())
+            && self.tags.iter().all(|tag| transaction.get_tags().contains(tag))
+            && transaction.get_associated_accounts().any(|account| self.matches_account(account))
+            && text
+    }
+}
+
+impl Database {
+    /// The transactions selected by the query, sorted by date
+    ///
+    /// The archived years in its dates are read. If only some of the
+    /// transactions were loaded, its dates must be within theirs.
+    pub fn query(&self, query: &Query) -> Result<Vec<&Transaction>, Error> {
+        self.check_loaded(query.start.as_ref(), query.end.as_ref())?;
+        let mut transactions = self.transactions.iter().collect::<Vec<_>>();
+        if let Some(archive) = &self.archive {
+            transactions.extend(archive.transactions_between(query.start.as_ref(), query.end.as_ref())?);
+        }
+        transactions.retain(|(_, transaction)| query.matches(transaction));
+        transactions.sort_by_key(|(id, transaction)| (*transaction.get_datetime(), id.0));
+        Ok(transactions.into_iter().map(|(_, transaction)| transaction).collect())
+    }
+}
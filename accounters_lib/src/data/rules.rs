@@ -0,0 +1,461 @@
+//! Rules that fix the transactions they recognise
+//!
+//! Banks give payments names like `KORTKÖP 240131 ICA MAXI STOCKHOLM`, and
+//! importers put all of them in the same account. A [`Rule`] recognises
+//! such transactions and gives them the right counter account, tags, name
+//! and notes. The counter account must be of the same type as the one it
+//! replaces.
+//!
+//! Rules look at a transaction from its source account, the one the money
+//! comes from or goes to: the asset accounts, or the ones the rule names.
+//! They are kept in the database in order, and only the first rule that
+//! matches a transaction is applied, so specific rules go before general
+//! ones. A rule in JSON looks like this:
+//!
+//! ```json
+//! {
+//!     "name": "groceries",
+//!     "conditions": { "name": "(?i)ica (maxi|nära)", "amount": { "currency": "SEK", "max": 0 } },
+//!     "actions": { "account": "expense/food", "name": "ICA", "tags": ["groceries"] }
+//! }
+//! ```
+
+use regex::Regex;
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+use time::Weekday;
+
+use crate::data::{
+    account::{AccountName, AccountType},
+    history::Operation,
+    money::{Amount, Currency, Number},
+    tags::Tag,
+    transaction::{Transaction, TransactionId},
+    Database, Error,
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub name: String,
+    #[serde(default)]
+    pub conditions: Conditions,
+    #[serde(default)]
+    pub actions: Actions,
+}
+
+/// What a transaction must be like for a rule to apply. Every condition
+/// given must be met.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Conditions {
+    /// Pattern that the name must match, which is usually the payee
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<Pattern>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<Pattern>,
+    /// Start of the name of the source account, like `asset/ICA_Bank`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount: Option<AmountRange>,
+    /// Days of the week, like `Saturday`, of which the transaction must be
+    /// on one
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "weekday_names")]
+    pub weekdays: Vec<Weekday>,
+}
+
+/// The amounts moved through the source account that a rule accepts,
+/// where the money that leaves the account is negative
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AmountRange {
+    pub currency: Currency,
+    /// The least amount, included
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<Number>,
+    /// The greatest amount, included
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<Number>,
+}
+
+impl AmountRange {
+    pub fn contains(&self, amount: &Amount) -> bool {
+        if !amount.currencies().contains(&self.currency) {
+            return false
+        }
+        let number = amount.in_currency(&self.currency);
+        self.min.as_ref().is_none_or(|min| (number.clone() - min.clone()).is_nonnegative())
+            && self.max.as_ref().is_none_or(|max| (max.clone() - number.clone()).is_nonnegative())
+    }
+}
+
+/// The changes made to the transactions that match a rule
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Actions {
+    /// The account of the other side of the transaction, replacing the one
+    /// it had. Transactions split in several accounts keep theirs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<AccountName>,
+    /// Tags added to the transaction
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<Tag>,
+    /// The new name, which can use the groups of the name pattern, like
+    /// `$1` or `${payee}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+}
+
+/// A regular expression, stored as its text
+#[derive(Clone, Debug)]
+pub struct Pattern(pub Regex);
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Serialize for Pattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        Regex::new(&text).map(Pattern).map_err(D::Error::custom)
+    }
+}
+
+/// Days of the week written by their English name
+mod weekday_names {
+    use super::*;
+    use std::str::FromStr;
+
+    pub fn serialize<S: Serializer>(days: &[Weekday], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(days.iter().map(|day| day.to_string()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Weekday>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|name| Weekday::from_str(name).map_err(|_| D::Error::custom(format!("unknown day of the week {name}"))))
+            .collect()
+    }
+}
+
+impl Rule {
+    /// Check that the rule can match something
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.is_empty() {
+            return Err(String::from("the rule has no name"))
+        }
+        if let Some(AmountRange { min: Some(min), max: Some(max), .. }) = &self.conditions.amount {
+            if !(max.clone() - min.clone()).is_nonnegative() {
+                return Err(format!("the least amount, {min}, is greater than the greatest, {max}"))
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the rule applies to the transaction, seen from the source
+    /// account given
+    pub fn matches(&self, transaction: &Transaction, source: &AccountName) -> bool {
+        let conditions = &self.conditions;
+        let Ok(amount) = transaction.get_amount(source) else {
+            return false
+        };
+        let date = transaction.get_datetime().get_date();
+
+        conditions.name.as_ref().is_none_or(|pattern| pattern.0.is_match(transaction.get_name()))
+            && conditions.notes.as_ref().is_none_or(|pattern| pattern.0.is_match(transaction.get_notes()))
+            && conditions.account.as_ref().is_none_or(|prefix| source.as_ref().starts_with(prefix.as_str()))
+            && conditions.amount.as_ref().is_none_or(|range| range.contains(amount))
+            && (conditions.weekdays.is_empty() || conditions.weekdays.contains(&date.weekday()))
+    }
+
+    /// The transaction with the changes of the rule, seen from the source
+    /// account given
+    pub fn apply(&self, transaction: &Transaction, source: &AccountName) -> Transaction {
+        let actions = &self.actions;
+        let mut changed = transaction.clone();
+
+        if let Some(name) = &actions.name {
+            let captures = self.conditions.name.as_ref().and_then(|pattern| pattern.0.captures(transaction.get_name()));
+            let new_name = match captures {
+                Some(captures) => {
+                    let mut expanded = String::new();
+                    captures.expand(name, &mut expanded);
+                    expanded
+                },
+                None => name.clone(),
+            };
+            changed.set_name(&new_name);
+        }
+        if let Some(notes) = &actions.notes {
+            changed.set_notes(notes);
+        }
+        for tag in actions.tags.iter() {
+            changed.add_tag(tag.clone());
+        }
+        if let (Some(account), Some(other)) = (&actions.account, self.replaced_account(transaction, source)) {
+            changed.replace_account(other, account.clone());
+        }
+        changed
+    }
+
+    /// The account that [`apply`](Rule::apply) replaces with the one of the
+    /// rule: the counter account of the source, if there is only one
+    fn replaced_account<'a>(&self, transaction: &'a Transaction, source: &AccountName) -> Option<&'a AccountName> {
+        let account = self.actions.account.as_ref()?;
+        let others = transaction.get_associated_accounts().filter(|other| *other != source).collect::<Vec<_>>();
+        match others.as_slice() {
+            [other] if account != source => Some(other),
+            _ => None,
+        }
+    }
+}
+
+impl Database {
+    pub fn get_rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Add a rule after the existing ones
+    ///
+    /// The account it gives to transactions, if any, must exist.
+    pub fn add_rule(&mut self, rule: Rule) -> Result<(), Error> {
+        if let Some(account) = &rule.actions.account {
+            if !self.accounts.contains_key(account) {
+                return Err(Error::UnknownAccount(account.clone()))
+            }
+        }
+        self.apply(Operation::AddRule(self.rules.len(), rule))
+    }
+
+    pub fn remove_rule(&mut self, name: &str) -> Result<(), Error> {
+        let Some(index) = self.rules.iter().position(|rule| rule.name == name) else {
+            return Err(Error::UnknownRule(name.to_owned()))
+        };
+        self.apply(Operation::RemoveRule(index, self.rules[index].clone()))
+    }
+
+    pub(crate) fn insert_rule(&mut self, index: usize, rule: Rule) -> Result<(), Error> {
+        rule.validate().map_err(Error::InvalidRule)?;
+        if self.rules.iter().any(|other| other.name == rule.name) {
+            return Err(Error::RuleNameInUse(rule.name))
+        }
+        self.rules.insert(index.min(self.rules.len()), rule);
+        Ok(())
+    }
+
+    pub(crate) fn delete_rule(&mut self, index: usize, rule: &Rule) -> Result<(), Error> {
+        if self.rules.get(index) != Some(rule) {
+            return Err(Error::UnknownRule(rule.name.clone()))
+        }
+        self.rules.remove(index);
+        Ok(())
+    }
+
+    /// The transaction as the first rule that matches it leaves it, or
+    /// nothing if no rule matches
+    ///
+    /// Fails if the rule would give the transaction an account of another
+    /// type than the one it replaces, since the amounts of assets and flows
+    /// have opposite signs.
+    pub fn categorize(&self, transaction: &Transaction) -> Result<Option<Transaction>, Error> {
+        let account_type = |account: &AccountName| self.accounts.get(account).map(|account| *account.get_account_type());
+        let matching = self.rules.iter().find_map(|rule| {
+            let source = transaction.get_associated_accounts().find(|account| {
+                (rule.conditions.account.is_some() || account_type(account) == Some(AccountType::Asset))
+                    && rule.matches(transaction, account)
+            })?;
+            Some((rule, source))
+        });
+        let Some((rule, source)) = matching else {
+            return Ok(None)
+        };
+
+        if let (Some(account), Some(replaced)) = (&rule.actions.account, rule.replaced_account(transaction, source)) {
+            if account_type(account) != account_type(replaced) {
+                return Err(Error::InvalidRule(format!(
+                    "{} would move the amount of {} to {}, which is of another type",
+                    rule.name,
+                    replaced.as_ref(),
+                    account.as_ref()
+                )))
+            }
+        }
+        Ok(Some(rule.apply(transaction, source)))
+    }
+
+    /// Apply the rules to the transactions given, as a single step
+    ///
+    /// Archived transactions are loaded to be changed. A transaction that
+    /// the rules would make the same as another one is left as it is. If any
+    /// other cannot be changed, nothing is.
+    pub fn apply_rules(&mut self, ids: &[TransactionId]) -> Result<AppliedRules, Error> {
+        self.grouped(|database| {
+            let mut applied = AppliedRules::default();
+            for id in ids {
+                database.load_archived(id)?;
+                let transaction = database.transactions.get(id).ok_or(Error::UnknownTransaction(*id))?;
+                let Some(categorized) = database.categorize(transaction)? else {
+                    continue
+                };
+                let new_id = categorized.generate_id();
+                if new_id == *id {
+                    continue
+                }
+                // Added first, so that a collision leaves the old one alone
+                match database.add_transaction(categorized) {
+                    Ok(()) => (),
+                    Err(Error::TransactionIdInUse(_)) => {
+                        applied.duplicates.push(*id);
+                        continue
+                    },
+                    Err(error) => return Err(error),
+                }
+                database.remove_transaction(*id)?;
+                applied.changed.push(new_id);
+            }
+            Ok(applied)
+        })
+    }
+}
+
+/// What [`Database::apply_rules`] did
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AppliedRules {
+    /// The new ids of the transactions that changed
+    pub changed: Vec<TransactionId>,
+    /// The transactions left as they were, because the rules would make
+    /// them the same as another one
+    pub duplicates: Vec<TransactionId>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::{account::Account, datetime::DateTime};
+    use std::str::FromStr;
+
+    mod categorizing {
+        use super::*;
+
+        fn card_payment(name: &str, date: &str, amount: &str) -> Transaction {
+            Transaction::example_transaction(
+                name,
+                "",
+                DateTime::from_str(date).unwrap(),
+                &[("asset/card", amount), ("flow/uncategorized", amount)],
+            )
+        }
+
+        fn database() -> Database {
+            let mut database = Database::default();
+            database.add_account(Account::new("asset/card", AccountType::Asset)).unwrap();
+            for name in ["flow/uncategorized", "expense/food", "expense/fika"] {
+                database.add_account(Account::new(name, AccountType::Flow)).unwrap();
+            }
+            database
+        }
+
+        fn rule(json: &str) -> Rule {
+            serde_json::from_str(json).unwrap()
+        }
+
+        #[test]
+        fn matching() {
+            let mut database = database();
+            database.add_rule(rule(r#"{
+                "name": "weekend fika",
+                "conditions": {"name": "(?i)café", "amount": {"currency": "SEK", "min": -100}, "weekdays": ["Saturday", "Sunday"]},
+                "actions": {"account": "expense/fika", "tags": ["fika"]}
+            }"#)).unwrap();
+            database.add_rule(rule(r#"{
+                "name": "groceries",
+                "conditions": {"name": "^KORTKÖP \\d+ (?<shop>ICA \\w+)", "account": "asset/card", "amount": {"currency": "SEK", "max": "0"}},
+                "actions": {"account": "expense/food", "name": "${shop}", "notes": "groceries"}
+            }"#)).unwrap();
+
+            // 2024-03-02 is a Saturday
+            let fika = database.categorize(&card_payment("Café Blom", "2024-03-02", "-45 SEK")).unwrap().unwrap();
+            assert_eq!(fika.get_amount(&AccountName::new("expense/fika")).unwrap(), &Amount::from_str("-45 SEK").unwrap());
+            assert!(fika.get_tags().contains(&Tag::new("fika")));
+            assert!(database.categorize(&card_payment("Café Blom", "2024-03-04", "-45 SEK")).unwrap().is_none());
+            assert!(database.categorize(&card_payment("Café Blom", "2024-03-02", "-450 SEK")).unwrap().is_none());
+            assert!(database.categorize(&card_payment("Café Blom", "2024-03-02", "-45 EUR")).unwrap().is_none());
+
+            let shop = database.categorize(&card_payment("KORTKÖP 240131 ICA MAXI STOCKHOLM", "2024-01-31", "-300 SEK")).unwrap().unwrap();
+            assert_eq!(shop.get_name(), "ICA MAXI");
+            assert_eq!(shop.get_notes(), "groceries");
+            assert!(shop.get_amount(&AccountName::new("flow/uncategorized")).is_err());
+            assert!(database.categorize(&card_payment("KORTKÖP 240131 ICA MAXI", "2024-01-31", "300 SEK")).unwrap().is_none());
+        }
+
+        #[test]
+        fn account_types() {
+            let mut database = database();
+            database.add_account(Account::new("asset/savings", AccountType::Asset)).unwrap();
+            database.add_rule(rule(r#"{"name": "savings", "conditions": {"name": "SPAR"}, "actions": {"account": "asset/savings"}}"#)).unwrap();
+
+            let transfer = card_payment("SPAR", "2024-01-31", "-300 SEK");
+            assert!(matches!(database.categorize(&transfer), Err(Error::InvalidRule(_))));
+            database.add_transaction(transfer.clone()).unwrap();
+            assert!(matches!(database.apply_rules(&[transfer.generate_id()]), Err(Error::InvalidRule(_))));
+            assert!(database.get_transaction(&transfer.generate_id()).is_some());
+        }
+
+        #[test]
+        fn rules_in_the_database() {
+            let mut database = database();
+            let groceries = rule(r#"{"name": "ica", "conditions": {"name": "ICA"}, "actions": {"account": "expense/food"}}"#);
+            database.add_rule(groceries.clone()).unwrap();
+            assert_eq!(database.add_rule(groceries.clone()), Err(Error::RuleNameInUse(String::from("ica"))));
+            let unknown = rule(r#"{"name": "other", "actions": {"account": "expense/other"}}"#);
+            assert_eq!(database.add_rule(unknown), Err(Error::UnknownAccount(AccountName::new("expense/other"))));
+            let invalid = rule(r#"{"name": "other", "conditions": {"amount": {"currency": "SEK", "min": 1, "max": "-1"}}}"#);
+            assert!(matches!(database.add_rule(invalid), Err(Error::InvalidRule(_))));
+            assert!(serde_json::from_str::<Rule>(r#"{"name": "bad", "conditions": {"name": "("}}"#).is_err());
+
+            let payment = card_payment("ICA", "2024-01-31", "-300 SEK");
+            let other = card_payment("Rent", "2024-01-31", "-9000 SEK");
+            database.add_transaction(payment.clone()).unwrap();
+            database.add_transaction(other.clone()).unwrap();
+            let changed = database.apply_rules(&[payment.generate_id(), other.generate_id()]).unwrap().changed;
+            assert_eq!(changed.len(), 1);
+            let categorized = database.get_transaction(&changed[0]).unwrap();
+            assert!(categorized.get_amount(&AccountName::new("expense/food")).is_ok());
+            // Applying them again changes nothing
+            assert_eq!(database.apply_rules(&changed).unwrap(), AppliedRules::default());
+
+            let text = serde_json::to_string(&database).unwrap();
+            let mut loaded: Database = serde_json::from_str(&text).unwrap();
+            assert_eq!(loaded.get_rules(), &[groceries]);
+            loaded.remove_rule("ica").unwrap();
+            assert!(loaded.get_rules().is_empty());
+            assert_eq!(loaded.remove_rule("ica"), Err(Error::UnknownRule(String::from("ica"))));
+
+            database.undo().unwrap();
+            assert!(database.get_transaction(&payment.generate_id()).is_some());
+
+            // A payment that would become a copy of another one is kept
+            // as it is, without stopping the others
+            let renamed = rule(r#"{"name": "ica maxi", "conditions": {"name": "MAXI"}, "actions": {"account": "expense/food", "name": "ICA"}}"#);
+            database.add_rule(renamed).unwrap();
+            let maxi = card_payment("KORTKÖP MAXI", "2024-01-31", "-300 SEK");
+            let fika = card_payment("ICA", "2024-02-01", "-30 SEK");
+            database.add_transaction(maxi.clone()).unwrap();
+            database.add_transaction(fika.clone()).unwrap();
+            let applied = database.apply_rules(&[payment.generate_id(), maxi.generate_id(), fika.generate_id()]).unwrap();
+            assert_eq!(applied.duplicates, [maxi.generate_id()]);
+            assert_eq!(applied.changed.len(), 2);
+            assert!(database.get_transaction(&maxi.generate_id()).is_some());
+        }
+    }
+}
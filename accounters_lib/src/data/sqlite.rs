@@ -0,0 +1,565 @@
+//! Databases stored in SQLite files
+//!
+//! Only available with the `sqlite` feature. Every database is a file with
+//! tables for accounts, transactions, postings (the amount of a
+//! transaction in one account) and rules. A database loaded from here writes each
+//! change to its file as it is made, so there is no need to save it, and
+//! large databases never need to be rewritten completely.
+//!
+//! Transactions are indexed by date, so the ones in a range of dates can be
+//! read without loading the whole file, see [`SqliteStorage::load_between`].
+//! Their key in the file is a hash of what identifies them, computed with an
+//! algorithm of its own so that it does not change between builds.
+
+use rusqlite::{params, Connection};
+use serde_json::{json, Value};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::data::{
+    account::{Account, AccountName},
+    datetime::DateTime,
+    history::Operation,
+    migration::CURRENT_VERSION,
+    rules::Rule,
+    storage::{ChangeSink, Storage},
+    transaction::Transaction,
+    Database, FileError,
+};
+
+const SCHEMA: &str = "
+    PRAGMA foreign_keys = ON;
+    CREATE TABLE IF NOT EXISTS meta (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS accounts (
+        name TEXT PRIMARY KEY,
+        account_type TEXT NOT NULL,
+        tags TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS transactions (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        notes TEXT NOT NULL,
+        tags TEXT NOT NULL,
+        datetime TEXT NOT NULL,
+        sort_key TEXT NOT NULL,
+        metadata TEXT NOT NULL DEFAULT '{}'
+    );
+    CREATE INDEX IF NOT EXISTS transactions_by_date ON transactions (sort_key);
+    CREATE TABLE IF NOT EXISTS postings (
+        transaction_id INTEGER NOT NULL REFERENCES transactions (id) ON DELETE CASCADE,
+        account TEXT NOT NULL REFERENCES accounts (name),
+        amount TEXT NOT NULL,
+        PRIMARY KEY (transaction_id, account)
+    );
+    CREATE INDEX IF NOT EXISTS postings_by_account ON postings (account);
+    CREATE TABLE IF NOT EXISTS rules (
+        position INTEGER NOT NULL,
+        rule TEXT NOT NULL
+    );
+";
+
+/// A directory with one SQLite file per database
+pub struct SqliteStorage {
+    directory: PathBuf,
+}
+
+impl SqliteStorage {
+    pub fn new<P: AsRef<Path>>(directory: P) -> Self {
+        SqliteStorage { directory: directory.as_ref().to_owned() }
+    }
+
+    pub fn get_path(&self, name: &str) -> PathBuf {
+        self.directory.join(format!("{name}.sqlite"))
+    }
+
+    /// Load all the accounts, but only the transactions between the given
+    /// dates (both included)
+    ///
+    /// The changes made to the result are written to the file as usual, but
+    /// balances can only be computed between those dates, and it cannot be
+    /// saved as a whole.
+    pub fn load_between(
+        &self,
+        name: &str,
+        start: Option<&DateTime>,
+        end: Option<&DateTime>
+    ) -> Result<Database, FileError> {
+        let sink = SqliteSink::open(self.get_path(name))?;
+        let mut database = sink.read(start, end)?;
+        database.sink = Some(Box::new(sink));
+        if start.is_some() || end.is_some() {
+            database.loaded_range = Some((start.copied(), end.copied()));
+        }
+        Ok(database)
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn list(&self) -> Result<Vec<String>, FileError> {
+        crate::data::storage::list_with_extension(&self.directory, "sqlite")
+    }
+
+    fn load(&self, name: &str) -> Result<Database, FileError> {
+        self.load_between(name, None, None)
+    }
+
+    fn save(&self, name: &str, database: &Database) -> Result<(), FileError> {
+        let path = self.get_path(name);
+        if database.get_storage_path() == Some(path.as_path()) {
+            // Every change is already in the file
+            return Ok(())
+        }
+        if database.loaded_range.is_some() {
+            return Err(FileError::Storage {
+                path,
+                message: String::from("only some transactions of the database are loaded, so it cannot replace the file"),
+            })
+        }
+        SqliteSink::open(path)?.replace(database)
+    }
+}
+
+/// An open SQLite file, which receives the changes of a database
+struct SqliteSink {
+    path: PathBuf,
+    connection: Connection,
+}
+
+impl SqliteSink {
+    fn open(path: PathBuf) -> Result<Self, FileError> {
+        let mut connection = Connection::open(&path).map_err(|e| error(&path, e))?;
+        connection.execute_batch(SCHEMA).map_err(|e| error(&path, e))?;
+
+        // Files created before transactions had metadata
+        let has_metadata = connection
+            .prepare("SELECT metadata FROM transactions LIMIT 0")
+            .is_ok();
+        if !has_metadata {
+            connection
+                .execute_batch("ALTER TABLE transactions ADD COLUMN metadata TEXT NOT NULL DEFAULT '{}'")
+                .map_err(|e| error(&path, e))?;
+        }
+
+        let version: Option<String> = connection
+            .query_row("SELECT value FROM meta WHERE key = 'version'", [], |row| row.get(0))
+            .ok();
+        match version {
+            None => {
+                connection
+                    .execute("INSERT INTO meta (key, value) VALUES ('version', ?1)", [CURRENT_VERSION.to_string()])
+                    .map_err(|e| error(&path, e))?;
+            },
+            Some(version) if version != CURRENT_VERSION.to_string() => {
+                return Err(FileError::Storage {
+                    path,
+                    message: format!("unsupported format version {}", version),
+                })
+            },
+            Some(_) => {},
+        }
+
+        // Files whose keys were computed with the hasher of the standard
+        // library, which may change between builds
+        let keys: Option<String> = connection
+            .query_row("SELECT value FROM meta WHERE key = 'transaction_keys'", [], |row| row.get(0))
+            .ok();
+        if keys.as_deref() != Some(TRANSACTION_KEYS) {
+            rekey_transactions(&mut connection).map_err(|e| error(&path, e))?;
+        }
+
+        Ok(SqliteSink { path, connection })
+    }
+
+    fn read(&self, start: Option<&DateTime>, end: Option<&DateTime>) -> Result<Database, FileError> {
+        let path = &self.path;
+        let format_error = |message: String| FileError::Storage { path: path.to_owned(), message };
+
+        let mut accounts = HashMap::new();
+        let mut statement = self.connection
+            .prepare("SELECT name, account_type, tags FROM accounts")
+            .map_err(|e| error(path, e))?;
+        let rows = statement
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)))
+            .map_err(|e| error(path, e))?;
+        for row in rows {
+            let (name, account_type, tags) = row.map_err(|e| error(path, e))?;
+            let account: Account = serde_json::from_value(json!({
+                "name": name,
+                "account_type": parse_json(path, &account_type)?,
+                "tags": parse_json(path, &tags)?,
+            })).map_err(|e| format_error(e.to_string()))?;
+            accounts.insert(AccountName::new(&name), account);
+        }
+
+        // Text comparison of sort keys, see DateTime::sort_string. They are
+        // in UTC, so the rows within two days of the limits are read, and
+        // then the local dates are checked.
+        let margin = time::Duration::days(2);
+        let sort_start = start
+            .and_then(|date| date.get_date().checked_sub(margin))
+            .map(|date| DateTime::new(date, None).sort_string())
+            .unwrap_or_default();
+        let sort_end = end
+            .and_then(|date| date.get_date().checked_add(margin))
+            .map(|date| DateTime::new(date, None).sort_string())
+            .unwrap_or_else(|| String::from("~"));
+
+        let mut rows_by_id = HashMap::new();
+        let mut statement = self.connection
+            .prepare("SELECT id, name, notes, tags, datetime, metadata FROM transactions WHERE sort_key BETWEEN ?1 AND ?2")
+            .map_err(|e| error(path, e))?;
+        let rows = statement
+            .query_map(params![sort_start, sort_end], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, String>(5)?
+                ))
+            })
+            .map_err(|e| error(path, e))?;
+        for row in rows {
+            let (id, name, notes, tags, datetime, metadata) = row.map_err(|e| error(path, e))?;
+            rows_by_id.insert(id, json!({
+                "name": name,
+                "notes": notes,
+                "tags": parse_json(path, &tags)?,
+                "datetime": datetime,
+                "amounts": {},
+                "metadata": parse_json(path, &metadata)?,
+            }));
+        }
+
+        let mut statement = self.connection
+            .prepare(
+                "SELECT postings.transaction_id, postings.account, postings.amount
+                FROM postings JOIN transactions ON transactions.id = postings.transaction_id
+                WHERE transactions.sort_key BETWEEN ?1 AND ?2"
+            )
+            .map_err(|e| error(path, e))?;
+        let rows = statement
+            .query_map(params![sort_start, sort_end], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+            })
+            .map_err(|e| error(path, e))?;
+        for row in rows {
+            let (id, account, amount) = row.map_err(|e| error(path, e))?;
+            if let Some(transaction) = rows_by_id.get_mut(&id) {
+                transaction["amounts"][account] = Value::String(amount);
+            }
+        }
+
+        let mut transactions = HashMap::new();
+        for (_, row) in rows_by_id {
+            let transaction: Transaction = serde_json::from_value(row).map_err(|e| format_error(e.to_string()))?;
+            if transaction.get_datetime().is_between(start, end) {
+                transactions.insert(transaction.generate_id(), transaction);
+            }
+        }
+
+        let mut rules = Vec::new();
+        let mut statement = self.connection
+            .prepare("SELECT rule FROM rules ORDER BY position")
+            .map_err(|e| error(path, e))?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0)).map_err(|e| error(path, e))?;
+        for row in rows {
+            let rule = row.map_err(|e| error(path, e))?;
+            rules.push(serde_json::from_str(&rule).map_err(|e| format_error(e.to_string()))?);
+        }
+
+        let mut database = Database { accounts, transactions, rules, ..Default::default() };
+        database
+            .build_account_transaction_map()
+            .map_err(|source| FileError::Database { path: path.to_owned(), source })?;
+        Ok(database)
+    }
+
+    /// Replace the whole content of the file with the database
+    fn replace(mut self, database: &Database) -> Result<(), FileError> {
+        let path = self.path.clone();
+        let transaction = self.connection.transaction().map_err(|e| error(&path, e))?;
+        transaction
+            .execute_batch("DELETE FROM postings; DELETE FROM transactions; DELETE FROM accounts; DELETE FROM rules;")
+            .map_err(|e| error(&path, e))?;
+        for account in database.accounts.values() {
+            insert_account(&transaction, account).map_err(|e| error(&path, e))?;
+        }
+        for trns in database.transactions.values() {
+            insert_transaction(&transaction, trns).map_err(|e| error(&path, e))?;
+        }
+        for (index, rule) in database.rules.iter().enumerate() {
+            insert_rule(&transaction, index, rule).map_err(|e| error(&path, e))?;
+        }
+        transaction.commit().map_err(|e| error(&path, e))
+    }
+}
+
+impl ChangeSink for SqliteSink {
+    fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    fn write_change(&mut self, operation: &Operation) -> Result<(), String> {
+        let transaction = self.connection.transaction().map_err(|e| e.to_string())?;
+        // What is removed must be in the file, or the file and the database
+        // do not match
+        let n_rows = match operation {
+            Operation::AddAccount(account) => insert_account(&transaction, account).map(|_| 1),
+            Operation::RemoveAccount(account) => transaction
+                .execute("DELETE FROM accounts WHERE name = ?1", [account.get_name().as_ref()]),
+            Operation::AddTransaction(trns) => insert_transaction(&transaction, trns).map(|_| 1),
+            Operation::RemoveTransaction(trns) => transaction_key(trns)
+                .and_then(|key| transaction.execute("DELETE FROM transactions WHERE id = ?1", [key])),
+            Operation::AddRule(index, rule) => transaction
+                .execute("UPDATE rules SET position = position + 1 WHERE position >= ?1", [*index as i64])
+                .and_then(|_| insert_rule(&transaction, *index, rule))
+                .map(|_| 1),
+            Operation::RemoveRule(index, _) => transaction
+                .execute("DELETE FROM rules WHERE position = ?1", [*index as i64])
+                .and_then(|n_removed| {
+                    transaction.execute("UPDATE rules SET position = position - 1 WHERE position > ?1", [*index as i64])?;
+                    Ok(n_removed)
+                }),
+        }.map_err(|e| e.to_string())?;
+        if n_rows != 1 {
+            return Err(format!("{} rows changed in the file instead of 1, it does not match the database", n_rows))
+        }
+        transaction.commit().map_err(|e| e.to_string())
+    }
+}
+
+fn insert_account(connection: &Connection, account: &Account) -> rusqlite::Result<()> {
+    let value = serde_json::to_value(account).map_err(to_sql_error)?;
+    connection.execute(
+        "INSERT INTO accounts (name, account_type, tags) VALUES (?1, ?2, ?3)",
+        params![account.get_name().as_ref(), value["account_type"].to_string(), value["tags"].to_string()],
+    )?;
+    Ok(())
+}
+
+fn insert_transaction(connection: &Connection, transaction: &Transaction) -> rusqlite::Result<()> {
+    let value = serde_json::to_value(transaction).map_err(to_sql_error)?;
+    let key = transaction_key(transaction)?;
+    connection.execute(
+        "INSERT INTO transactions (id, name, notes, tags, datetime, sort_key, metadata)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            key,
+            transaction.get_name(),
+            value["notes"].as_str().unwrap_or_default(),
+            value["tags"].to_string(),
+            transaction.get_datetime().to_iso_string(),
+            transaction.get_datetime().sort_string(),
+            serde_json::to_string(transaction.get_metadata()).map_err(to_sql_error)?
+        ],
+    )?;
+    for (account, amount) in value["amounts"].as_object().into_iter().flatten() {
+        connection.execute(
+            "INSERT INTO postings (transaction_id, account, amount) VALUES (?1, ?2, ?3)",
+            params![key, account, amount.as_str().unwrap_or_default()],
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_rule(connection: &Connection, index: usize, rule: &Rule) -> rusqlite::Result<()> {
+    connection.execute(
+        "INSERT INTO rules (position, rule) VALUES (?1, ?2)",
+        params![index as i64, serde_json::to_string(rule).map_err(to_sql_error)?],
+    )?;
+    Ok(())
+}
+
+/// How the keys of the transactions are computed, see [`key_of`]
+const TRANSACTION_KEYS: &str = "fnv1a";
+
+/// The key of a transaction in the file
+fn transaction_key(transaction: &Transaction) -> rusqlite::Result<i64> {
+    let value = serde_json::to_value(transaction).map_err(to_sql_error)?;
+    Ok(key_of(
+        transaction.get_name(),
+        value["notes"].as_str().unwrap_or_default(),
+        &value["tags"],
+        &transaction.get_datetime().sort_string(),
+        &value["amounts"],
+    ))
+}
+
+/// A 64-bit FNV-1a hash of the same fields as
+/// [`Transaction::generate_id`], which unlike the hasher of the standard
+/// library is the same in every build
+///
+/// SQLite integers are signed, so the hash is stored with the same bits.
+fn key_of(name: &str, notes: &str, tags: &Value, sort_key: &str, amounts: &Value) -> i64 {
+    let text = json!([name, notes, tags, sort_key, amounts]).to_string();
+    let hash = text.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    hash as i64
+}
+
+/// Replace the keys of all the transactions with the ones of [`key_of`]
+fn rekey_transactions(connection: &mut Connection) -> rusqlite::Result<()> {
+    let transaction = connection.transaction()?;
+    // The postings are updated after their transactions
+    transaction.execute_batch("PRAGMA defer_foreign_keys = ON")?;
+
+    let mut rows = HashMap::new();
+    {
+        let mut statement = transaction.prepare("SELECT id, name, notes, tags, sort_key FROM transactions")?;
+        let mut query = statement.query([])?;
+        while let Some(row) = query.next()? {
+            let tags: String = row.get(3)?;
+            let tags: Value = serde_json::from_str(&tags).map_err(to_sql_error)?;
+            let fields = (row.get::<_, String>(1)?, row.get::<_, String>(2)?, tags, row.get::<_, String>(4)?);
+            rows.insert(row.get::<_, i64>(0)?, (fields, serde_json::Map::new()));
+        }
+        let mut statement = transaction.prepare("SELECT transaction_id, account, amount FROM postings")?;
+        let mut query = statement.query([])?;
+        while let Some(row) = query.next()? {
+            if let Some((_, amounts)) = rows.get_mut(&row.get::<_, i64>(0)?) {
+                amounts.insert(row.get(1)?, Value::String(row.get(2)?));
+            }
+        }
+    }
+
+    for (old_key, ((name, notes, tags, sort_key), amounts)) in rows {
+        let key = key_of(&name, &notes, &tags, &sort_key, &Value::Object(amounts));
+        transaction.execute("UPDATE transactions SET id = ?1 WHERE id = ?2", [key, old_key])?;
+        transaction.execute("UPDATE postings SET transaction_id = ?1 WHERE transaction_id = ?2", [key, old_key])?;
+    }
+    transaction.execute(
+        "INSERT OR REPLACE INTO meta (key, value) VALUES ('transaction_keys', ?1)",
+        [TRANSACTION_KEYS],
+    )?;
+    transaction.commit()
+}
+
+fn parse_json(path: &Path, text: &str) -> Result<Value, FileError> {
+    serde_json::from_str(text).map_err(|e| FileError::Storage { path: path.to_owned(), message: e.to_string() })
+}
+
+fn to_sql_error(error: serde_json::Error) -> rusqlite::Error {
+    rusqlite::Error::ToSqlConversionFailure(Box::new(error))
+}
+
+fn error(path: &Path, error: rusqlite::Error) -> FileError {
+    FileError::Storage { path: path.to_owned(), message: error.to_string() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    mod storage {
+        use super::*;
+
+        fn lunch(day: &str, amount: &str) -> Transaction {
+            Transaction::example_transaction(
+                "Lunch",
+                "With tags and notes",
+                DateTime::from_str(day).unwrap(),
+                &[("bank/ICA_Bank", amount), ("food/lunch", amount)]
+            )
+        }
+
+        #[test]
+        fn incremental_changes() {
+            std::fs::create_dir_all("test_files/sqlite").unwrap();
+            let storage = SqliteStorage::new("test_files/sqlite");
+            let _ = std::fs::remove_file(storage.get_path("incremental"));
+            let bank = AccountName::new("bank/ICA_Bank");
+
+            let mut initial = Database::default();
+            initial.add_account(Account::new("bank/ICA_Bank", crate::data::account::AccountType::Asset)).unwrap();
+            storage.save("incremental", &initial).unwrap();
+
+            let mut database = storage.load("incremental").unwrap();
+            database.add_account(Account::new("food/lunch", crate::data::account::AccountType::Flow)).unwrap();
+            database.add_transaction(lunch("2023-07-13 12:00", "-100 SEK")).unwrap();
+            database.add_transaction(lunch("2023-08-13", "-50.5 SEK, 3 EUR")).unwrap();
+            database.add_transaction(lunch("2023-09-13", "-20 SEK")).unwrap();
+            database.remove_transaction(lunch("2023-09-13", "-20 SEK").generate_id()).unwrap();
+            // In UTC, this one happens in August
+            database.add_transaction(lunch("2023-09-01T00:30+02:00", "-7 SEK")).unwrap();
+
+            let reloaded = storage.load("incremental").unwrap();
+            assert_eq!(
+                database.get_account_balance(&bank, None, None),
+                reloaded.get_account_balance(&bank, None, None)
+            );
+            assert_eq!(3, reloaded.get_transaction_ids().count());
+
+            let rule = |name: &str| -> crate::data::rules::Rule {
+                serde_json::from_value(json!({"name": name})).unwrap()
+            };
+            database.add_rule(rule("first")).unwrap();
+            database.add_rule(rule("second")).unwrap();
+            database.remove_rule("first").unwrap();
+            database.undo().unwrap();
+            assert_eq!(database.get_rules(), storage.load("incremental").unwrap().get_rules());
+
+            let august = storage.load_between(
+                "incremental",
+                Some(&DateTime::from_str("2023-08-01").unwrap()),
+                Some(&DateTime::from_str("2023-08-31").unwrap())
+            ).unwrap();
+            assert_eq!(1, august.get_transaction_ids().count());
+            assert_eq!(
+                Ok(crate::data::money::Amount::from_str("-50.5 SEK, 3 EUR").unwrap()),
+                august.get_account_balance(&bank, Some(DateTime::from_str("2023-08-10").unwrap()), Some(DateTime::from_str("2023-08-20").unwrap()))
+            );
+            assert!(matches!(august.get_account_balance(&bank, None, None), Err(crate::data::Error::NotLoaded(..))));
+            assert!(matches!(august.query(&Default::default()), Err(crate::data::Error::NotLoaded(..))));
+            assert!(storage.save("incremental_copy", &august).is_err());
+            let september = storage.load_between("incremental", Some(&DateTime::from_str("2023-09-01").unwrap()), None).unwrap();
+            assert_eq!(1, september.get_transaction_ids().count());
+        }
+
+        #[test]
+        fn stable_keys() {
+            std::fs::create_dir_all("test_files/sqlite").unwrap();
+            let storage = SqliteStorage::new("test_files/sqlite");
+            let path = storage.get_path("keys");
+            let _ = std::fs::remove_file(&path);
+
+            // The key must never change, or existing files could not be
+            // changed anymore
+            let key = key_of("Lunch", "", &json!(["food"]), "2023-07-13", &json!({"bank/ICA_Bank": "-100 SEK"}));
+            assert_eq!(3005095319503839863, key);
+
+            let mut database = Database::default();
+            database.add_account(Account::new("bank/ICA_Bank", crate::data::account::AccountType::Asset)).unwrap();
+            database.add_account(Account::new("food/lunch", crate::data::account::AccountType::Flow)).unwrap();
+            database.add_transaction(lunch("2023-07-13", "-100 SEK")).unwrap();
+            storage.save("keys", &database).unwrap();
+
+            // A file with keys from an older build
+            let connection = Connection::open(&path).unwrap();
+            connection.execute_batch(
+                "PRAGMA foreign_keys = OFF;
+                UPDATE transactions SET id = 1; UPDATE postings SET transaction_id = 1;
+                DELETE FROM meta WHERE key = 'transaction_keys';"
+            ).unwrap();
+            drop(connection);
+
+            let mut database = storage.load("keys").unwrap();
+            let mut other = storage.load("keys").unwrap();
+            database.remove_transaction(lunch("2023-07-13", "-100 SEK").generate_id()).unwrap();
+            assert_eq!(0, storage.load("keys").unwrap().get_transaction_ids().count());
+
+            // Removing what is not in the file anymore fails, and changes
+            // nothing
+            let removed = other.remove_transaction(lunch("2023-07-13", "-100 SEK").generate_id());
+            assert!(matches!(removed, Err(crate::data::Error::Storage(_))));
+            assert_eq!(1, other.get_transaction_ids().count());
+        }
+    }
+}
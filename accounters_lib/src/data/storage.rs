@@ -0,0 +1,153 @@
+//! Places where databases are kept
+//!
+//! A [`Storage`] holds any number of databases, identified by name. The
+//! library does not care how they are stored, so new ways of storing them can
+//! be added without changing the [`Database`] or the programs using it.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::read_dir;
+use std::path::{Path, PathBuf};
+
+use crate::data::{
+    file::{LockedFile, SaveOptions},
+    history::Operation,
+    journal::Journal,
+    Database, FileError,
+};
+
+/// A collection of databases that can be loaded and saved by name
+pub trait Storage {
+    /// Names of the databases available, sorted
+    fn list(&self) -> Result<Vec<String>, FileError>;
+
+    fn load(&self, name: &str) -> Result<Database, FileError>;
+
+    /// Store the database, replacing the previous version if there is one
+    fn save(&self, name: &str, database: &Database) -> Result<(), FileError>;
+}
+
+/// Receives every change made to a database, to store it as it happens
+///
+/// If writing a change fails, the change is reverted in the database too.
+pub trait ChangeSink {
+    /// Where the changes are written
+    fn get_path(&self) -> &Path;
+
+    fn write_change(&mut self, operation: &Operation) -> Result<(), String>;
+
+    /// Store the whole current state, if that makes loading faster
+    fn checkpoint(&mut self, _database: &Database) -> Result<(), FileError> {
+        Ok(())
+    }
+}
+
+/// Names of the files in the directory with the given extension, without it
+pub(crate) fn list_with_extension(directory: &Path, extension: &str) -> Result<Vec<String>, FileError> {
+    let entries = read_dir(directory).map_err(|e| FileError::io(directory, e))?;
+    let mut names = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != extension {
+                return None
+            }
+            Some(path.file_stem()?.to_str()?.to_owned())
+        })
+        .collect::<Vec<_>>();
+    names.sort();
+    Ok(names)
+}
+
+/// A directory with one JSON file per database
+///
+/// Loading a database locks its file until the storage is dropped, so that
+/// another program cannot overwrite it in the meantime.
+pub struct JsonStorage {
+    directory: PathBuf,
+    options: SaveOptions,
+    locks: RefCell<HashMap<String, LockedFile>>,
+}
+
+impl JsonStorage {
+    pub fn new<P: AsRef<Path>>(directory: P) -> Self {
+        JsonStorage {
+            directory: directory.as_ref().to_owned(),
+            options: SaveOptions::default(),
+            locks: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_options(mut self, options: SaveOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn get_path(&self, name: &str) -> PathBuf {
+        self.directory.join(format!("{name}.json"))
+    }
+
+    fn lock(&self, name: &str) -> Result<LockedFile, FileError> {
+        Ok(LockedFile::lock(self.get_path(name))?.with_options(self.options.clone()))
+    }
+}
+
+impl Storage for JsonStorage {
+    fn list(&self) -> Result<Vec<String>, FileError> {
+        list_with_extension(&self.directory, "json")
+    }
+
+    fn load(&self, name: &str) -> Result<Database, FileError> {
+        let mut locks = self.locks.borrow_mut();
+        if !locks.contains_key(name) {
+            locks.insert(name.to_owned(), self.lock(name)?);
+        }
+        locks[name].load()
+    }
+
+    fn save(&self, name: &str, database: &Database) -> Result<(), FileError> {
+        match self.locks.borrow().get(name) {
+            Some(file) => file.save(database),
+            None => self.lock(name)?.save(database),
+        }
+    }
+}
+
+/// A directory with one journal per database
+///
+/// Databases loaded from here write every change to their journal as it
+/// happens, so saving them to the same journal does nothing. Saving any other
+/// database adds a snapshot of it to the journal, which is created if needed.
+pub struct JournalStorage {
+    directory: PathBuf,
+}
+
+impl JournalStorage {
+    pub fn new<P: AsRef<Path>>(directory: P) -> Self {
+        JournalStorage { directory: directory.as_ref().to_owned() }
+    }
+
+    pub fn get_path(&self, name: &str) -> PathBuf {
+        self.directory.join(format!("{name}.journal"))
+    }
+}
+
+impl Storage for JournalStorage {
+    fn list(&self) -> Result<Vec<String>, FileError> {
+        list_with_extension(&self.directory, "journal")
+    }
+
+    fn load(&self, name: &str) -> Result<Database, FileError> {
+        Database::open_journal(self.get_path(name))
+    }
+
+    fn save(&self, name: &str, database: &Database) -> Result<(), FileError> {
+        let path = self.get_path(name);
+        match database.get_storage_path() {
+            Some(current) if current == path => Ok(()),
+            _ if path.exists() => {
+                Journal::open(&path)?.append_snapshot(database).map_err(|e| FileError::io(&path, e))
+            },
+            _ => Journal::create(&path, database).map(|_| ()),
+        }
+    }
+}
@@ -4,20 +4,25 @@ use serde::{Deserialize, Serialize};
 use crate::data::{
     account::AccountName,
     money::Amount,
-    tags::Tag
+    tags::Tag,
+    Error
 };
 
 use std::hash::{Hash, Hasher};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet};
 use std::str::FromStr;
 
 #[derive(Deserialize, Serialize, Clone)]
 pub struct Transaction {
     name: String,
     notes: String,
-    tags: HashSet<Tag>,
+    tags: BTreeSet<Tag>,
     datetime: DateTime,
-    amounts: HashMap<AccountName, Amount>,
+    amounts: BTreeMap<AccountName, Amount>,
+    /// Extra information, like where the transaction was imported from. It
+    /// is not part of the identity of the transaction.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: BTreeMap<String, String>,
 }
 
 impl Hash for Transaction {
@@ -44,9 +49,10 @@ impl Transaction {
         Transaction {
             name: String::new(),
             notes: String::new(),
-            tags: HashSet::new(),
+            tags: BTreeSet::new(),
             datetime: DateTime::from_str("2022-05-08").unwrap(),
-            amounts: HashMap::new()
+            amounts: BTreeMap::new(),
+            metadata: BTreeMap::new(),
         }
     }
 
@@ -56,7 +62,7 @@ impl Transaction {
         datetime: DateTime,
         amounts: &[(&str, &str)],
     ) -> Transaction {
-        let mut amounts_map = HashMap::new();
+        let mut amounts_map = BTreeMap::new();
         for (account, amount) in amounts {
             amounts_map.insert(AccountName::new(account), Amount::from_str(amount).unwrap());
         }
@@ -64,9 +70,10 @@ impl Transaction {
         Transaction {
             name: name.to_owned(),
             notes: notes.to_owned(),
-            tags: HashSet::new(),
+            tags: BTreeSet::new(),
             datetime,
             amounts: amounts_map,
+            metadata: BTreeMap::new(),
         }
     }
 
@@ -76,7 +83,7 @@ impl Transaction {
         datetime: DateTime,
         amounts: &[(String, Amount)],
     ) -> Transaction {
-        let mut amounts_map = HashMap::new();
+        let mut amounts_map = BTreeMap::new();
         for (account, amount) in amounts {
             amounts_map.insert(AccountName::new(account), amount.to_owned());
         }
@@ -84,9 +91,10 @@ impl Transaction {
         Transaction {
             name: name.to_owned(),
             notes: notes.to_owned(),
-            tags: HashSet::new(),
+            tags: BTreeSet::new(),
             datetime,
             amounts: amounts_map,
+            metadata: BTreeMap::new(),
         }
     }
 
@@ -94,8 +102,10 @@ impl Transaction {
         self.amounts.keys()
     }
 
-    pub fn get_amount(&self, account_name: &AccountName) -> Result<&Amount, &'static str> {
-        self.amounts.get(account_name).ok_or("Nope")
+    pub fn get_amount(&self, account_name: &AccountName) -> Result<&Amount, Error> {
+        self.amounts.get(account_name).ok_or_else(|| {
+            Error::AccountNotAssociatedWithTransaction((account_name.to_owned(), self.generate_id()))
+        })
     }
 
     pub fn generate_id(&self) -> TransactionId {
@@ -108,7 +118,48 @@ impl Transaction {
         &self.name
     }
 
-    pub fn get_amounts(&self) -> &HashMap<AccountName, Amount> {
+    pub fn get_notes(&self) -> &str {
+        &self.notes
+    }
+
+    pub fn set_name(&mut self, name: &str) {
+        self.name = name.to_owned();
+    }
+
+    pub fn set_notes(&mut self, notes: &str) {
+        self.notes = notes.to_owned();
+    }
+
+    /// Move the amount of an account to another one, adding it to what the
+    /// other account had
+    pub fn replace_account(&mut self, old: &AccountName, new: AccountName) {
+        let Some(amount) = self.amounts.remove(old) else {
+            return
+        };
+        let total = match self.amounts.remove(&new) {
+            Some(previous) => previous + &amount,
+            None => amount,
+        };
+        self.amounts.insert(new, total);
+    }
+
+    pub fn get_tags(&self) -> &BTreeSet<Tag> {
+        &self.tags
+    }
+
+    pub fn add_tag(&mut self, tag: Tag) {
+        self.tags.insert(tag);
+    }
+
+    pub fn get_metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+
+    pub fn set_metadata(&mut self, key: &str, value: &str) {
+        self.metadata.insert(key.to_owned(), value.to_owned());
+    }
+
+    pub fn get_amounts(&self) -> &BTreeMap<AccountName, Amount> {
         &self.amounts
     }
     
@@ -0,0 +1,524 @@
+//! Databases split in one file per year
+//!
+//! A database is stored as a directory with:
+//!
+//! - `accounts.json`, with every account and the rules
+//! - `2023.json`, `2024.json`..., with the transactions of each year
+//! - `balances.json`, with the balance of every account at the start of
+//!   each year
+//!
+//! Only the most recent years are loaded. The older ones form an
+//! archive, which is read the first time a query needs it. Balances from
+//! the beginning of time use the cached opening balances instead, so they
+//! never need to read the archive.
+
+use std::cell::{OnceCell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use crate::data::{
+    account::AccountName,
+    datetime::DateTime,
+    file::{write_atomically, LockedFile},
+    money::Amount,
+    storage::Storage,
+    transaction::{Transaction, TransactionId},
+    Database, Error, FileError,
+};
+
+const ACCOUNTS_FILE: &str = "accounts.json";
+const BALANCES_FILE: &str = "balances.json";
+
+/// Balances of every account at the start of each year
+type OpeningBalances = BTreeMap<i32, BTreeMap<AccountName, Amount>>;
+
+/// The transactions of an archived year once read, or why they could not be
+type ArchivedYear = OnceCell<Result<HashMap<TransactionId, Transaction>, String>>;
+
+/// The years of a database that have not been loaded yet
+pub(crate) struct Archive {
+    directory: PathBuf,
+    /// The first year in memory. Every year before it is archived.
+    first_loaded: i32,
+    years: BTreeMap<i32, ArchivedYear>,
+    /// Balances at the start of `first_loaded`
+    opening: BTreeMap<AccountName, Amount>,
+}
+
+impl Archive {
+    /// The moment before which transactions are archived
+    fn cutoff(&self) -> DateTime {
+        DateTime::simple((self.first_loaded, 1, 1), None)
+    }
+
+    /// The transactions of an archived year, reading them if needed
+    fn year(&self, year: i32) -> Result<&HashMap<TransactionId, Transaction>, Error> {
+        let Some(cell) = self.years.get(&year) else {
+            return Err(Error::Storage(format!("{year} is not archived")))
+        };
+        cell.get_or_init(|| {
+            let path = year_path(&self.directory, year);
+            let database = Database::parse_file(&path).map_err(|e| e.to_string())?;
+            Ok(database.transactions)
+        }).as_ref().map_err(|message| Error::Storage(message.to_owned()))
+    }
+
+    fn all_years(&self) -> impl Iterator<Item = Result<&HashMap<TransactionId, Transaction>, Error>> {
+        self.years.keys().map(|year| self.year(*year))
+    }
+
+    /// The archived years that have days in the interval
+    fn years_between<'a>(&'a self, start: Option<&'a DateTime>, end: Option<&'a DateTime>) -> impl Iterator<Item = i32> + 'a {
+        self.years.keys().copied().filter(move |year| {
+            let year_start = DateTime::simple((*year, 1, 1), None);
+            let year_end = DateTime::simple((*year + 1, 1, 1), None);
+            !(start.is_some_and(|start| start >= &year_end) || end.is_some_and(|end| end < &year_start))
+        })
+    }
+
+    /// The archived transactions in the interval, reading their years if
+    /// needed
+    pub(crate) fn transactions_between(
+        &self,
+        start: Option<&DateTime>,
+        end: Option<&DateTime>
+    ) -> Result<Vec<(&TransactionId, &Transaction)>, Error> {
+        let mut transactions = Vec::new();
+        for year in self.years_between(start, end) {
+            transactions.extend(self.year(year)?.iter());
+        }
+        Ok(transactions)
+    }
+
+    pub(crate) fn get_balance(
+        &self,
+        account_name: &AccountName,
+        start: Option<&DateTime>,
+        end: Option<&DateTime>
+    ) -> Result<Amount, Error> {
+        let cutoff = self.cutoff();
+        if start.is_some_and(|start| start >= &cutoff) {
+            return Ok(Amount::default())
+        }
+        if start.is_none() && end.is_none_or(|end| end >= &cutoff) {
+            return Ok(self.opening.get(account_name).cloned().unwrap_or_default())
+        }
+
+        let mut total = Amount::default();
+        for year in self.years_between(start, end) {
+            for transaction in self.year(year)?.values() {
+                let in_range = transaction.get_datetime().is_between(start, end);
+                if let (true, Ok(amount)) = (in_range, transaction.get_amount(account_name)) {
+                    total = total + amount;
+                }
+            }
+        }
+        Ok(total)
+    }
+
+    /// The archived year that has the transaction, if any
+    fn year_of(&self, id: &TransactionId) -> Result<Option<i32>, Error> {
+        for year in self.years.keys() {
+            if self.year(*year)?.contains_key(id) {
+                return Ok(Some(*year))
+            }
+        }
+        Ok(None)
+    }
+
+    /// Stop archiving the given year and the ones after it, returning their
+    /// transactions
+    fn unarchive_from(&mut self, first: i32) -> Result<HashMap<TransactionId, Transaction>, Error> {
+        // Every year is read before changing anything, in case one fails
+        let mut transactions = HashMap::new();
+        for year in self.years.range(first..).map(|(year, _)| *year).collect::<Vec<_>>() {
+            transactions.extend(self.year(year)?.iter().map(|(id, trns)| (*id, trns.clone())));
+        }
+
+        self.years.retain(|year, _| *year < first);
+        for transaction in transactions.values() {
+            for (account_name, amount) in transaction.get_amounts() {
+                let balance = self.opening.remove(account_name).unwrap_or_default();
+                self.opening.insert(account_name.to_owned(), balance - amount);
+            }
+        }
+        self.first_loaded = first;
+        Ok(transactions)
+    }
+
+    pub(crate) fn get_transaction(&self, id: &TransactionId) -> Result<Option<&Transaction>, Error> {
+        for transactions in self.all_years() {
+            if let Some(transaction) = transactions?.get(id) {
+                return Ok(Some(transaction))
+            }
+        }
+        Ok(None)
+    }
+
+    /// Whether a new transaction would be a copy of an archived one
+    pub(crate) fn contains(&self, transaction: &Transaction) -> Result<bool, Error> {
+        let year = transaction.get_datetime().get_date().year();
+        if !self.years.contains_key(&year) {
+            return Ok(false)
+        }
+        Ok(self.year(year)?.contains_key(&transaction.generate_id()))
+    }
+
+    pub(crate) fn uses_account(&self, account_name: &AccountName) -> Result<bool, Error> {
+        for transactions in self.all_years() {
+            if transactions?.values().any(|trns| trns.get_amounts().contains_key(account_name)) {
+                return Ok(true)
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl Database {
+    /// Load the archived year of a transaction, and the ones after it, so
+    /// that the transaction can be changed
+    ///
+    /// Returns whether the transaction was archived.
+    pub(crate) fn load_archived(&mut self, id: &TransactionId) -> Result<bool, Error> {
+        let Some(archive) = self.archive.as_mut() else {
+            return Ok(false)
+        };
+        let Some(year) = archive.year_of(id)? else {
+            return Ok(false)
+        };
+        let transactions = archive.unarchive_from(year)?;
+        if archive.years.is_empty() {
+            self.archive = None;
+        }
+
+        for (id, transaction) in transactions {
+            for account_name in transaction.get_associated_accounts() {
+                if let Some(account) = self.accounts.get_mut(account_name) {
+                    account.add_transaction(id);
+                }
+            }
+            self.transactions.insert(id, transaction);
+        }
+        Ok(true)
+    }
+}
+
+fn year_path(directory: &Path, year: i32) -> PathBuf {
+    directory.join(format!("{year}.json"))
+}
+
+/// The years with a transactions file in the directory, sorted
+fn find_years(directory: &Path) -> Result<Vec<i32>, FileError> {
+    let entries = std::fs::read_dir(directory).map_err(|e| FileError::io(directory, e))?;
+    let mut years = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != "json" {
+                return None
+            }
+            path.file_stem()?.to_str()?.parse::<i32>().ok()
+        })
+        .collect::<Vec<_>>();
+    years.sort();
+    Ok(years)
+}
+
+/// A directory with one subdirectory per database, each split by years
+pub struct YearlyStorage {
+    directory: PathBuf,
+    loaded_years: usize,
+    locks: RefCell<HashMap<String, LockedFile>>,
+}
+
+impl YearlyStorage {
+    pub fn new<P: AsRef<Path>>(directory: P) -> Self {
+        YearlyStorage {
+            directory: directory.as_ref().to_owned(),
+            loaded_years: 1,
+            locks: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Set how many of the most recent years are loaded, the rest are read
+    /// only when needed. By default only the last one is loaded.
+    pub fn with_loaded_years(mut self, loaded_years: usize) -> Self {
+        self.loaded_years = loaded_years.max(1);
+        self
+    }
+
+    pub fn get_path(&self, name: &str) -> PathBuf {
+        self.directory.join(name)
+    }
+
+    /// Lock the database while the storage is in use, like
+    /// [`JsonStorage`](crate::data::storage::JsonStorage) does
+    fn lock(&self, name: &str) -> Result<(), FileError> {
+        let mut locks = self.locks.borrow_mut();
+        if !locks.contains_key(name) {
+            locks.insert(name.to_owned(), LockedFile::lock(self.get_path(name).join(ACCOUNTS_FILE))?);
+        }
+        Ok(())
+    }
+
+    fn read_balances(directory: &Path) -> Result<OpeningBalances, FileError> {
+        let path = directory.join(BALANCES_FILE);
+        if !path.exists() {
+            return Ok(OpeningBalances::new())
+        }
+        let text = std::fs::read_to_string(&path).map_err(|e| FileError::io(&path, e))?;
+        serde_json::from_str(&text).map_err(|e| FileError::format(&path, e))
+    }
+}
+
+impl Storage for YearlyStorage {
+    fn list(&self) -> Result<Vec<String>, FileError> {
+        let entries = std::fs::read_dir(&self.directory).map_err(|e| FileError::io(&self.directory, e))?;
+        let mut names = entries
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if !path.join(ACCOUNTS_FILE).is_file() {
+                    return None
+                }
+                Some(path.file_name()?.to_str()?.to_owned())
+            })
+            .collect::<Vec<_>>();
+        names.sort();
+        Ok(names)
+    }
+
+    fn load(&self, name: &str) -> Result<Database, FileError> {
+        let directory = self.get_path(name);
+        self.lock(name)?;
+
+        let accounts_path = directory.join(ACCOUNTS_FILE);
+        let accounts = Database::parse_file(&accounts_path)?;
+        let mut database = Database {
+            accounts: accounts.accounts,
+            rules: accounts.rules,
+            ..Default::default()
+        };
+
+        let years = find_years(&directory)?;
+        let mut balances = Self::read_balances(&directory)?;
+        let first_loaded = years.len().checked_sub(self.loaded_years)
+            .and_then(|index| years.get(index).copied())
+            // Without cached balances every year is needed
+            .filter(|year| balances.contains_key(year))
+            .unwrap_or_else(|| years.first().copied().unwrap_or_default());
+
+        for year in years.iter().filter(|year| **year >= first_loaded) {
+            database.transactions.extend(Database::parse_file(&year_path(&directory, *year))?.transactions);
+        }
+        let archived = years.iter()
+            .filter(|year| **year < first_loaded)
+            .map(|year| (*year, OnceCell::new()))
+            .collect::<BTreeMap<_, _>>();
+        if !archived.is_empty() {
+            database.archive = Some(Archive {
+                directory: directory.clone(),
+                first_loaded,
+                years: archived,
+                opening: balances.remove(&first_loaded).unwrap_or_default(),
+            });
+        }
+
+        database
+            .build_account_transaction_map()
+            .map_err(|source| FileError::Database { path: directory, source })?;
+        Ok(database)
+    }
+
+    fn save(&self, name: &str, database: &Database) -> Result<(), FileError> {
+        let directory = self.get_path(name);
+        std::fs::create_dir_all(&directory).map_err(|e| FileError::io(&directory, e))?;
+        self.lock(name)?;
+        let database_error = |source| FileError::Database { path: directory.clone(), source };
+
+        // The archive can stay as it is if it belongs to this directory and
+        // no transaction was added to its years
+        let mut archive = database.archive.as_ref()
+            .filter(|archive| archive.directory == directory);
+        if let Some(kept) = archive {
+            let cutoff = kept.cutoff();
+            if database.transactions.values().any(|trns| trns.get_datetime() < &cutoff) {
+                archive = None;
+            }
+        }
+
+        let mut by_year: BTreeMap<i32, HashMap<TransactionId, Transaction>> = BTreeMap::new();
+        let mut add = |id: &TransactionId, transaction: &Transaction| {
+            by_year
+                .entry(transaction.get_datetime().get_date().year())
+                .or_default()
+                .insert(*id, transaction.clone());
+        };
+        for (id, transaction) in database.transactions.iter() {
+            add(id, transaction);
+        }
+        if archive.is_none() {
+            if let Some(other) = database.archive.as_ref() {
+                for transactions in other.all_years() {
+                    transactions.map_err(database_error)?.iter().for_each(|(id, trns)| add(id, trns));
+                }
+            }
+        }
+
+        let accounts = Database {
+            accounts: database.accounts.clone(),
+            rules: database.rules.clone(),
+            ..Default::default()
+        };
+        let text = serde_json::to_string_pretty(&accounts).map_err(|e| FileError::format(&directory, e))?;
+        write_atomically(&directory.join(ACCOUNTS_FILE), text.as_bytes(), 0)?;
+
+        // Years that are not archived anymore and have no transactions left
+        // are emptied
+        let first_written = archive.map(|archive| archive.first_loaded).unwrap_or(i32::MIN);
+        for year in find_years(&directory)? {
+            if year >= first_written && !by_year.contains_key(&year) {
+                by_year.insert(year, HashMap::new());
+            }
+        }
+
+        let mut balances = match archive {
+            Some(archive) => {
+                let mut balances = Self::read_balances(&directory)?;
+                balances.retain(|year, _| *year < archive.first_loaded);
+                balances
+            },
+            None => OpeningBalances::new(),
+        };
+        let mut running = archive.map(|archive| archive.opening.clone()).unwrap_or_default();
+        for (year, transactions) in by_year {
+            balances.insert(year, running.clone());
+            for transaction in transactions.values() {
+                for (account_name, amount) in transaction.get_amounts() {
+                    let balance = running.remove(account_name).unwrap_or_default();
+                    running.insert(account_name.to_owned(), balance + amount);
+                }
+            }
+
+            let year_database = Database { transactions, ..Default::default() };
+            let text = serde_json::to_string_pretty(&year_database).map_err(|e| FileError::format(&directory, e))?;
+            write_atomically(&year_path(&directory, year), text.as_bytes(), 0)?;
+        }
+
+        let text = serde_json::to_string_pretty(&balances).map_err(|e| FileError::format(&directory, e))?;
+        write_atomically(&directory.join(BALANCES_FILE), text.as_bytes(), 0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data::{account::{Account, AccountType}, query::Query};
+    use std::str::FromStr;
+
+    mod storage {
+        use super::*;
+
+        fn lunch(day: &str) -> Transaction {
+            Transaction::example_transaction(
+                "Lunch",
+                "",
+                DateTime::from_str(day).unwrap(),
+                &[("bank/ICA_Bank", "-100 SEK"), ("food/lunch", "-100 SEK")]
+            )
+        }
+
+        fn balance(database: &Database, start: Option<&str>, end: Option<&str>) -> Amount {
+            database.get_account_balance(
+                &AccountName::new("bank/ICA_Bank"),
+                start.map(|day| DateTime::from_str(day).unwrap()),
+                end.map(|day| DateTime::from_str(day).unwrap())
+            ).unwrap()
+        }
+
+        #[test]
+        fn lazy_archive() {
+            let directory = "test_files/yearly";
+            let _ = std::fs::remove_dir_all(directory);
+            std::fs::create_dir_all(directory).unwrap();
+
+            let mut database = Database::default();
+            database.add_account(Account::new("bank/ICA_Bank", AccountType::Asset)).unwrap();
+            database.add_account(Account::new("food/lunch", AccountType::Flow)).unwrap();
+            for day in ["2021-03-01", "2022-03-01", "2022-07-01", "2023-03-01"] {
+                database.add_transaction(lunch(day)).unwrap();
+            }
+            YearlyStorage::new(directory).save("home", &database).unwrap();
+            assert!(Path::new("test_files/yearly/home/2022.json").is_file());
+
+            let storage = YearlyStorage::new(directory);
+            assert_eq!(vec!["home"], storage.list().unwrap());
+            let mut loaded = storage.load("home").unwrap();
+            assert_eq!(1, loaded.get_transaction_ids().count());
+            let archive = loaded.archive.as_ref().unwrap();
+            assert!(archive.years.values().all(|year| year.get().is_none()));
+
+            // Whole history balances come from the cached opening balances
+            assert_eq!(Amount::from_str("-400 SEK").unwrap(), balance(&loaded, None, None));
+            assert!(loaded.archive.as_ref().unwrap().years.values().all(|year| year.get().is_none()));
+
+            // Older ranges read only the years they need
+            assert_eq!(Amount::from_str("-200 SEK").unwrap(), balance(&loaded, Some("2022-01-01"), Some("2022-12-31")));
+            let archive = loaded.archive.as_ref().unwrap();
+            assert!(archive.years[&2021].get().is_none());
+            assert!(archive.years[&2022].get().is_some());
+            assert!(loaded.get_transaction(&lunch("2021-03-01").generate_id()).is_none());
+            assert!(loaded.find_transaction(&lunch("2021-03-01").generate_id()).unwrap().is_some());
+
+            // Queries read the archived years in their dates
+            let query = Query {
+                start: Some(DateTime::from_str("2022-01-01").unwrap()),
+                end: Some(DateTime::from_str("2022-12-31").unwrap()),
+                ..Default::default()
+            };
+            assert_eq!(2, loaded.query(&query).unwrap().len());
+            let everything = loaded.query(&Query::default()).unwrap();
+            assert_eq!(4, everything.len());
+            assert_eq!(&DateTime::from_str("2021-03-01").unwrap(), everything[0].get_datetime());
+
+            // Archived accounts and transactions are protected
+            assert!(loaded.add_transaction(lunch("2022-03-01")).is_err());
+            assert!(loaded.remove_account(AccountName::new("food/lunch")).is_err());
+
+            // Adding to the current year keeps the archive untouched
+            loaded.add_transaction(lunch("2023-05-01")).unwrap();
+            storage.save("home", &loaded).unwrap();
+            let reloaded = storage.load("home").unwrap();
+            assert_eq!(Amount::from_str("-500 SEK").unwrap(), balance(&reloaded, None, None));
+
+            // Adding to an archived year rewrites it
+            loaded.add_transaction(lunch("2021-05-01")).unwrap();
+            storage.save("home", &loaded).unwrap();
+            let reloaded = storage.load("home").unwrap();
+            assert_eq!(Amount::from_str("-600 SEK").unwrap(), balance(&reloaded, None, None));
+            assert_eq!(Amount::from_str("-200 SEK").unwrap(), balance(&reloaded, None, Some("2021-12-31")));
+
+            // Removing an archived transaction loads its year and the
+            // following ones
+            let mut loaded = storage.load("home").unwrap();
+            loaded.remove_transaction(lunch("2022-07-01").generate_id()).unwrap();
+            assert_eq!(3, loaded.get_transaction_ids().count());
+            assert_eq!(Amount::from_str("-500 SEK").unwrap(), balance(&loaded, None, None));
+            storage.save("home", &loaded).unwrap();
+            let reloaded = storage.load("home").unwrap();
+            assert_eq!(Amount::from_str("-500 SEK").unwrap(), balance(&reloaded, None, None));
+            assert_eq!(Amount::from_str("-200 SEK").unwrap(), balance(&reloaded, None, Some("2021-12-31")));
+            assert_eq!(Amount::from_str("-100 SEK").unwrap(), balance(&reloaded, Some("2022-01-01"), Some("2022-12-31")));
+
+            // Archived years that cannot be read are reported
+            std::fs::write("test_files/yearly/home/2021.json", "{").unwrap();
+            let mut reloaded = storage.load("home").unwrap();
+            assert!(matches!(reloaded.find_transaction(&lunch("2020-01-01").generate_id()), Err(Error::Storage(_))));
+
+            // Even when a failed group cannot be reverted because of them
+            let result = reloaded.grouped(|database| {
+                database.add_account(Account::new("food/fika", AccountType::Flow))?;
+                Err::<(), _>(Error::UnbalancedTransaction)
+            });
+            assert_eq!(Err(Error::UnbalancedTransaction), result);
+        }
+    }
+}
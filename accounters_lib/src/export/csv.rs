@@ -0,0 +1,374 @@
+//! Export to CSV files, as described in RFC 4180
+//!
+//! Transactions are written with one row per posting, that is, per account
+//! and currency in the transaction. Amounts keep the sign they have in the
+//! database. Fields are quoted only when they need it and rows end with
+//! CRLF.
+
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::data::{
+    account::AccountType,
+    datetime::DateTime,
+    money::{Amount, Currency},
+    query::Query,
+    transaction::Transaction,
+    Database,
+};
+use crate::export::ExportError;
+
+/// A field of the rows of transactions
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Column {
+    Date,
+    Time,
+    Name,
+    Notes,
+    Account,
+    Currency,
+    Amount,
+    Tags,
+    Id,
+}
+
+impl Column {
+    fn header(&self) -> &'static str {
+        match self {
+            Column::Date => "date",
+            Column::Time => "time",
+            Column::Name => "name",
+            Column::Notes => "notes",
+            Column::Account => "account",
+            Column::Currency => "currency",
+            Column::Amount => "amount",
+            Column::Tags => "tags",
+            Column::Id => "id",
+        }
+    }
+}
+
+impl FromStr for Column {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let column = match s.trim().to_lowercase().as_str() {
+            "date" => Column::Date,
+            "time" => Column::Time,
+            "name" => Column::Name,
+            "notes" => Column::Notes,
+            "account" => Column::Account,
+            "currency" => Column::Currency,
+            "amount" => Column::Amount,
+            "tags" => Column::Tags,
+            "id" => Column::Id,
+            other => return Err(format!("unknown column: {}", other)),
+        };
+        Ok(column)
+    }
+}
+
+/// Which columns to write, and in which order
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub enum ColumnSet {
+    /// Date, name, notes, account, currency and amount
+    #[default]
+    Postings,
+    /// Every column
+    Full,
+    Custom(Vec<Column>),
+}
+
+impl ColumnSet {
+    pub fn columns(&self) -> &[Column] {
+        use Column::*;
+        match self {
+            ColumnSet::Postings => &[Date, Name, Notes, Account, Currency, Amount],
+            ColumnSet::Full => &[Id, Date, Time, Name, Notes, Tags, Account, Currency, Amount],
+            ColumnSet::Custom(columns) => columns,
+        }
+    }
+}
+
+/// Either `postings`, `full` or a list of columns separated by commas, like
+/// `date,account,amount`
+impl FromStr for ColumnSet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "postings" => Ok(ColumnSet::Postings),
+            "full" => Ok(ColumnSet::Full),
+            list => list.split(',').map(Column::from_str).collect::<Result<_, _>>().map(ColumnSet::Custom),
+        }
+    }
+}
+
+/// How numbers are written
+///
+/// The default writes them like `-1234.50`, which any program can read.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct NumberFormat {
+    pub decimal_separator: char,
+    pub thousands_separator: Option<char>,
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        NumberFormat { decimal_separator: '.', thousands_separator: None }
+    }
+}
+
+impl NumberFormat {
+    /// The usual format for a language or locale, like `sv`, `es_ES` or
+    /// `en-GB`, if it is known
+    pub fn for_locale(locale: &str) -> Option<Self> {
+        let language = locale.split(['_', '-', '.']).next()?.to_lowercase();
+        let (decimal_separator, thousands_separator) = match language.as_str() {
+            "c" | "posix" => ('.', None),
+            "en" | "ja" | "zh" | "ko" | "he" | "th" => ('.', Some(',')),
+            "de" | "es" | "it" | "nl" | "pt" | "da" | "id" | "tr" | "el" => (',', Some('.')),
+            "sv" | "fr" | "fi" | "nb" | "nn" | "no" | "pl" | "cs" | "sk" | "ru" | "uk" => (',', Some(' ')),
+            _ => return None,
+        };
+        Some(NumberFormat { decimal_separator, thousands_separator })
+    }
+
+    /// Write a number given in the plain format of
+    /// [`Number::to_plain_string`](crate::data::money::Number::to_plain_string)
+    fn format(&self, plain: &str) -> String {
+        let (sign, digits) = match plain.strip_prefix('-') {
+            Some(digits) => ("-", digits),
+            None => ("", plain),
+        };
+        let (units, decimals) = digits.split_once('.').unwrap_or((digits, ""));
+
+        let mut output = String::from(sign);
+        for (i, c) in units.chars().enumerate() {
+            if let Some(separator) = self.thousands_separator {
+                if i != 0 && (units.len() - i) % 3 == 0 {
+                    output.push(separator);
+                }
+            }
+            output.push(c);
+        }
+        if !decimals.is_empty() {
+            output.push(self.decimal_separator);
+            output.push_str(decimals);
+        }
+        output
+    }
+}
+
+/// Options for both exports
+#[derive(Clone, Debug)]
+pub struct CsvOptions {
+    pub columns: ColumnSet,
+    pub number_format: NumberFormat,
+    pub delimiter: char,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions { columns: ColumnSet::default(), number_format: NumberFormat::default(), delimiter: ',' }
+    }
+}
+
+impl CsvOptions {
+    /// The delimiter that goes with a number format, since a comma cannot be
+    /// both
+    pub fn with_number_format(mut self, number_format: NumberFormat) -> Self {
+        if number_format.decimal_separator == ',' && self.delimiter == ',' {
+            self.delimiter = ';';
+        }
+        self.number_format = number_format;
+        self
+    }
+
+    fn write_row<W: Write>(&self, writer: &mut W, fields: &[String]) -> std::io::Result<()> {
+        let row = fields.iter().map(|field| self.quote(field)).collect::<Vec<_>>();
+        write!(writer, "{}\r\n", row.join(&self.delimiter.to_string()))
+    }
+
+    fn quote(&self, field: &str) -> String {
+        let needs_quotes = field.contains([self.delimiter, '"', '\r', '\n']);
+        if needs_quotes {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_owned()
+        }
+    }
+
+    fn format_number(&self, amount: &Amount, currency: &Currency) -> String {
+        self.number_format.format(&amount.in_currency(currency).to_plain_string())
+    }
+}
+
+/// Write the postings of the transactions selected by the query, sorted by
+/// date
+pub fn write_postings<W: Write>(
+    database: &Database,
+    query: &Query,
+    options: &CsvOptions,
+    writer: &mut W,
+) -> Result<(), ExportError> {
+    let columns = options.columns.columns();
+    let header = columns.iter().map(|column| column.header().to_owned()).collect::<Vec<_>>();
+    options.write_row(writer, &header)?;
+
+    for transaction in database.query(query)? {
+        for (account_name, amount) in transaction.get_amounts() {
+            if !query.matches_account(account_name) {
+                continue
+            }
+            for currency in amount.currencies() {
+                let row = columns
+                    .iter()
+                    .map(|column| match column {
+                        Column::Account => account_name.as_ref().to_owned(),
+                        Column::Currency => currency.0.clone(),
+                        Column::Amount => options.format_number(amount, &currency),
+                        column => transaction_field(transaction, column),
+                    })
+                    .collect::<Vec<_>>();
+                options.write_row(writer, &row)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn transaction_field(transaction: &Transaction, column: &Column) -> String {
+    let datetime = transaction.get_datetime();
+    match column {
+        Column::Date => datetime.get_date_string(),
+        Column::Time => match datetime.get_time() {
+            Some(_) => datetime.to_iso_string().split_once('T').map_or(String::new(), |(_, time)| time.to_owned()),
+            None => String::new(),
+        },
+        Column::Name => transaction.get_name().to_owned(),
+        Column::Notes => transaction.get_notes().to_owned(),
+        Column::Tags => transaction.get_tags().iter().map(|tag| tag.as_ref()).collect::<Vec<_>>().join(" "),
+        Column::Id => transaction.generate_id().0.to_string(),
+        Column::Account | Column::Currency | Column::Amount => String::new(),
+    }
+}
+
+/// Write the balance of every account selected by the query at the end of
+/// the given moment, or of everything if there is none, one row per
+/// currency
+///
+/// Only the account filter of the query is used. Accounts with no money
+/// get a single row with an empty currency.
+pub fn write_balances<W: Write>(
+    database: &Database,
+    query: &Query,
+    as_of: Option<DateTime>,
+    options: &CsvOptions,
+    writer: &mut W,
+) -> Result<(), ExportError> {
+    let header = ["account", "type", "currency", "balance"].map(String::from);
+    options.write_row(writer, &header)?;
+
+    let mut account_names = database.get_account_names().filter(|name| query.matches_account(name)).collect::<Vec<_>>();
+    account_names.sort();
+
+    for account_name in account_names {
+        let account_type = match database.get_account(account_name).map(|account| account.get_account_type()) {
+            Some(AccountType::Flow) => "flow",
+            _ => "asset",
+        };
+        let balance = database.get_account_balance(account_name, None, as_of)?;
+        let mut rows = balance
+            .currencies()
+            .into_iter()
+            .map(|currency| (currency.0.clone(), options.format_number(&balance, &currency)))
+            .collect::<Vec<_>>();
+        if rows.is_empty() {
+            rows.push((String::new(), options.number_format.format("0")));
+        }
+        for (currency, number) in rows {
+            options.write_row(writer, &[account_name.as_ref().to_owned(), account_type.to_owned(), currency, number])?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    mod writing {
+        use super::*;
+        use crate::data::account::Account;
+
+        fn database() -> Database {
+            let mut database = Database::default();
+            database.add_account(Account::new("asset/bank", AccountType::Asset)).unwrap();
+            database.add_account(Account::new("expense/food", AccountType::Flow)).unwrap();
+            database.add_account(Account::new("expense/rent", AccountType::Flow)).unwrap();
+            database.add_transaction(Transaction::example_transaction(
+                "Rent",
+                "",
+                DateTime::simple((2024, 2, 1), None),
+                &[("asset/bank", "-6500 SEK"), ("expense/rent", "-6500 SEK")],
+            )).unwrap();
+            database.add_transaction(Transaction::example_transaction(
+                "Lunch, \"the good one\"",
+                "with\nfriends",
+                DateTime::simple((2024, 1, 15), Some((12, 30))),
+                &[("asset/bank", "-1234.5 SEK"), ("expense/food", "-1234.5 SEK")],
+            )).unwrap();
+            database
+        }
+
+        #[test]
+        fn postings() {
+            let mut output = Vec::new();
+            write_postings(&database(), &Query::default(), &CsvOptions::default(), &mut output).unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                "date,name,notes,account,currency,amount\r\n\
+                2024-01-15,\"Lunch, \"\"the good one\"\"\",\"with\nfriends\",asset/bank,SEK,-1234.50\r\n\
+                2024-01-15,\"Lunch, \"\"the good one\"\"\",\"with\nfriends\",expense/food,SEK,-1234.50\r\n\
+                2024-02-01,Rent,,asset/bank,SEK,-6500.00\r\n\
+                2024-02-01,Rent,,expense/rent,SEK,-6500.00\r\n"
+            );
+        }
+
+        #[test]
+        fn filtered_with_locale() {
+            let query = Query { account: Some(String::from("expense/")), ..Default::default() };
+            let options = CsvOptions {
+                columns: "date,time,account,amount".parse().unwrap(),
+                ..Default::default()
+            }.with_number_format(NumberFormat::for_locale("sv_SE.UTF-8").unwrap());
+            let mut output = Vec::new();
+            write_postings(&database(), &query, &options, &mut output).unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                "date;time;account;amount\r\n\
+                2024-01-15;12:30:00;expense/food;-1 234,50\r\n\
+                2024-02-01;;expense/rent;-6 500,00\r\n"
+            );
+        }
+
+        #[test]
+        fn balances() {
+            let mut database = database();
+            database.add_account(Account::new("asset/cash", AccountType::Asset)).unwrap();
+            let mut output = Vec::new();
+            let options = CsvOptions::default().with_number_format(NumberFormat::for_locale("en").unwrap());
+            let as_of = DateTime::simple((2024, 1, 31), None);
+            write_balances(&database, &Query::default(), Some(as_of), &options, &mut output).unwrap();
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                "account,type,currency,balance\r\n\
+                asset/bank,asset,SEK,\"-1,234.50\"\r\n\
+                asset/cash,asset,,0\r\n\
+                expense/food,flow,SEK,\"-1,234.50\"\r\n\
+                expense/rent,flow,,0\r\n"
+            );
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Export to the plain text journal format of ledger and hledger
+//!
+//! Every account is declared first, and then every transaction is written
+//! with its postings. The conventions differ in a few places:
+//!
+//! - Account names use `:` instead of `/` to separate levels.
+//! - Ledger requires the postings of a transaction to add up to zero, so
+//!   the amounts of flow accounts are written with the opposite sign.
+//! - An amount with several currencies becomes one posting per currency.
+//! - The time of a transaction, which ledger does not support, its tags and
+//!   its metadata are written as tags in a comment. Notes become comments
+//!   too.
+
+use std::io::Write;
+
+use crate::data::{
+    account::{AccountName, AccountType},
+    money::Amount,
+    query::Query,
+    tags::Tag,
+    Database,
+    Error,
+};
+use crate::export::ExportError;
+
+/// Write the whole database as a journal
+///
+/// The archived years are read too. A database with only some of its
+/// transactions loaded cannot be written.
+pub fn write_journal<W: Write>(database: &Database, writer: &mut W) -> Result<(), ExportError> {
+    let transactions = database.query(&Query::default())?;
+
+    let mut account_names = database.get_account_names().collect::<Vec<_>>();
+    account_names.sort();
+
+    for account_name in account_names.iter() {
+        let Some(account) = database.get_account(account_name) else {
+            continue
+        };
+        let mut tags = account.get_tags().iter().map(tag).collect::<Vec<_>>();
+        if let AccountType::Asset = account.get_account_type() {
+            tags.insert(0, String::from("type: A"));
+        }
+        write!(writer, "account {}", ledger_account(account_name))?;
+        if !tags.is_empty() {
+            write!(writer, "  ; {}", tags.join(", "))?;
+        }
+        writeln!(writer)?;
+    }
+
+    for transaction in transactions {
+        writeln!(writer)?;
+        let datetime = transaction.get_datetime();
+        writeln!(writer, "{} {}", datetime.get_date_string(), single_line(transaction.get_name()))?;
+
+        let mut tags = transaction.get_tags().iter().map(tag).collect::<Vec<_>>();
+        if datetime.get_time().is_some() {
+            tags.insert(0, format!("time: {}", datetime.to_iso_string().split_once('T').unwrap().1));
+        }
+        for (key, value) in transaction.get_metadata() {
+            tags.push(format!("{}: {}", tag_name(key), single_line(value).replace(',', ";")));
+        }
+        if !tags.is_empty() {
+            writeln!(writer, "    ; {}", tags.join(", "))?;
+        }
+        for line in transaction.get_notes().lines().filter(|line| !line.trim().is_empty()) {
+            writeln!(writer, "    ; {}", line.trim_end())?;
+        }
+
+        let mut postings = Vec::new();
+        for (account_name, amount) in transaction.get_amounts() {
+            let amount = match database.get_account(account_name).map(|account| account.get_account_type()) {
+                Some(AccountType::Flow) => -amount,
+                _ => amount.clone(),
+            };
+            postings.extend(
+                split_currencies(&amount).into_iter().map(|amount| (ledger_account(account_name), amount))
+            );
+        }
+        let width = postings.iter().map(|(account, _)| account.chars().count()).max().unwrap_or(0);
+        for (account, amount) in postings {
+            writeln!(writer, "    {:<width$}  {}", account, amount)?;
+        }
+    }
+    Ok(())
+}
+
+/// The whole database as a journal
+pub fn to_journal(database: &Database) -> Result<String, Error> {
+    let mut output = Vec::new();
+    match write_journal(database, &mut output) {
+        Ok(()) => Ok(String::from_utf8(output).expect("The journal is built from strings")),
+        Err(ExportError::Database(error)) => Err(error),
+        Err(ExportError::Io(_)) => unreachable!("Writing to memory cannot fail"),
+    }
+}
+
+/// The name of the account in ledger
+pub fn ledger_account(account_name: &AccountName) -> String {
+    // Two spaces separate the account from the amount in a posting
+    account_name.as_ref().replace('/', ":").replace("  ", " ")
+}
+
+/// One amount per currency, as ledger writes them
+fn split_currencies(amount: &Amount) -> Vec<String> {
+    if amount.is_zero() {
+        return vec![String::from("0")]
+    }
+    amount.currencies()
+        .iter()
+        .map(|currency| format!("{} {}", amount.in_currency(currency).to_plain_string(), currency.0))
+        .collect()
+}
+
+/// A tag without a value, in the `name:` syntax of ledger
+fn tag(tag: &Tag) -> String {
+    format!("{}:", tag_name(tag.as_ref()))
+}
+
+fn tag_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_whitespace() || c == ':' || c == ',' { '_' } else { c })
+        .collect()
+}
+
+fn single_line(text: &str) -> String {
+    text.lines().map(str::trim).collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    mod journal {
+        use super::*;
+        use crate::data::{
+            account::Account,
+            datetime::DateTime,
+            storage::Storage,
+            transaction::Transaction,
+            yearly::YearlyStorage,
+        };
+        use std::str::FromStr;
+
+        #[test]
+        fn writing() {
+            let mut database = Database::default();
+            database.add_account(Account::new("bank/ICA_Bank", AccountType::Asset)).unwrap();
+            database.add_account(Account::new("entertainment/eat_out", AccountType::Flow)).unwrap();
+            database.add_transaction(Transaction::example_transaction(
+                "Comprar nabos",
+                "Na que comentar xd\nNada",
+                DateTime::from_str("2023-07-13 14:54").unwrap(),
+                &[("bank/ICA_Bank", "-132 SEK, -3.5 EUR"), ("entertainment/eat_out", "-132 SEK, -3.5 EUR")],
+            )).unwrap();
+            database.add_transaction(Transaction::example_transaction(
+                "Earlier",
+                "",
+                DateTime::from_str("2023-07-01").unwrap(),
+                &[("bank/ICA_Bank", "-1 SEK"), ("entertainment/eat_out", "-1 SEK")],
+            )).unwrap();
+
+            let expected = "\
+account bank:ICA_Bank  ; type: A
+account entertainment:eat_out
+
+2023-07-01 Earlier
+    bank:ICA_Bank          -1.00 SEK
+    entertainment:eat_out  1.00 SEK
+
+2023-07-13 Comprar nabos
+    ; time: 14:54:00
+    ; Na que comentar xd
+    ; Nada
+    bank:ICA_Bank          -3.50 EUR
+    bank:ICA_Bank          -132.00 SEK
+    entertainment:eat_out  3.50 EUR
+    entertainment:eat_out  132.00 SEK
+";
+            assert_eq!(expected, to_journal(&database).unwrap());
+        }
+
+        #[test]
+        fn archived_years() {
+            let directory = "test_files/ledger_yearly";
+            let _ = std::fs::remove_dir_all(directory);
+            std::fs::create_dir_all(directory).unwrap();
+
+            let mut database = Database::default();
+            database.add_account(Account::new("bank/ICA_Bank", AccountType::Asset)).unwrap();
+            database.add_account(Account::new("food/lunch", AccountType::Flow)).unwrap();
+            for day in ["2020-03-01", "2023-03-01"] {
+                database.add_transaction(Transaction::example_transaction(
+                    "Lunch",
+                    "",
+                    DateTime::from_str(day).unwrap(),
+                    &[("bank/ICA_Bank", "-100 SEK"), ("food/lunch", "-100 SEK")],
+                )).unwrap();
+            }
+            let storage = YearlyStorage::new(directory);
+            storage.save("home", &database).unwrap();
+            let loaded = storage.load("home").unwrap();
+            assert_eq!(1, loaded.get_transaction_ids().count());
+
+            let journal = to_journal(&loaded).unwrap();
+            assert!(journal.contains("2020-03-01 Lunch"));
+            assert!(journal.contains("2023-03-01 Lunch"));
+        }
+    }
+}
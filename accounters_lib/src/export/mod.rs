@@ -0,0 +1,36 @@
+//! Conversion of databases to the formats of other programs
+
+use crate::data::Error;
+
+pub mod csv;
+pub mod ledger;
+
+/// Something that stopped an export
+#[derive(Debug)]
+pub enum ExportError {
+    Io(std::io::Error),
+    Database(Error),
+}
+
+impl From<std::io::Error> for ExportError {
+    fn from(value: std::io::Error) -> Self {
+        ExportError::Io(value)
+    }
+}
+
+impl From<Error> for ExportError {
+    fn from(value: Error) -> Self {
+        ExportError::Database(value)
+    }
+}
+
+impl std::fmt::Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportError::Io(error) => write!(f, "the file could not be written: {}", error),
+            ExportError::Database(error) => write!(f, "the database could not be read: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
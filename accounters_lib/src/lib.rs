@@ -1,5 +1,5 @@
 #[allow(dead_code)]
-
 pub mod data;
+pub mod export;
 
 // From the new laptop!
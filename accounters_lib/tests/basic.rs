@@ -1,15 +1,27 @@
 use accounters_lib::data::{
     account::{Account, AccountType, AccountName},
-    datetime::DateTime,
+    datetime::{DateTime, Period},
+    history::Operation,
+    journal::{Entry, Journal},
     transaction::Transaction,
     money::Amount,
+    file::{LockedFile, SaveOptions},
+    rules::Rule,
+    storage::{JournalStorage, JsonStorage, Storage},
+    yearly::YearlyStorage,
     Database,
+    Error,
+    FileError,
 };
 
+use accounters_lib::export::ledger;
+
 use std::str::FromStr;
+use time::Month;
 
 #[test]
 fn read_and_write_file() {
+    std::fs::create_dir_all("test_files").unwrap();
     let mut database_1 = Database::default();
 
     database_1
@@ -31,7 +43,7 @@ fn read_and_write_file() {
         ))
         .unwrap();
 
-    database_1.save_to_file("test_files/file_1.txt");
+    database_1.save_to_file("test_files/file_1.txt").unwrap();
 
     let mut database_2 = Database::read_from_file("test_files/file_1.txt").unwrap();
 
@@ -58,7 +70,7 @@ fn read_and_write_file() {
             Err(accounters_lib::data::Error::AccountHasTransactions(_))
     ));
 
-    database_2.save_to_file("test_files/file_1.txt");
+    database_2.save_to_file("test_files/file_1.txt").unwrap();
 }
 
 #[test]
@@ -99,12 +111,443 @@ fn compute_balance() {
     )).unwrap();
 
     assert_eq!(
-        Amount::from_str("800 EUR, -1200 SEK"),
+        Ok(Amount::from_str("800 EUR, -1200 SEK").unwrap()),
         database.get_account_balance(&AccountName::new("balance/splitwise"), None, None)
     );
 
     assert_eq!(
-        Amount::from_str("-1200 SEK"),
+        Ok(Amount::from_str("-1200 SEK").unwrap()),
         database.get_account_balance(&AccountName::new("balance/splitwise"), None, Some(DateTime::from_str("2023-08-31").unwrap()))
     );
+
+    let (start, end) = Period::Month { year: 2023, month: Month::September }.bounds();
+    assert_eq!(
+        Ok(Amount::from_str("800 EUR").unwrap()),
+        database.get_account_balance(&AccountName::new("balance/splitwise"), start, end)
+    );
+}
+
+#[test]
+fn file_errors() {
+    std::fs::create_dir_all("test_files").unwrap();
+
+    let missing = Database::read_from_file("test_files/does_not_exist.json");
+    assert!(matches!(
+        missing,
+        Err(FileError::Io { path, .. }) if path.ends_with("does_not_exist.json")
+    ));
+
+    std::fs::write(
+        "test_files/broken.json",
+        "{\n  \"accounts\": [],\n  \"transactions\": [\n    {\"name\": 3}\n  ]\n}\n"
+    ).unwrap();
+    let broken = Database::read_from_file("test_files/broken.json");
+    let Err(error @ FileError::Format { line: 4, .. }) = broken else {
+        panic!("Expected a format error in line 4")
+    };
+    assert!(error.to_string().starts_with("test_files/broken.json:4:"));
+}
+
+#[test]
+fn dangling_references() {
+    std::fs::create_dir_all("test_files").unwrap();
+    std::fs::write(
+        "test_files/dangling.json",
+        r#"{
+            "accounts": [{"name": "bank/ICA_Bank", "account_type": "Asset", "tags": []}],
+            "transactions": [
+                {
+                    "name": "Lunch", "notes": "", "tags": [], "datetime": "2023-07-13",
+                    "amounts": {"bank/ICA_Bank": "-100 SEK", "food/lunch": "-100 SEK"}
+                },
+                {
+                    "name": "Gift", "notes": "", "tags": [], "datetime": "2023-07-14",
+                    "amounts": {"bank/ICA_Bank": "50 SEK", "income/gifts": "50 SEK"}
+                }
+            ]
+        }"#
+    ).unwrap();
+
+    let Err(FileError::Database { source: Error::MissingAccounts(references), .. })
+        = Database::read_from_file("test_files/dangling.json") else {
+        panic!("Expected the missing accounts to be reported")
+    };
+    let mut missing = references.iter().map(|(_, name)| name.as_ref()).collect::<Vec<_>>();
+    missing.sort();
+    assert_eq!(vec!["food/lunch", "income/gifts"], missing);
+}
+
+#[test]
+fn remove_transaction() {
+    let mut database = Database::default();
+    database.add_account(Account::new("bank/ICA_Bank", AccountType::Asset)).unwrap();
+    database.add_account(Account::new("food/lunch", AccountType::Flow)).unwrap();
+
+    let transaction = Transaction::example_transaction(
+        "Lunch",
+        "",
+        DateTime::from_str("2023-07-13").unwrap(),
+        &[("bank/ICA_Bank", "-100 SEK"), ("food/lunch", "-100 SEK")]
+    );
+    let id = transaction.generate_id();
+    database.add_transaction(transaction).unwrap();
+
+    assert!(database.get_transaction(&id).is_some());
+    database.remove_transaction(id).unwrap();
+    assert!(database.get_transaction(&id).is_none());
+    assert_eq!(Err(Error::UnknownTransaction(id)), database.remove_transaction(id));
+    assert!(database.get_account(&AccountName::new("food/missing")).is_none());
+    database.remove_account(AccountName::new("food/lunch")).unwrap();
+}
+
+#[test]
+fn undo_and_redo() {
+    let mut database = Database::default();
+    let bank = AccountName::new("bank/ICA_Bank");
+    let lunch = AccountName::new("food/lunch");
+    let lunch_transaction = |day: &str| Transaction::example_transaction(
+        "Lunch",
+        "",
+        DateTime::from_str(day).unwrap(),
+        &[("bank/ICA_Bank", "-100 SEK"), ("food/lunch", "-100 SEK")]
+    );
+
+    database.add_account(Account::new("bank/ICA_Bank", AccountType::Asset)).unwrap();
+    database.grouped(|db| {
+        db.add_account(Account::new("food/lunch", AccountType::Flow))?;
+        db.add_transaction(lunch_transaction("2023-07-13"))?;
+        db.add_transaction(lunch_transaction("2023-07-14"))
+    }).unwrap();
+    assert_eq!(Ok(Amount::from_str("-200 SEK").unwrap()), database.get_account_balance(&bank, None, None));
+
+    // The whole group goes away at once
+    assert_eq!(Ok(true), database.undo());
+    assert!(database.get_account(&lunch).is_none());
+    assert_eq!(Ok(Amount::default()), database.get_account_balance(&bank, None, None));
+
+    assert_eq!(Ok(true), database.redo());
+    assert_eq!(Ok(Amount::from_str("-200 SEK").unwrap()), database.get_account_balance(&bank, None, None));
+    assert_eq!(Ok(false), database.redo());
+
+    // Removals can be undone too, and a new change forgets what was undone
+    database.remove_transaction(lunch_transaction("2023-07-13").generate_id()).unwrap();
+    assert_eq!(Ok(true), database.undo());
+    assert_eq!(Ok(Amount::from_str("-200 SEK").unwrap()), database.get_account_balance(&bank, None, None));
+    database.add_transaction(lunch_transaction("2023-07-15")).unwrap();
+    assert!(!database.get_history().can_redo());
+
+    assert_eq!(Ok(true), database.undo());
+    assert_eq!(Ok(true), database.undo());
+    assert_eq!(Ok(true), database.undo());
+    assert_eq!(Ok(false), database.undo());
+    assert!(database.get_account(&bank).is_none());
+}
+
+#[test]
+fn failed_group_is_reverted() {
+    let mut database = Database::default();
+    database.add_account(Account::new("bank/ICA_Bank", AccountType::Asset)).unwrap();
+
+    let result = database.grouped(|db| {
+        db.add_account(Account::new("food/lunch", AccountType::Flow))?;
+        db.add_transaction(Transaction::example_transaction(
+            "Lunch",
+            "",
+            DateTime::from_str("2023-07-13").unwrap(),
+            &[("bank/ICA_Bank", "-100 SEK"), ("food/dinner", "-100 SEK")]
+        ))
+    });
+
+    assert_eq!(Err(Error::UnknownAccount(AccountName::new("food/dinner"))), result);
+    assert!(database.get_account(&AccountName::new("food/lunch")).is_none());
+    assert_eq!(Ok(true), database.undo());
+    assert!(database.get_account(&AccountName::new("bank/ICA_Bank")).is_none());
+    assert_eq!(Ok(false), database.undo());
+}
+
+#[test]
+fn journal_replay() {
+    std::fs::create_dir_all("test_files").unwrap();
+    let path = "test_files/journal_replay.journal";
+    let _ = std::fs::remove_file(path);
+    let bank = AccountName::new("bank/ICA_Bank");
+
+    let mut database = Database::default();
+    database.add_account(Account::new("bank/ICA_Bank", AccountType::Asset)).unwrap();
+    database.start_journal(path).unwrap();
+    database.add_account(Account::new("food/lunch", AccountType::Flow)).unwrap();
+    database.add_transaction(Transaction::example_transaction(
+        "Lunch",
+        "",
+        DateTime::from_str("2023-07-13").unwrap(),
+        &[("bank/ICA_Bank", "-100 SEK"), ("food/lunch", "-100 SEK")]
+    )).unwrap();
+    database.undo().unwrap();
+    database.append_snapshot().unwrap();
+    database.add_transaction(Transaction::example_transaction(
+        "Dinner",
+        "",
+        DateTime::from_str("2023-07-13").unwrap(),
+        &[("bank/ICA_Bank", "-300 SEK"), ("food/lunch", "-300 SEK")]
+    )).unwrap();
+
+    // Undoing is just another change in the journal
+    let entries = Journal::read_entries(path).unwrap();
+    assert_eq!(6, entries.len());
+    assert!(matches!(entries[3], Entry::Change { operation: Operation::RemoveTransaction(_), .. }));
+
+    // The journal is locked while the database writes to it
+    assert!(matches!(Database::open_journal(path), Err(FileError::Locked { .. })));
+    let balance = database.get_account_balance(&bank, None, None);
+    drop(database);
+
+    let reopened = Database::open_journal(path).unwrap();
+    assert_eq!(Ok(Amount::from_str("-300 SEK").unwrap()), reopened.get_account_balance(&bank, None, None));
+    assert_eq!(balance, reopened.get_account_balance(&bank, None, None));
+}
+
+#[test]
+fn journal_at_moment() {
+    std::fs::create_dir_all("test_files").unwrap();
+    let path = "test_files/journal_at_moment.journal";
+    let bank = AccountName::new("bank/ICA_Bank");
+    let mut initial = Database::default();
+    initial.add_account(Account::new("bank/ICA_Bank", AccountType::Asset)).unwrap();
+    initial.add_account(Account::new("food/lunch", AccountType::Flow)).unwrap();
+    let lunch = |day: &str| Transaction::example_transaction(
+        "Lunch",
+        "",
+        DateTime::from_str(day).unwrap(),
+        &[("bank/ICA_Bank", "-100 SEK"), ("food/lunch", "-100 SEK")]
+    );
+
+    let entries = [
+        Entry::Snapshot { timestamp: DateTime::from_str("2023-07-01 10:00Z").unwrap(), database: initial },
+        Entry::Change {
+            timestamp: DateTime::from_str("2023-07-13 12:00Z").unwrap(),
+            operation: Operation::AddTransaction(lunch("2023-07-13"))
+        },
+        Entry::Change {
+            timestamp: DateTime::from_str("2023-07-14 12:00Z").unwrap(),
+            operation: Operation::AddTransaction(lunch("2023-07-14"))
+        },
+    ];
+    let text = entries.iter().map(|entry| serde_json::to_string(entry).unwrap() + "\n").collect::<String>();
+    std::fs::write(path, text).unwrap();
+
+    let balance_at = |moment: &str| Database::read_journal_at(path, &DateTime::from_str(moment).unwrap())
+        .map(|database| database.get_account_balance(&bank, None, None).unwrap());
+    assert_eq!(Amount::from_str("-100 SEK").unwrap(), balance_at("2023-07-13 14:00+02:00").unwrap());
+    assert_eq!(Amount::from_str("-200 SEK").unwrap(), balance_at("2023-07-14 12:00Z").unwrap());
+    assert!(matches!(balance_at("2023-06-30"), Err(FileError::Format { .. })));
+}
+
+#[test]
+fn backups_and_locking() {
+    std::fs::create_dir_all("test_files").unwrap();
+    let path = "test_files/backups.json";
+    for suffix in ["", ".1", ".2", ".3"] {
+        let _ = std::fs::remove_file(format!("{path}{suffix}"));
+    }
+
+    let mut database = Database::default();
+    let file = LockedFile::lock(path).unwrap().with_options(SaveOptions { backups: 2 });
+    for name in ["bank/first", "bank/second", "bank/third", "bank/fourth"] {
+        database.add_account(Account::new(name, AccountType::Asset)).unwrap();
+        file.save(&database).unwrap();
+    }
+
+    // Only the last two previous versions are kept
+    let accounts_in = |path: &str| Database::read_from_file(path).unwrap().get_account_names().count();
+    assert_eq!(4, accounts_in(path));
+    assert_eq!(3, accounts_in("test_files/backups.json.1"));
+    assert_eq!(2, accounts_in("test_files/backups.json.2"));
+    assert!(!std::path::Path::new("test_files/backups.json.3").exists());
+    assert!(!std::path::Path::new("test_files/backups.json.tmp").exists());
+
+    assert!(matches!(database.save_to_file(path), Err(FileError::Locked { .. })));
+    drop(file);
+    database.save_to_file(path).unwrap();
+}
+
+#[test]
+fn migrate_old_file() {
+    std::fs::create_dir_all("test_files").unwrap();
+    let path = "test_files/unversioned.json";
+    let old_text = r#"{
+  "accounts": [
+    {"name": "bank/ICA_Bank", "account_type": "Asset", "tags": []},
+    {"name": "food/lunch", "account_type": "Flow", "tags": []}
+  ],
+  "transactions": [{
+    "name": "Lunch",
+    "notes": "",
+    "tags": [],
+    "datetime": {"date": [2023, 194], "time": null},
+    "amounts": {
+      "bank/ICA_Bank": {"amounts": {"SEK": {"value": -100, "n_decimals": 0}}},
+      "food/lunch": {"amounts": {"SEK": {"value": -100, "n_decimals": 0}}}
+    }
+  }]
+}"#;
+    std::fs::write(path, old_text).unwrap();
+
+    // Loading upgrades in memory only
+    let database = Database::read_from_file(path).unwrap();
+    assert_eq!(
+        Ok(Amount::from_str("-100 SEK").unwrap()),
+        database.get_account_balance(&AccountName::new("bank/ICA_Bank"), None, None)
+    );
+    assert_eq!(old_text, std::fs::read_to_string(path).unwrap());
+
+    let report = Database::migrate_file(path, true).unwrap();
+    assert_eq!((0, 1), (report.from, report.to));
+    assert_eq!(old_text, std::fs::read_to_string(path).unwrap());
+
+    Database::migrate_file(path, false).unwrap();
+    assert!(Database::migrate_file(path, true).unwrap().is_up_to_date());
+    assert_eq!(old_text, std::fs::read_to_string("test_files/unversioned.json.1").unwrap());
+}
+
+#[test]
+fn storages() {
+    let directory = "test_files/storages";
+    let _ = std::fs::remove_dir_all(directory);
+    std::fs::create_dir_all(directory).unwrap();
+
+    let mut database = Database::default();
+    database.add_account(Account::new("bank/ICA_Bank", AccountType::Asset)).unwrap();
+
+    let storages: Vec<Box<dyn Storage>> = vec![
+        Box::new(JsonStorage::new(directory)),
+        Box::new(JournalStorage::new(directory)),
+    ];
+    for storage in storages.iter() {
+        storage.save("home", &database).unwrap();
+        storage.save("work", &Database::default()).unwrap();
+        assert_eq!(vec!["home", "work"], storage.list().unwrap());
+        let loaded = storage.load("home").unwrap();
+        assert!(loaded.get_account(&AccountName::new("bank/ICA_Bank")).is_some());
+    }
+
+    // The loaded file stays locked for other programs
+    assert!(matches!(JsonStorage::new(directory).load("home"), Err(FileError::Locked { .. })));
+    storages[0].save("home", &database).unwrap();
+}
+
+#[test]
+fn stable_output() {
+    std::fs::create_dir_all("test_files").unwrap();
+    let mut database = Database::default();
+    for name in ["bank/ICA_Bank", "food/lunch", "bank/Abanca", "food/dinner", "balance/splitwise"] {
+        let account_type = if name.starts_with("food") { AccountType::Flow } else { AccountType::Asset };
+        database.add_account(Account::new(name, account_type)).unwrap();
+    }
+    for (day, name) in [("2023-07-14", "b"), ("2023-07-13", "c"), ("2023-07-14", "a"), ("2023-07-12 10:00", "d")] {
+        database.add_transaction(Transaction::example_transaction(
+            name,
+            "",
+            DateTime::from_str(day).unwrap(),
+            &[("bank/ICA_Bank", "-100 SEK, 3 EUR"), ("food/lunch", "-100 SEK, 1 EUR"), ("food/dinner", "2 EUR")]
+        )).unwrap();
+    }
+
+    database.save_to_file("test_files/stable_1.json").unwrap();
+    let reloaded = Database::read_from_file("test_files/stable_1.json").unwrap();
+    reloaded.save_to_file("test_files/stable_2.json").unwrap();
+    let first = std::fs::read_to_string("test_files/stable_1.json").unwrap();
+    assert_eq!(first, std::fs::read_to_string("test_files/stable_2.json").unwrap());
+
+    // Accounts by name, transactions by date
+    let position = |text: &str| first.find(text).unwrap();
+    assert!(position("balance/splitwise") < position("bank/Abanca"));
+    assert!(position("bank/Abanca") < position("bank/ICA_Bank"));
+    assert!(position("\"name\": \"d\"") < position("\"name\": \"c\""));
+    assert!(position("\"name\": \"c\"") < position("\"name\": \"b\""));
+}
+
+/// Compare the balances computed by hledger from the exported journal with
+/// ours. Run it with `cargo test -- --ignored` where hledger is installed.
+#[test]
+#[ignore = "needs hledger installed"]
+fn ledger_round_trip() {
+    let version = std::process::Command::new("hledger")
+        .arg("--version")
+        .output()
+        .expect("hledger must be installed to run the round trip");
+    assert!(version.status.success());
+
+    std::fs::create_dir_all("test_files").unwrap();
+    let mut database = Database::default();
+    database.add_account(Account::new("bank/ICA_Bank", AccountType::Asset)).unwrap();
+    database.add_account(Account::new("bank/Abanca", AccountType::Asset)).unwrap();
+    database.add_account(Account::new("food/lunch", AccountType::Flow)).unwrap();
+    database.add_account(Account::new("salary/job", AccountType::Flow)).unwrap();
+    for (day, accounts) in [
+        ("2023-07-01", [("bank/ICA_Bank", "30000 SEK"), ("salary/job", "30000 SEK")]),
+        ("2023-07-13 14:54", [("bank/ICA_Bank", "-132.5 SEK"), ("food/lunch", "-132.5 SEK")]),
+        ("2023-07-14", [("bank/Abanca", "-12.3 EUR"), ("food/lunch", "-12.3 EUR")]),
+    ] {
+        database.add_transaction(Transaction::example_transaction("Something", "Notes: here", DateTime::from_str(day).unwrap(), &accounts)).unwrap();
+    }
+    std::fs::write("test_files/round_trip.journal", ledger::to_journal(&database).unwrap()).unwrap();
+
+    let output = std::process::Command::new("hledger")
+        .args(["-f", "test_files/round_trip.journal", "balance", "--flat", "--no-total", "--layout=bare", "-O", "csv"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    // Rows are "account","commodity","balance"
+    let mut balances = std::collections::HashMap::<String, Amount>::new();
+    for line in String::from_utf8(output.stdout).unwrap().lines().skip(1) {
+        let fields = line.split(',').map(|field| field.trim_matches('"')).collect::<Vec<_>>();
+        let number = fields[2].split_whitespace().find(|part| part.parse::<f64>().is_ok()).unwrap();
+        let amount = Amount::from_str(&format!("{} {}", number, fields[1])).unwrap();
+        let total = balances.remove(fields[0]).unwrap_or_default();
+        balances.insert(fields[0].to_owned(), total + &amount);
+    }
+
+    for name in database.get_account_names() {
+        let ours = database.get_account_balance(name, None, None).unwrap();
+        let ours = match database.get_account(name).unwrap().get_account_type() {
+            AccountType::Asset => ours,
+            AccountType::Flow => -&ours,
+        };
+        let theirs = balances.remove(&ledger::ledger_account(name)).unwrap_or_default();
+        assert_eq!(ours, theirs, "balance of {}", name.as_ref());
+    }
+}
+
+#[test]
+fn rules_in_storages() {
+    let directory = "test_files/rules";
+    let _ = std::fs::remove_dir_all(directory);
+    std::fs::create_dir_all(directory).unwrap();
+    let rule = |name: &str| -> Rule {
+        serde_json::from_str(&format!(r#"{{"name": "{name}", "conditions": {{"name": "{name}"}}}}"#)).unwrap()
+    };
+
+    let mut database = Database::default();
+    database.add_rule(rule("ICA")).unwrap();
+    database.add_rule(rule("Coop")).unwrap();
+
+    let storages: Vec<Box<dyn Storage>> = vec![
+        Box::new(JsonStorage::new(directory)),
+        Box::new(JournalStorage::new(directory)),
+        Box::new(YearlyStorage::new(directory)),
+    ];
+    for storage in storages.iter() {
+        storage.save("home", &database).unwrap();
+        let loaded = storage.load("home").unwrap();
+        assert_eq!(database.get_rules(), loaded.get_rules());
+    }
+
+    // Removing a rule and undoing it puts it back in its place
+    let mut journaled = storages[1].load("home").unwrap();
+    let written = || Database::read_journal_at(format!("{directory}/home.journal"), &DateTime::now()).unwrap();
+    journaled.remove_rule("ICA").unwrap();
+    assert_eq!(&[rule("Coop")], written().get_rules());
+    journaled.undo().unwrap();
+    assert_eq!(database.get_rules(), written().get_rules());
 }